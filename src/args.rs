@@ -1,15 +1,141 @@
-use clap::Parser;
+use clap::{Parser, Subcommand};
+use serde_yaml::{Mapping, Value};
+
+use std::net::Ipv4Addr;
 
 #[derive(Debug, Parser)]
 #[clap(version, about)]
 pub struct Args {
-    /// 配置文件路径
-    #[arg(short, long, default_value = "/etc/rdhcpd.yml")]
-    pub cfg: String,
+    /// 配置文件路径(默认 /etc/rdhcpd.yml), 可多次指定, 每个配置作为独立实例运行在各自的接口上
+    #[arg(short, long)]
+    pub cfg: Vec<String>,
+
+    /// 服务的网卡; 未指定配置文件时, 仅凭命令行参数和该网卡地址运行
+    #[arg(long)]
+    pub interface: Option<String>,
+
+    /// 地址池, 如 10.0.0.50-10.0.0.99
+    #[arg(long, value_parser = parse_range)]
+    pub range: Option<(Ipv4Addr, Ipv4Addr)>,
+
+    /// 网关地址
+    #[arg(long)]
+    pub gateway: Option<Ipv4Addr>,
+
+    /// DNS服务器, 逗号分隔
+    #[arg(long, value_delimiter = ',')]
+    pub dns: Vec<Ipv4Addr>,
+
+    /// 租期, 如 12h
+    #[arg(long)]
+    pub lease_time: Option<String>,
+
+    #[command(subcommand)]
+    pub command: Option<Command>,
+}
+
+#[derive(Debug, Subcommand)]
+pub enum Command {
+    /// 交互式生成配置文件
+    Init {
+        /// 写入的配置文件路径
+        #[arg(short, long, default_value = "/etc/rdhcpd.yml")]
+        output: String,
+    },
+    /// 静态地址分配管理
+    Reservations {
+        #[command(subcommand)]
+        action: ReservationsAction,
+    },
+}
+
+#[derive(Debug, Subcommand)]
+pub enum ReservationsAction {
+    /// 从CSV文件(mac,ip)校验并导入静态地址分配
+    Import {
+        /// CSV文件路径
+        file: String,
+        /// 只校验, 不写入
+        #[arg(long)]
+        dry_run: bool,
+    },
 }
 
 impl Args {
     pub fn parse_args() -> Self {
         Self::parse()
     }
+
+    /// The configuration keys set on the command line.
+    pub fn overrides(&self) -> Mapping {
+        let mut m = Mapping::new();
+        let ip = |a: &Ipv4Addr| Value::from(a.to_string());
+        if let Some(intf) = &self.interface {
+            m.insert("intf".into(), intf.as_str().into());
+        }
+        if let Some((start, end)) = &self.range {
+            m.insert("start".into(), ip(start));
+            m.insert("end".into(), ip(&end_after(*end)));
+        }
+        if let Some(gw) = &self.gateway {
+            m.insert("gateway".into(), ip(gw));
+        }
+        if !self.dns.is_empty() {
+            m.insert(
+                "dns_servers".into(),
+                Value::Sequence(self.dns.iter().map(ip).collect()),
+            );
+        }
+        if let Some(t) = &self.lease_time {
+            m.insert("lease_time".into(), t.as_str().into());
+        }
+        m
+    }
+}
+
+/// The `end` setting, which is not handed out, for a range whose last address is `last`;
+/// 255.255.255.255 is never handed out anyway.
+pub fn end_after(last: Ipv4Addr) -> Ipv4Addr {
+    u32::from(last).checked_add(1).map_or(last, Ipv4Addr::from)
+}
+
+/// Parses an address range written as `start-end`, both included.
+pub fn parse_range(s: &str) -> Result<(Ipv4Addr, Ipv4Addr), String> {
+    let (start, end) = s.split_once('-').ok_or("expected start-end")?;
+    let start: Ipv4Addr = start
+        .trim()
+        .parse()
+        .map_err(|e| format!("{}: {}", start, e))?;
+    let end: Ipv4Addr = end.trim().parse().map_err(|e| format!("{}: {}", end, e))?;
+    if u32::from(start) > u32::from(end) {
+        return Err("start is after end".to_string());
+    }
+    Ok((start, end))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn overrides(args: &[&str]) -> Mapping {
+        Args::parse_from([&["rdhcpd"], args].concat()).overrides()
+    }
+
+    #[test]
+    fn range_includes_its_end() {
+        let m = overrides(&["--range", "10.0.0.50-10.0.0.99"]);
+        assert_eq!(m["start"], Value::from("10.0.0.50"));
+        assert_eq!(m["end"], Value::from("10.0.0.100"));
+        let m = overrides(&["--range", "10.0.0.1-255.255.255.255"]);
+        assert_eq!(m["end"], Value::from("255.255.255.255"));
+    }
+
+    #[test]
+    fn parse_range_checks_order() {
+        let a = Ipv4Addr::new(10, 0, 0, 5);
+        assert_eq!(parse_range("10.0.0.5 - 10.0.0.5"), Ok((a, a)));
+        assert!(parse_range("10.0.0.6-10.0.0.5").is_err());
+        assert!(parse_range("10.0.0.5").is_err());
+        assert!(parse_range("10.0.0.5-x").is_err());
+    }
 }
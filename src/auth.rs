@@ -0,0 +1,362 @@
+//! DHCP authentication (RFC 3118) with delayed authentication: HMAC-MD5 over the message, keyed
+//! with a secret both sides were configured with.
+//!
+//! A client asks for authentication with an empty option 90 in its DHCPDISCOVER or DHCPINFORM;
+//! from then on its messages must carry a valid MAC and an increasing replay counter, and every
+//! reply to it is signed.
+
+use crate::config::AuthConf;
+use crate::hwaddr::HwAddr;
+use crate::options::{self, Authentication, DhcpOption, MessageType};
+use crate::packet::{self, Packet};
+
+use std::cell::RefCell;
+use std::collections::HashMap;
+
+const PROTOCOL_DELAYED: u8 = 1;
+const ALGORITHM_HMAC_MD5: u8 = 1;
+/// Replay detection by a monotonically increasing counter.
+const RDM_COUNTER: u8 = 0;
+/// Secret ID and HMAC-MD5.
+const INFO_LEN: usize = 4 + 16;
+/// Clients remembered at most; past this the least recently seen ones are forgotten, those that
+/// never sent a valid MAC first.
+const MAX_CLIENTS: usize = 4096;
+
+/// A client that asked for authentication.
+struct Client {
+    /// The key it authenticates with.
+    key: u32,
+    /// Its last replay counter.
+    replay: u64,
+    /// Whether it has sent a valid MAC yet.
+    verified: bool,
+    /// When it was last heard from (ms).
+    seen: u128,
+}
+
+struct State {
+    required: bool,
+    keys: Vec<(u32, Vec<u8>)>,
+    clients: HashMap<HwAddr, Client>,
+    /// The replay counter of the last signed reply.
+    replay: u64,
+}
+
+thread_local! {
+    /// The authentication of the instance served by this thread, see `instance.rs`.
+    static STATE: RefCell<Option<State>> = const { RefCell::new(None) };
+}
+
+/// Authenticates this thread's clients with `conf`; a no-op without it.
+pub fn enter(conf: Option<AuthConf>) {
+    let state = conf.map(|c| State {
+        required: c.required,
+        keys: c.keys.iter().map(|k| (k.id, k.secret().unwrap())).collect(),
+        clients: HashMap::new(),
+        replay: 0,
+    });
+    STATE.with(|s| *s.borrow_mut() = state);
+}
+
+/// Whether `p`, received as `data`, may be served.
+pub fn verify(data: &[u8], p: &Packet) -> bool {
+    STATE.with(|s| match &mut *s.borrow_mut() {
+        Some(state) => state.verify(data, p),
+        None => true,
+    })
+}
+
+/// Whether `mac` authenticates with one of the configured keys, which FORCERENEW requires.
+pub fn has_key(mac: &HwAddr) -> bool {
+    STATE.with(|s| {
+        s.borrow()
+            .as_ref()
+            .is_some_and(|state| state.clients.contains_key(mac))
+    })
+}
+
+/// Adds option 90 to a reply for a client that authenticates, with the MAC still zero, and
+/// returns the key for `seal`. It goes right after the message type, so it is placed before
+/// options that may not fit.
+pub fn attach(p: &mut Packet) -> Option<Vec<u8>> {
+    STATE.with(|s| {
+        let mut s = s.borrow_mut();
+        let state = s.as_mut()?;
+        let id = state.clients.get(&p.chaddr)?.key;
+        let secret = state.keys.iter().find(|(k, _)| *k == id)?.1.clone();
+        state.replay = (state.replay + 1).max(crate::utils::now_timestamp_ms() as u64);
+        let mut info = id.to_be_bytes().to_vec();
+        info.extend([0; 16]);
+        let at = p
+            .options
+            .iter()
+            .position(|o| matches!(o, DhcpOption::DhcpMessageType(_)))
+            .map_or(0, |i| i + 1);
+        p.options.insert(
+            at,
+            DhcpOption::Authentication(Authentication {
+                protocol: PROTOCOL_DELAYED,
+                algorithm: ALGORITHM_HMAC_MD5,
+                rdm: RDM_COUNTER,
+                replay: state.replay,
+                info,
+            }),
+        );
+        Some(secret)
+    })
+}
+
+/// Fills in the MAC `attach` left zero in the encoded reply; false if option 90 did not fit.
+pub fn seal(data: &mut [u8], secret: &[u8]) -> bool {
+    let Some(at) = mac_offset(data) else {
+        return false;
+    };
+    let mac = message_mac(data, at, secret);
+    data[at..at + 16].copy_from_slice(&mac);
+    true
+}
+
+impl State {
+    fn verify(&mut self, data: &[u8], p: &Packet) -> bool {
+        let t = p.message_type();
+        let name = t.as_ref().map_or("BOOTP", |t| crate::log::name(*t));
+        let mac = p.chaddr;
+        let a = match p.option(options::AUTHENTICATION) {
+            Some(DhcpOption::Authentication(a))
+                if a.protocol == PROTOCOL_DELAYED
+                    && a.algorithm == ALGORITHM_HMAC_MD5
+                    && a.rdm == RDM_COUNTER =>
+            {
+                a
+            }
+            _ => {
+                if self.required {
+                    println!("WARN: {} from {} not authenticated, dropped", name, mac);
+                    return false;
+                }
+                // served, but it cannot undo the binding of the client it claims to be
+                return true;
+            }
+        };
+        if a.info.is_empty() {
+            // RFC 3118 5.3: asking for authentication, answered with the server's key
+            if !matches!(t, Ok(MessageType::Discover | MessageType::Inform)) {
+                println!("WARN: {} from {} without a MAC, dropped", name, mac);
+                return false;
+            }
+            if !self.clients.contains_key(&p.chaddr) {
+                let key = self.keys[0].0;
+                self.remember(p.chaddr, key, 0, false);
+            }
+            return true;
+        }
+        if a.info.len() != INFO_LEN {
+            println!("WARN: {} from {}: malformed authentication", name, mac);
+            return false;
+        }
+        let id = u32::from_be_bytes(a.info[..4].try_into().unwrap());
+        let Some((_, secret)) = self.keys.iter().find(|(k, _)| *k == id) else {
+            println!("WARN: {} from {}: unknown key {}, dropped", name, mac, id);
+            return false;
+        };
+        let Some(at) = mac_offset(data) else {
+            return false;
+        };
+        if message_mac(data, at, secret) != a.info[4..] {
+            println!("WARN: {} from {}: wrong MAC, dropped", name, mac);
+            return false;
+        }
+        if let Some(c) = self.clients.get(&p.chaddr) {
+            if c.verified && a.replay <= c.replay {
+                println!("WARN: {} from {}: replayed, dropped", name, mac);
+                return false;
+            }
+        }
+        self.remember(p.chaddr, id, a.replay, true);
+        true
+    }
+
+    fn remember(&mut self, mac: HwAddr, key: u32, replay: u64, verified: bool) {
+        if self.clients.len() >= MAX_CLIENTS && !self.clients.contains_key(&mac) {
+            let oldest = self
+                .clients
+                .iter()
+                .min_by_key(|(_, c)| (c.verified, c.seen))
+                .map(|(m, _)| *m);
+            if let Some(m) = oldest {
+                self.clients.remove(&m);
+            }
+        }
+        let seen = crate::utils::now_timestamp_ms();
+        let client = Client {
+            key,
+            replay,
+            verified,
+            seen,
+        };
+        self.clients.insert(mac, client);
+    }
+}
+
+/// Where the HMAC of option 90 starts in `data`.
+fn mac_offset(data: &[u8]) -> Option<usize> {
+    let mut i = 240;
+    while i + 1 < data.len() {
+        match data[i] {
+            packet::END => return None,
+            packet::PAD => i += 1,
+            options::AUTHENTICATION => {
+                let len = data[i + 1] as usize;
+                let at = i + 2 + 3 + 8 + 4;
+                return (len == 3 + 8 + INFO_LEN && i + 2 + len <= data.len()).then_some(at);
+            }
+            _ => i += 2 + data[i + 1] as usize,
+        }
+    }
+    None
+}
+
+/// The HMAC-MD5 of the message as RFC 3118 5.4 computes it: with hops, giaddr and the MAC
+/// itself zero.
+fn message_mac(data: &[u8], at: usize, secret: &[u8]) -> [u8; 16] {
+    let mut m = data.to_vec();
+    m[3] = 0;
+    m[24..28].fill(0);
+    m[at..at + 16].fill(0);
+    hmac_md5(secret, &m)
+}
+
+fn hmac_md5(key: &[u8], data: &[u8]) -> [u8; 16] {
+    let mut block = [0u8; 64];
+    if key.len() > 64 {
+        block[..16].copy_from_slice(&md5(key));
+    } else {
+        block[..key.len()].copy_from_slice(key);
+    }
+    let mut inner: Vec<u8> = block.iter().map(|b| b ^ 0x36).collect();
+    inner.extend(data);
+    let mut outer: Vec<u8> = block.iter().map(|b| b ^ 0x5c).collect();
+    outer.extend(md5(&inner));
+    md5(&outer)
+}
+
+const K: [u32; 64] = [
+    0xd76aa478, 0xe8c7b756, 0x242070db, 0xc1bdceee, 0xf57c0faf, 0x4787c62a, 0xa8304613, 0xfd469501,
+    0x698098d8, 0x8b44f7af, 0xffff5bb1, 0x895cd7be, 0x6b901122, 0xfd987193, 0xa679438e, 0x49b40821,
+    0xf61e2562, 0xc040b340, 0x265e5a51, 0xe9b6c7aa, 0xd62f105d, 0x02441453, 0xd8a1e681, 0xe7d3fbc8,
+    0x21e1cde6, 0xc33707d6, 0xf4d50d87, 0x455a14ed, 0xa9e3e905, 0xfcefa3f8, 0x676f02d9, 0x8d2a4c8a,
+    0xfffa3942, 0x8771f681, 0x6d9d6122, 0xfde5380c, 0xa4beea44, 0x4bdecfa9, 0xf6bb4b60, 0xbebfbc70,
+    0x289b7ec6, 0xeaa127fa, 0xd4ef3085, 0x04881d05, 0xd9d4d039, 0xe6db99e5, 0x1fa27cf8, 0xc4ac5665,
+    0xf4292244, 0x432aff97, 0xab9423a7, 0xfc93a039, 0x655b59c3, 0x8f0ccc92, 0xffeff47d, 0x85845dd1,
+    0x6fa87e4f, 0xfe2ce6e0, 0xa3014314, 0x4e0811a1, 0xf7537e82, 0xbd3af235, 0x2ad7d2bb, 0xeb86d391,
+];
+
+const S: [u32; 64] = [
+    7, 12, 17, 22, 7, 12, 17, 22, 7, 12, 17, 22, 7, 12, 17, 22, 5, 9, 14, 20, 5, 9, 14, 20, 5, 9,
+    14, 20, 5, 9, 14, 20, 4, 11, 16, 23, 4, 11, 16, 23, 4, 11, 16, 23, 4, 11, 16, 23, 6, 10, 15,
+    21, 6, 10, 15, 21, 6, 10, 15, 21, 6, 10, 15, 21,
+];
+
+/// MD5 (RFC 1321).
+fn md5(data: &[u8]) -> [u8; 16] {
+    let mut h: [u32; 4] = [0x67452301, 0xefcdab89, 0x98badcfe, 0x10325476];
+    let mut padded = data.to_vec();
+    padded.push(0x80);
+    while padded.len() % 64 != 56 {
+        padded.push(0);
+    }
+    padded.extend((data.len() as u64 * 8).to_le_bytes());
+    for chunk in padded.chunks(64) {
+        let mut w = [0u32; 16];
+        for (i, word) in chunk.chunks(4).enumerate() {
+            w[i] = u32::from_le_bytes([word[0], word[1], word[2], word[3]]);
+        }
+        let [mut a, mut b, mut c, mut d] = h;
+        for i in 0..64 {
+            let (f, g) = match i / 16 {
+                0 => ((b & c) | (!b & d), i),
+                1 => ((d & b) | (!d & c), (5 * i + 1) % 16),
+                2 => (b ^ c ^ d, (3 * i + 5) % 16),
+                _ => (c ^ (b | !d), (7 * i) % 16),
+            };
+            let f = f.wrapping_add(a).wrapping_add(K[i]).wrapping_add(w[g]);
+            a = d;
+            d = c;
+            c = b;
+            b = b.wrapping_add(f.rotate_left(S[i]));
+        }
+        for (x, y) in h.iter_mut().zip([a, b, c, d]) {
+            *x = x.wrapping_add(y);
+        }
+    }
+    let mut out = [0u8; 16];
+    for (i, x) in h.iter().enumerate() {
+        out[i * 4..i * 4 + 4].copy_from_slice(&x.to_le_bytes());
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn hex(b: &[u8]) -> String {
+        b.iter().map(|x| format!("{:02x}", x)).collect()
+    }
+
+    /// RFC 1321 A.5.
+    #[test]
+    fn md5_vectors() {
+        let cases: [(&[u8], &str); 4] = [
+            (b"", "d41d8cd98f00b204e9800998ecf8427e"),
+            (b"abc", "900150983cd24fb0d6963f7d28e17f72"),
+            (b"message digest", "f96b697d7cb7938d525a2f31aaf161d0"),
+            (
+                b"12345678901234567890123456789012345678901234567890123456789012345678901234567890",
+                "57edf4a22be3c955ac49da2e2107b67a",
+            ),
+        ];
+        for (data, digest) in cases {
+            assert_eq!(hex(&md5(data)), digest);
+        }
+    }
+
+    #[test]
+    fn unverified_clients_are_forgotten_first() {
+        let mut state = State {
+            required: false,
+            keys: vec![(1, b"k".to_vec())],
+            clients: HashMap::new(),
+            replay: 0,
+        };
+        let mac = |n: u32| HwAddr::new(1, &[&[0, 0][..], &n.to_be_bytes()].concat());
+        state.remember(mac(0), 1, 5, true);
+        for n in 1..MAX_CLIENTS as u32 + 10 {
+            state.remember(mac(n), 1, 0, false);
+        }
+        assert_eq!(state.clients.len(), MAX_CLIENTS);
+        assert!(state.clients[&mac(0)].verified);
+    }
+
+    /// RFC 2202 test cases 1, 2 and 6.
+    #[test]
+    fn hmac_md5_vectors() {
+        let cases: [(&[u8], &[u8], &str); 3] = [
+            (&[0x0b; 16], b"Hi There", "9294727a3638bb1c13f48ef8158bfc9d"),
+            (
+                b"Jefe",
+                b"what do ya want for nothing?",
+                "750c783e6ab0b503eaa86e310a5db738",
+            ),
+            (
+                &[0xaa; 80],
+                b"Test Using Larger Than Block-Size Key - Hash Key First",
+                "6b1ab7fe4bd7bf8f0b62e6ce61b9d0cd",
+            ),
+        ];
+        for (key, data, mac) in cases {
+            assert_eq!(hex(&hmac_md5(key, data)), mac);
+        }
+    }
+}
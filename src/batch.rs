@@ -0,0 +1,136 @@
+//! Batched datagram I/O: `recvmmsg`/`sendmmsg` on Linux, one packet per call elsewhere.
+
+use std::io;
+use std::net::{SocketAddr, UdpSocket};
+
+pub const MAX_PACKET: usize = 1500;
+
+/// An encoded packet and where it goes.
+pub type Datagram = (Vec<u8>, SocketAddr);
+
+/// Receives up to `bufs.len()` packets, blocking (within the socket's read timeout) only
+/// until the first one arrives. Returns the length and sender of each.
+#[cfg(target_os = "linux")]
+pub fn recv(
+    socket: &UdpSocket,
+    bufs: &mut [[u8; MAX_PACKET]],
+) -> io::Result<Vec<(usize, SocketAddr)>> {
+    use std::os::fd::AsRawFd;
+
+    let n = bufs.len();
+    let mut addrs: Vec<libc::sockaddr_in> = vec![unsafe { std::mem::zeroed() }; n];
+    let mut iovs: Vec<libc::iovec> = bufs
+        .iter_mut()
+        .map(|b| libc::iovec {
+            iov_base: b.as_mut_ptr() as *mut libc::c_void,
+            iov_len: MAX_PACKET,
+        })
+        .collect();
+    let mut msgs: Vec<libc::mmsghdr> = (0..n)
+        .map(|i| {
+            let mut h: libc::mmsghdr = unsafe { std::mem::zeroed() };
+            h.msg_hdr.msg_name = &mut addrs[i] as *mut _ as *mut libc::c_void;
+            h.msg_hdr.msg_namelen = std::mem::size_of::<libc::sockaddr_in>() as libc::socklen_t;
+            h.msg_hdr.msg_iov = &mut iovs[i];
+            h.msg_hdr.msg_iovlen = 1;
+            h
+        })
+        .collect();
+    let got = unsafe {
+        libc::recvmmsg(
+            socket.as_raw_fd(),
+            msgs.as_mut_ptr(),
+            n as libc::c_uint,
+            libc::MSG_WAITFORONE,
+            std::ptr::null_mut(),
+        )
+    };
+    if got < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok((0..got as usize)
+        .map(|i| (msgs[i].msg_len as usize, from_sockaddr(&addrs[i])))
+        .collect())
+}
+
+/// Sends all `packets`, returning how many went out before the first error.
+#[cfg(target_os = "linux")]
+pub fn send(socket: &UdpSocket, packets: &[Datagram]) -> io::Result<usize> {
+    use std::os::fd::AsRawFd;
+
+    let mut addrs: Vec<libc::sockaddr_in> = packets.iter().map(|(_, a)| to_sockaddr(a)).collect();
+    let mut iovs: Vec<libc::iovec> = packets
+        .iter()
+        .map(|(d, _)| libc::iovec {
+            iov_base: d.as_ptr() as *mut libc::c_void,
+            iov_len: d.len(),
+        })
+        .collect();
+    let mut msgs: Vec<libc::mmsghdr> = (0..packets.len())
+        .map(|i| {
+            let mut h: libc::mmsghdr = unsafe { std::mem::zeroed() };
+            h.msg_hdr.msg_name = &mut addrs[i] as *mut _ as *mut libc::c_void;
+            h.msg_hdr.msg_namelen = std::mem::size_of::<libc::sockaddr_in>() as libc::socklen_t;
+            h.msg_hdr.msg_iov = &mut iovs[i];
+            h.msg_hdr.msg_iovlen = 1;
+            h
+        })
+        .collect();
+    let mut sent = 0;
+    while sent < msgs.len() {
+        let r = unsafe {
+            libc::sendmmsg(
+                socket.as_raw_fd(),
+                msgs[sent..].as_mut_ptr(),
+                (msgs.len() - sent) as libc::c_uint,
+                0,
+            )
+        };
+        if r < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        sent += r as usize;
+    }
+    Ok(sent)
+}
+
+#[cfg(target_os = "linux")]
+fn from_sockaddr(a: &libc::sockaddr_in) -> SocketAddr {
+    SocketAddr::from((
+        u32::from_be(a.sin_addr.s_addr).to_be_bytes(),
+        u16::from_be(a.sin_port),
+    ))
+}
+
+#[cfg(target_os = "linux")]
+fn to_sockaddr(addr: &SocketAddr) -> libc::sockaddr_in {
+    let ip = match addr {
+        SocketAddr::V4(a) => u32::from(*a.ip()),
+        // the DHCP socket is IPv4 only
+        SocketAddr::V6(_) => 0,
+    };
+    libc::sockaddr_in {
+        sin_family: libc::AF_INET as libc::sa_family_t,
+        sin_port: addr.port().to_be(),
+        sin_addr: libc::in_addr { s_addr: ip.to_be() },
+        sin_zero: [0; 8],
+    }
+}
+
+#[cfg(not(target_os = "linux"))]
+pub fn recv(
+    socket: &UdpSocket,
+    bufs: &mut [[u8; MAX_PACKET]],
+) -> io::Result<Vec<(usize, SocketAddr)>> {
+    Ok(vec![socket.recv_from(&mut bufs[0])?])
+}
+
+#[cfg(not(target_os = "linux"))]
+pub fn send(socket: &UdpSocket, packets: &[Datagram]) -> io::Result<usize> {
+    for (i, (data, addr)) in packets.iter().enumerate() {
+        if let Err(e) = socket.send_to(data, addr) {
+            return if i == 0 { Err(e) } else { Ok(i) };
+        }
+    }
+    Ok(packets.len())
+}
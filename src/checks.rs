@@ -0,0 +1,87 @@
+//! Network sanity checks run at startup, beyond what parsing the configuration catches.
+
+use crate::config::Config;
+use crate::utils;
+
+use std::fs;
+use std::net::Ipv4Addr;
+use std::path::Path;
+
+/// Everything wrong with `conf`, one message per problem.
+pub fn check(conf: &Config) -> Vec<String> {
+    let mut problems = vec![];
+    let addrs = utils::interface_addrs(&conf.intf)
+        .map(|addrs| addrs.into_iter().map(|(a, _)| a).collect::<Vec<Ipv4Addr>>());
+    match addrs {
+        Ok(addrs) if addrs.is_empty() => problems.push(format!(
+            "interface {} has no IPv4 address or does not exist",
+            conf.intf
+        )),
+        Ok(addrs) if !addrs.contains(&conf.listen_addr) => problems.push(format!(
+            "listen_addr {} is not configured on {}",
+            conf.listen_addr, conf.intf
+        )),
+        Ok(_) => {}
+        Err(e) => problems.push(format!("cannot list addresses of {}: {}", conf.intf, e)),
+    }
+    check_subnet(conf, conf.listen_addr, "", &mut problems);
+    // tenants are served through relays, so their gateway is the address on their subnet
+    for (i, t) in conf.tenants.iter().enumerate() {
+        let prefix = format!("tenant {}: ", i + 1);
+        check_subnet(&t.conf, t.conf.gateway, &prefix, &mut problems);
+    }
+    problems
+}
+
+/// Checks the pool, gateway and broadcast address against the subnet `anchor` is on.
+fn check_subnet(conf: &Config, anchor: Ipv4Addr, prefix: &str, problems: &mut Vec<String>) {
+    let mask = u32::from(conf.netmask);
+    if mask.leading_ones() + mask.trailing_zeros() != 32 {
+        problems.push(format!(
+            "{}netmask {} is not contiguous",
+            prefix, conf.netmask
+        ));
+    }
+    let net = u32::from(anchor) & mask;
+    let in_subnet = |a: Ipv4Addr| u32::from(a) & mask == net;
+    let subnet = Ipv4Addr::from(net);
+    for (name, addr) in [
+        ("start", conf.start),
+        ("end", conf.end),
+        ("gateway", conf.gateway),
+    ] {
+        if !in_subnet(addr) {
+            problems.push(format!(
+                "{}{} {} is outside the subnet {}/{}",
+                prefix, name, addr, subnet, conf.netmask
+            ));
+        }
+    }
+    if u32::from(conf.start) >= u32::from(conf.end) {
+        problems.push(format!(
+            "{}pool {} - {} is empty",
+            prefix, conf.start, conf.end
+        ));
+    }
+    let broadcast = Ipv4Addr::from(net | !mask);
+    if conf.broadcast != broadcast {
+        problems.push(format!(
+            "{}broadcast {} does not match netmask {} (expected {})",
+            prefix, conf.broadcast, conf.netmask, broadcast
+        ));
+    }
+    if let Err(e) = check_writable(&conf.lease_file) {
+        problems.push(format!("{}lease_file {}: {}", prefix, conf.lease_file, e));
+    }
+}
+
+/// Creates and removes a probe file next to `path`.
+fn check_writable(path: &str) -> std::io::Result<()> {
+    let dir = match Path::new(path).parent() {
+        Some(d) if !d.as_os_str().is_empty() => d,
+        _ => Path::new("."),
+    };
+    let probe = dir.join(".rdhcpd-write-test");
+    fs::write(&probe, b"")?;
+    fs::remove_file(probe)
+}
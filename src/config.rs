@@ -1,17 +1,47 @@
-use serde::Deserialize;
+use serde::{Deserialize, Deserializer};
+use serde_yaml::{Mapping, Value};
 
 use std::error::Error;
+use std::fmt;
 use std::fs::File;
 use std::io::BufReader;
-use std::net::Ipv4Addr;
+use std::net::{Ipv4Addr, Ipv6Addr, SocketAddr};
 use std::path::Path;
+use std::str::FromStr;
+
+use crate::args;
+use crate::hwaddr::HwAddr;
+use crate::options;
+use crate::packet;
+use crate::snmp;
+use crate::template;
+use crate::tsig;
+use crate::utils;
 
 #[derive(Deserialize, Debug, Clone)]
 pub struct Config {
+    /// Interface served; bound to when several configurations run in one process.
     pub intf: String,
     pub listen_addr: Ipv4Addr,
     pub start: Ipv4Addr,
     pub end: Ipv4Addr,
+    /// Further ranges of the pool besides `start`-`end`, each `a.b.c.d-e.f.g.h` including both
+    /// ends, unlike `end`, which is not handed out. They must not overlap.
+    #[serde(default, deserialize_with = "deserialize_ranges")]
+    pub ranges: Vec<(Ipv4Addr, Ipv4Addr)>,
+    /// How free addresses are picked for new clients.
+    #[serde(default)]
+    pub allocation: Allocation,
+    /// How long an address stays kept for its client after the lease ran out.
+    #[serde(default)]
+    pub recycle_after: Option<String>,
+    /// Pools naming the same shared network sit on one wire; a client is served from the next
+    /// one when its own is full.
+    #[serde(default)]
+    pub shared_network: Option<String>,
+    /// Addresses of the pool never handed out, each `a.b.c.d` or `a.b.c.d-e.f.g.h`.
+    #[serde(default, deserialize_with = "deserialize_ranges")]
+    pub exclude: Vec<(Ipv4Addr, Ipv4Addr)>,
     pub netmask: Ipv4Addr,
     pub broadcast: Ipv4Addr,
     pub gateway: Ipv4Addr,
@@ -19,11 +49,1294 @@ pub struct Config {
     pub lease_static: String,
     pub lease_file: String,
     pub lease_time: String,
+    /// Answer DISCOVERs carrying option 80 with an ACK right away (RFC 4039).
+    #[serde(default)]
+    pub rapid_commit: bool,
+    /// Return the client identifier in replies (RFC 6842); some old clients choke on it.
+    #[serde(default = "default_echo_client_id")]
+    pub echo_client_id: bool,
+    /// Shortest lease a client may ask for with option 51; requests are not shortened if unset.
+    #[serde(default)]
+    pub min_lease_time: Option<String>,
+    /// Longest lease a client may ask for with option 51; requests are not extended if unset.
+    #[serde(default)]
+    pub max_lease_time: Option<String>,
+    /// When clients start renewing (T1, option 58); half the lease time if unset.
+    #[serde(default)]
+    pub renew_time: Option<String>,
+    /// When clients start rebinding (T2, option 59); 87.5% of the lease time if unset.
+    #[serde(default)]
+    pub rebind_time: Option<String>,
+    #[serde(default)]
+    pub vendor_options: Vec<VendorOption>,
+    /// Options rdhcpd has no setting for, by code; they replace a built-in option of the same
+    /// code.
+    #[serde(default)]
+    pub options: Vec<SubOption>,
+    /// Offset from UTC in seconds (option 2), for clients without TZ string support.
+    #[serde(default)]
+    pub time_offset: Option<i32>,
+    /// POSIX TZ string (option 100), e.g. `CET-1CEST,M3.5.0,M10.5.0/3`.
+    #[serde(default)]
+    pub tz_posix: Option<String>,
+    /// tz database name (option 101), e.g. `Europe/Berlin`.
+    #[serde(default)]
+    pub tz_database: Option<String>,
+    /// How long IPv6-only capable clients should leave IPv4 off (RFC 8925), e.g. `30m`.
+    #[serde(default)]
+    pub v6_only_wait: Option<String>,
+    /// URI of the captive portal API (RFC 8910), sent as option 114.
+    #[serde(default)]
+    pub captive_portal_url: Option<String>,
+    /// CAPWAP access controllers (RFC 5417), sent as option 138.
+    #[serde(default)]
+    pub capwap_controllers: Option<Vec<Ipv4Addr>>,
+    /// MTU of the clients' interface (option 26), e.g. 9000 for jumbo frames or 1492 behind
+    /// PPPoE.
+    #[serde(default)]
+    pub mtu: Option<u16>,
+    /// NTP servers (option 42).
+    #[serde(default)]
+    pub ntp_servers: Vec<Ipv4Addr>,
+    /// RFC 868 time servers (option 4).
+    #[serde(default)]
+    pub time_servers: Vec<Ipv4Addr>,
+    /// Syslog servers (option 7).
+    #[serde(default)]
+    pub log_servers: Vec<Ipv4Addr>,
+    /// SIP servers (option 120), all given as addresses or all as domain names.
+    #[serde(default)]
+    pub sip_servers: Vec<String>,
+    /// NetBIOS over TCP/IP name servers, i.e. WINS (option 44).
+    #[serde(default)]
+    pub netbios_name_servers: Vec<Ipv4Addr>,
+    /// NetBIOS node type (option 46).
+    #[serde(default)]
+    pub netbios_node_type: Option<NetbiosNodeType>,
+    /// Domain name of the clients (option 15).
+    #[serde(default)]
+    pub domain: Option<String>,
+    /// Domain search list (option 119).
+    #[serde(default)]
+    pub search_domains: Vec<String>,
+    /// Proxy auto-config URL (option 252), e.g. `http://wpad.lan/wpad.dat`.
+    #[serde(default)]
+    pub wpad_url: Option<String>,
+    /// Boot server for PXE clients (`siaddr`), also sent as option 66 unless `tftp_server` is set.
+    #[serde(default)]
+    pub next_server: Option<Ipv4Addr>,
+    /// TFTP server name (option 66).
+    #[serde(default)]
+    pub tftp_server: Option<String>,
+    /// TFTP server addresses (option 150), as Cisco IP phones expect instead of option 66.
+    #[serde(default)]
+    pub tftp_servers: Option<Vec<Ipv4Addr>>,
+    /// Boot file name, sent in the `file` field and as option 67.
+    #[serde(default)]
+    pub boot_file: Option<String>,
+    /// Hand out no addresses, only boot information to PXE clients, next to another DHCP server.
+    #[serde(default)]
+    pub proxy_dhcp: bool,
+    /// `http://` URL that learned MUD URLs (option 161) are POSTed to.
+    #[serde(default)]
+    pub mud_webhook: Option<String>,
+    /// `http://` URL that lease events (bound, released) are POSTed to.
+    #[serde(default)]
+    pub lease_webhook: Option<String>,
+    /// Keep the addresses of the containers on a Docker network out of the pool.
+    #[serde(default)]
+    pub docker: Option<DockerConf>,
+    /// Render DNS zone files from the active leases.
+    #[serde(default)]
+    pub zones: Option<ZoneConf>,
+    /// Register the leases' names with a DNS server through dynamic updates (RFC 2136).
+    #[serde(default)]
+    pub ddns: Option<DdnsConf>,
+    /// Classless static routes (option 121) sent to every client.
+    #[serde(default)]
+    pub routes: Vec<Route>,
+    #[serde(default)]
+    pub classes: Vec<ClientClass>,
+    #[serde(default)]
+    pub vendor_profiles: Vec<VendorProfile>,
+    /// Address of the HTTP management interface, disabled when unset.
+    #[serde(default)]
+    pub mgmt_listen: Option<SocketAddr>,
+    /// Bearer tokens accepted by the management interface; open to anyone when empty.
+    #[serde(default)]
+    pub mgmt_tokens: Vec<MgmtToken>,
+    /// Stay silent for this long after start, learning the leases of the server being
+    /// replaced, see `takeover.rs`.
+    #[serde(default)]
+    pub takeover: Option<String>,
+    /// Copy all DHCP traffic to a remote collector.
+    #[serde(default)]
+    pub mirror: Option<MirrorConf>,
+    /// Answer bulk and active leasequeries over TCP.
+    #[serde(default)]
+    pub leasequery: Option<LeasequeryConf>,
+    /// Authenticate clients and sign replies with delayed authentication (RFC 3118).
+    #[serde(default)]
+    pub authentication: Option<AuthConf>,
+    /// Serve DHCPv6 on the same interface, see `dhcp6.rs`.
+    #[serde(default)]
+    pub dhcp6: Option<Dhcp6Conf>,
+    /// Send router advertisements on the interface, see `ra.rs`.
+    #[serde(default)]
+    pub router_advertisements: Option<RaConf>,
+    /// Packets received and replies sent per system call (`recvmmsg`/`sendmmsg` on Linux).
+    #[serde(default = "default_io_batch")]
+    pub io_batch: usize,
+    /// What to do when the startup network checks find a problem.
+    #[serde(default)]
+    pub startup_checks: CheckMode,
+    /// Drop malformed packets in the kernel before they reach the server (Linux).
+    #[serde(default)]
+    pub socket_filter: bool,
+    /// Where lease events are logged, `stdout` (default) or `journald`.
+    #[serde(default)]
+    pub log_target: LogTarget,
+    /// Push metrics to a StatsD or Graphite collector.
+    #[serde(default)]
+    pub metrics_push: Option<MetricsPush>,
+    /// Serve the statistics over SNMPv2c.
+    #[serde(default)]
+    pub snmp: Option<SnmpConf>,
+    /// File the SIGUSR1 state dump is written to instead of stdout.
+    #[serde(default)]
+    pub dump_file: Option<String>,
+    /// Keep the statistics across restarts.
+    #[serde(default)]
+    pub stats_file: Option<StatsFile>,
+    #[serde(skip)]
+    pub tenants: Vec<Tenant>,
+}
+
+impl Config {
+    /// Rejects settings that would otherwise only fail once a client shows up.
+    fn check_settings(&self) -> Result<(), Box<dyn Error>> {
+        let durations = [
+            Some(&self.lease_time),
+            self.min_lease_time.as_ref(),
+            self.max_lease_time.as_ref(),
+            self.renew_time.as_ref(),
+            self.rebind_time.as_ref(),
+            self.v6_only_wait.as_ref(),
+            self.recycle_after.as_ref(),
+            self.metrics_push.as_ref().map(|m| &m.interval),
+            self.stats_file.as_ref().map(|s| &s.interval),
+            self.takeover.as_ref(),
+            self.docker.as_ref().map(|d| &d.interval),
+            self.ddns.as_ref().map(|d| &d.retry),
+            self.dhcp6.as_ref().and_then(|d| d.lease_time.as_ref()),
+            self.dhcp6
+                .as_ref()
+                .and_then(|d| d.preferred_lifetime.as_ref()),
+            self.router_advertisements.as_ref().map(|r| &r.interval),
+            self.router_advertisements
+                .as_ref()
+                .and_then(|r| r.router_lifetime.as_ref()),
+        ]
+        .into_iter()
+        .chain(self.classes.iter().map(|c| c.lease_time.as_ref()));
+        if !(1..=1024).contains(&self.io_batch) {
+            return Err(
+                format!("io_batch must be between 1 and 1024, got {}", self.io_batch).into(),
+            );
+        }
+        let raw = self.classes.iter().flat_map(|c| &c.options);
+        for o in self.options.iter().chain(raw) {
+            if matches!(
+                o.code,
+                packet::PAD
+                    | packet::END
+                    | options::OVERLOAD
+                    | options::DHCP_MESSAGE_TYPE
+                    | options::SERVER_IDENTIFIER
+                    | options::RELAY_AGENT_INFORMATION
+                    | options::AUTHENTICATION
+            ) {
+                return Err(match options::title(o.code) {
+                    Some(t) => format!("options: code {} ({}) is set by the server", o.code, t),
+                    None => format!("options: code {} is set by the server", o.code),
+                }
+                .into());
+            }
+        }
+        for mac in self.classes.iter().flat_map(|c| &c.matches.mac) {
+            HwAddr::from_str(mac)?;
+        }
+        if let Some(z) = &self.zones {
+            if z.forward.is_some() && z.domain.is_none() {
+                return Err("zones: a forward zone needs a domain".into());
+            }
+        }
+        if let Some(d) = &self.ddns {
+            if d.zone.trim_end_matches('.').is_empty() {
+                return Err("ddns: zone must not be empty".into());
+            }
+            if let Some(k) = &d.key {
+                if d.gss.is_some() {
+                    return Err("ddns: use either a TSIG key or gss, not both".into());
+                }
+                k.key()?;
+            }
+        }
+        if let Some(a) = &self.authentication {
+            if a.keys.is_empty() {
+                return Err("authentication: at least one key is needed".into());
+            }
+            for (i, k) in a.keys.iter().enumerate() {
+                if a.keys[..i].iter().any(|o| o.id == k.id) {
+                    return Err(format!("authentication: key {} given twice", k.id).into());
+                }
+                k.secret()?;
+            }
+        }
+        let main = (u32::from(self.start), u32::from(self.end));
+        for (i, (s, e)) in self.ranges.iter().enumerate() {
+            if !self.subnet_contains(*s) || !self.subnet_contains(*e) {
+                return Err(format!("range {}-{} is not in the subnet", s, e).into());
+            }
+            let (a, b) = (u32::from(*s), u32::from(*e).saturating_add(1));
+            let others = self.ranges[..i]
+                .iter()
+                .map(|(s, e)| (u32::from(*s), u32::from(*e).saturating_add(1)));
+            if std::iter::once(main)
+                .chain(others)
+                .any(|(x, y)| a < y && x < b)
+            {
+                return Err(format!("range {}-{} overlaps another part of the pool", s, e).into());
+            }
+        }
+        if let Some(s) = &self.snmp {
+            if snmp::parse_oid(&s.oid).is_none() {
+                return Err(format!("snmp: invalid OID {}", s.oid).into());
+            }
+        }
+        if let Some(d) = &self.dhcp6 {
+            if d.start > d.end {
+                return Err("dhcp6: start must not be after end".into());
+            }
+        }
+        if let Some(ra) = &self.router_advertisements {
+            let (prefix, len) = ra.prefix()?;
+            if ra.autonomous && len != 64 {
+                return Err("router_advertisements: SLAAC needs a /64 prefix".into());
+            }
+            let mask = u128::MAX.checked_shl(128 - len as u32).unwrap_or(0);
+            let outside = |a: Ipv6Addr| u128::from(a) & mask != u128::from(prefix) & mask;
+            if let Some(d) = &self.dhcp6 {
+                if outside(d.start) || outside(d.end) {
+                    return Err("router_advertisements: the dhcp6 pool is not in the prefix".into());
+                }
+            }
+        }
+        if self.proxy_dhcp
+            && self.boot_file.is_none()
+            && self.classes.iter().all(|c| c.boot_file.is_none())
+        {
+            return Err("proxy_dhcp needs a boot_file".into());
+        }
+        if let Some(mtu) = self.mtu {
+            // RFC 2132: the minimum legal value is 68
+            if mtu < 68 {
+                return Err(format!("mtu must be at least 68, got {}", mtu).into());
+            }
+        }
+        let names = self.domain.iter().chain(&self.search_domains);
+        for name in names.chain(&self.sip_servers) {
+            if name
+                .trim_end_matches('.')
+                .split('.')
+                .any(|l| l.is_empty() || l.len() > 63)
+            {
+                return Err(format!("invalid domain name {}", name).into());
+            }
+        }
+        self.sip_option()?;
+        // sub-options and the option 125 enterprise blocks carry a one-byte length
+        let class_subs = self
+            .classes
+            .iter()
+            .filter_map(|c| match &c.vendor_specific {
+                Some(VendorSpecific::Suboptions { suboptions }) => Some(suboptions),
+                _ => None,
+            });
+        let vendor_subs = self.vendor_options.iter().map(|v| &v.suboptions);
+        for o in class_subs.chain(vendor_subs).flatten() {
+            if o.value.size() > 255 {
+                return Err(format!("sub-option {} is longer than 255 bytes", o.code).into());
+            }
+        }
+        for v in &self.vendor_options {
+            if v.suboptions
+                .iter()
+                .map(|o| 2 + o.value.size())
+                .sum::<usize>()
+                > 255
+            {
+                return Err(format!(
+                    "vendor_options: sub-options of enterprise {} exceed 255 bytes",
+                    v.enterprise
+                )
+                .into());
+            }
+        }
+        // Longer lists are split over several option 119 instances (RFC 3396), but they still
+        // have to fit in the smallest message every client accepts.
+        let list = options::encode_domain_list(&self.search_domains).len();
+        if 240 + list + 2 * list.div_ceil(255) >= packet::MAX_MESSAGE_SIZE {
+            return Err("search_domains do not fit in a 576 byte message".into());
+        }
+        for d in durations.flatten() {
+            duration_str::parse(d.as_str())
+                .map_err(|e| format!("invalid duration {}: {}", d, e))?;
+        }
+        Ok(())
+    }
+
+    /// `sip_servers` as option 120 wants them, which cannot mix names and addresses.
+    pub fn sip_option(&self) -> Result<Option<options::SipServers>, String> {
+        if self.sip_servers.is_empty() {
+            return Ok(None);
+        }
+        let addrs: Vec<Ipv4Addr> = self
+            .sip_servers
+            .iter()
+            .filter_map(|s| s.parse().ok())
+            .collect();
+        if addrs.len() == self.sip_servers.len() {
+            return Ok(Some(options::SipServers::Addrs(addrs)));
+        }
+        if !addrs.is_empty() {
+            return Err("sip_servers must be all addresses or all domain names".to_string());
+        }
+        Ok(Some(options::SipServers::Names(self.sip_servers.clone())))
+    }
+
+    /// Whether `addr` lies in the subnet served by this configuration.
+    pub fn subnet_contains(&self, addr: Ipv4Addr) -> bool {
+        let mask = u32::from(self.netmask);
+        u32::from(addr) & mask == u32::from(self.start) & mask
+    }
+
+    /// Whether `addr` falls in one of the `exclude` entries.
+    pub fn excluded(&self, addr: Ipv4Addr) -> bool {
+        self.exclude.iter().any(|(s, e)| (*s..=*e).contains(&addr))
+    }
+}
+
+/// How NetBIOS names are resolved (RFC 1001): broadcast, point-to-point (WINS), mixed or
+/// hybrid (WINS first).
+#[derive(Deserialize, Debug, Clone, Copy)]
+#[serde(rename_all = "lowercase")]
+pub enum NetbiosNodeType {
+    B = 1,
+    P = 2,
+    M = 4,
+    H = 8,
+}
+
+#[derive(Deserialize, Debug, Clone)]
+pub struct MetricsPush {
+    pub protocol: MetricsProtocol,
+    /// StatsD UDP, Graphite plaintext TCP or OTLP/HTTP collector address.
+    pub addr: SocketAddr,
+    #[serde(default = "default_metrics_prefix")]
+    pub prefix: String,
+    #[serde(default = "default_metrics_interval")]
+    pub interval: String,
+}
+
+#[derive(Deserialize, Debug, Clone)]
+pub struct ZoneConf {
+    /// Path of the reverse (PTR) zone file.
+    pub reverse: String,
+    /// Path of the forward (A) zone file for `domain`.
+    #[serde(default)]
+    pub forward: Option<String>,
+    /// Domain the lease hostnames live in, e.g. `lan`.
+    #[serde(default)]
+    pub domain: Option<String>,
+    #[serde(default = "default_zone_ttl")]
+    pub ttl: u32,
+    /// Name server put in the SOA and NS records.
+    #[serde(default = "default_zone_nameserver")]
+    pub nameserver: String,
+    /// Shell command run after the zones changed, e.g. `rndc reload`.
+    #[serde(default)]
+    pub reload: Option<String>,
+}
+
+fn default_zone_ttl() -> u32 {
+    300
+}
+
+fn default_zone_nameserver() -> String {
+    "localhost".to_string()
+}
+
+#[derive(Deserialize, Debug, Clone)]
+pub struct DdnsConf {
+    /// Primary server of the zone, e.g. `192.168.10.53:53`.
+    pub server: SocketAddr,
+    /// Zone the A records are put in, e.g. `lan`.
+    pub zone: String,
+    /// Zone the PTR records are put in, e.g. `10.168.192.in-addr.arpa`; none are sent when unset.
+    #[serde(default)]
+    pub reverse_zone: Option<String>,
+    #[serde(default = "default_zone_ttl")]
+    pub ttl: u32,
+    /// How long to wait before sending an update the server did not answer again.
+    #[serde(default = "default_ddns_retry")]
+    pub retry: String,
+    /// Sign the updates with this TSIG key.
+    #[serde(default)]
+    pub key: Option<TsigKeyConf>,
+    /// Sign the updates with GSS-TSIG (Kerberos), as Active Directory zones want.
+    #[serde(default)]
+    pub gss: Option<GssConf>,
+}
+
+#[derive(Deserialize, Debug, Clone)]
+pub struct GssConf {
+    /// Keytab holding the key of `principal`.
+    pub keytab: String,
+    /// Principal the updates are made as, e.g. `rdhcpd$@CORP.EXAMPLE.COM`.
+    pub principal: String,
+    /// Where the ticket is cached between updates.
+    #[serde(default = "default_gss_ccache")]
+    pub ccache: String,
+    /// Host name of the DNS server, for its `DNS/<name>` service ticket; Kerberos does not
+    /// work with the bare address in `server`.
+    #[serde(default)]
+    pub server_name: Option<String>,
+}
+
+fn default_gss_ccache() -> String {
+    "/tmp/rdhcpd.krb5cc".to_string()
+}
+
+#[derive(Deserialize, Clone)]
+pub struct TsigKeyConf {
+    /// Key name as configured on the server, e.g. `rdhcpd`.
+    pub name: String,
+    #[serde(default = "default_tsig_algorithm")]
+    pub algorithm: String,
+    /// Base64 secret, as `tsig-keygen` prints it.
+    pub secret: String,
+}
+
+/// Without the secret, as the configuration is logged at startup.
+impl fmt::Debug for TsigKeyConf {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("TsigKeyConf")
+            .field("name", &self.name)
+            .field("algorithm", &self.algorithm)
+            .finish_non_exhaustive()
+    }
+}
+
+impl TsigKeyConf {
+    pub fn key(&self) -> Result<tsig::Key, String> {
+        if !self.algorithm.eq_ignore_ascii_case(tsig::HMAC_SHA256) {
+            return Err(format!(
+                "ddns: unsupported TSIG algorithm {}, only {} is",
+                self.algorithm,
+                tsig::HMAC_SHA256
+            ));
+        }
+        let secret = tsig::decode_base64(&self.secret)
+            .filter(|s| !s.is_empty())
+            .ok_or_else(|| format!("ddns: TSIG key {} has an invalid secret", self.name))?;
+        Ok(tsig::Key {
+            name: self.name.clone(),
+            secret,
+        })
+    }
+}
+
+fn default_echo_client_id() -> bool {
+    true
+}
+
+fn default_tsig_algorithm() -> String {
+    tsig::HMAC_SHA256.to_string()
+}
+
+fn default_ddns_retry() -> String {
+    "30s".to_string()
+}
+
+#[derive(Deserialize, Debug, Clone)]
+pub struct AuthConf {
+    /// Drop requests that are not authenticated instead of serving them unsigned.
+    #[serde(default)]
+    pub required: bool,
+    /// The first key signs the replies to clients that have not picked one yet.
+    pub keys: Vec<AuthKeyConf>,
+}
+
+#[derive(Deserialize, Clone)]
+pub struct AuthKeyConf {
+    /// The secret ID clients name the key by.
+    pub id: u32,
+    /// Base64.
+    pub secret: String,
+}
+
+impl fmt::Debug for AuthKeyConf {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("AuthKeyConf")
+            .field("id", &self.id)
+            .finish_non_exhaustive()
+    }
+}
+
+impl AuthKeyConf {
+    pub fn secret(&self) -> Result<Vec<u8>, String> {
+        match tsig::decode_base64(&self.secret) {
+            Some(s) if !s.is_empty() => Ok(s),
+            _ => Err(format!(
+                "authentication: key {} secret is not base64",
+                self.id
+            )),
+        }
+    }
+}
+
+#[derive(Deserialize, Debug, Clone)]
+pub struct Dhcp6Conf {
+    pub start: Ipv6Addr,
+    pub end: Ipv6Addr,
+    pub lease_file: String,
+    /// Valid lifetime of the addresses; `lease_time` when unset.
+    #[serde(default)]
+    pub lease_time: Option<String>,
+    /// Preferred lifetime, which renewals follow; the valid lifetime when unset.
+    #[serde(default)]
+    pub preferred_lifetime: Option<String>,
+    #[serde(default)]
+    pub dns_servers: Vec<Ipv6Addr>,
+    /// Serve DHCPv4 over DHCPv6 (RFC 7341) too.
+    #[serde(default)]
+    pub dhcp4o6: bool,
+}
+
+#[derive(Deserialize, Debug, Clone)]
+pub struct RaConf {
+    /// The on-link prefix, e.g. `2001:db8:10::/64`.
+    pub prefix: String,
+    /// Let hosts configure their own addresses in `prefix` (SLAAC).
+    #[serde(default = "default_ra_autonomous")]
+    pub autonomous: bool,
+    /// About how often advertisements are sent unsolicited.
+    #[serde(default = "default_ra_interval")]
+    pub interval: String,
+    /// How long hosts use this router as their default one; three intervals when unset, 0s
+    /// for none.
+    #[serde(default)]
+    pub router_lifetime: Option<String>,
+    /// Recursive DNS servers (RFC 8106); those of `dhcp6` when unset.
+    #[serde(default)]
+    pub dns_servers: Option<Vec<Ipv6Addr>>,
+}
+
+impl RaConf {
+    /// `prefix` as address and length.
+    pub fn prefix(&self) -> Result<(Ipv6Addr, u8), String> {
+        let invalid = || format!("router_advertisements: invalid prefix {}", self.prefix);
+        let (addr, len) = self.prefix.split_once('/').ok_or_else(invalid)?;
+        let addr: Ipv6Addr = addr.parse().map_err(|_| invalid())?;
+        let len: u8 = len.parse().map_err(|_| invalid())?;
+        if len > 128 {
+            return Err(invalid());
+        }
+        Ok((addr, len))
+    }
+}
+
+fn default_ra_autonomous() -> bool {
+    true
+}
+
+fn default_ra_interval() -> String {
+    "10m".to_string()
+}
+
+#[derive(Deserialize, Debug, Clone)]
+pub struct LeasequeryConf {
+    #[serde(default = "default_leasequery_listen")]
+    pub listen: SocketAddr,
+    /// Relay agents allowed to connect; anyone when empty.
+    #[serde(default)]
+    pub allow: Vec<Ipv4Addr>,
+}
+
+fn default_leasequery_listen() -> SocketAddr {
+    SocketAddr::from(([0, 0, 0, 0], 67))
+}
+
+#[derive(Deserialize, Debug, Clone)]
+pub struct DockerConf {
+    /// Name or id of the Docker network the served interface belongs to.
+    pub network: String,
+    /// Docker Engine API socket.
+    #[serde(default = "default_docker_socket")]
+    pub socket: String,
+    /// How often the network's containers are read.
+    #[serde(default = "default_docker_interval")]
+    pub interval: String,
+}
+
+fn default_docker_socket() -> String {
+    "/var/run/docker.sock".to_string()
+}
+
+fn default_docker_interval() -> String {
+    "30s".to_string()
+}
+
+#[derive(Deserialize, Debug, Clone)]
+pub struct StatsFile {
+    pub path: String,
+    /// How often the counters are written.
+    #[serde(default = "default_stats_interval")]
+    pub interval: String,
+}
+
+fn default_stats_interval() -> String {
+    "1m".to_string()
+}
+
+fn default_io_batch() -> usize {
+    1
+}
+
+#[derive(Deserialize, Clone)]
+pub struct SnmpConf {
+    #[serde(default = "default_snmp_listen")]
+    pub listen: SocketAddr,
+    pub community: String,
+    /// Where the statistics sit in the OID tree; by default under NET-SNMP's playpen.
+    #[serde(default = "default_snmp_oid")]
+    pub oid: String,
+}
+
+impl fmt::Debug for SnmpConf {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("SnmpConf")
+            .field("listen", &self.listen)
+            .field("oid", &self.oid)
+            .finish_non_exhaustive()
+    }
+}
+
+fn default_snmp_listen() -> SocketAddr {
+    SocketAddr::from(([0, 0, 0, 0], 161))
+}
+
+fn default_snmp_oid() -> String {
+    "1.3.6.1.4.1.8072.9999.9999".to_string()
+}
+
+fn default_metrics_prefix() -> String {
+    "rdhcpd".to_string()
+}
+
+fn default_metrics_interval() -> String {
+    "10s".to_string()
+}
+
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum MetricsProtocol {
+    Statsd,
+    Graphite,
+    /// OTLP/HTTP with a JSON body, posted to `/v1/metrics`.
+    Otlp,
+}
+
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum Allocation {
+    /// The next free address after the last one handed out.
+    #[default]
+    Sequential,
+    /// The first free address from one derived from the client identifier or hardware
+    /// address, so a client tends to get the same address even without the lease file.
+    Hash,
+    /// The first free address from a random one, so that addresses are hard to predict.
+    Random,
+}
+
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum CheckMode {
+    /// Report problems and start anyway.
+    #[default]
+    Warn,
+    /// Refuse to start.
+    Strict,
+    Off,
+}
+
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum LogTarget {
+    #[default]
+    Stdout,
+    Journald,
+}
+
+#[derive(Deserialize, Debug, Clone)]
+pub struct MirrorConf {
+    #[serde(default)]
+    pub protocol: MirrorProtocol,
+    pub addr: SocketAddr,
+}
+
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum MirrorProtocol {
+    #[default]
+    Udp,
+    Tcp,
+}
+
+#[derive(Deserialize, Clone)]
+pub struct MgmtToken {
+    /// Shown in the audit log instead of the token itself.
+    pub name: String,
+    pub token: String,
+    #[serde(default)]
+    pub role: Role,
+}
+
+impl fmt::Debug for MgmtToken {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("MgmtToken")
+            .field("name", &self.name)
+            .field("role", &self.role)
+            .finish_non_exhaustive()
+    }
+}
+
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum Role {
+    /// May only use GET requests.
+    #[default]
+    Read,
+    Admin,
+}
+
+/// Wireless controller discovery for a known vendor, see `profiles.rs`.
+#[derive(Deserialize, Debug, Clone)]
+pub struct VendorProfile {
+    pub profile: ProfileKind,
+    pub controllers: Vec<Ipv4Addr>,
+}
+
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "kebab-case")]
+pub enum ProfileKind {
+    UbiquitiUnifi,
+    Aruba,
+    Ruckus,
+    MikrotikCapsman,
+}
+
+/// Criteria a client has to meet to belong to a class; every given criterion must match.
+#[derive(Deserialize, Debug, Clone, Default)]
+pub struct ClassMatch {
+    /// Matches any of the client's option 77 user class values exactly.
+    #[serde(default)]
+    pub user_class: Option<String>,
+    /// Matches when the option 60 vendor class identifier starts with this text, e.g.
+    /// `PXEClient` or `udhcp`.
+    #[serde(default)]
+    pub vendor_class: Option<String>,
+    /// Matches clients with any of these hardware addresses, e.g. a reserved host.
+    #[serde(default)]
+    pub mac: Vec<String>,
+    /// Matches requests forwarded by any of these relays (giaddr), e.g. one per VLAN.
+    #[serde(default)]
+    pub relay: Vec<Ipv4Addr>,
+    /// Matches when the option 82 circuit id contains this text, e.g. an SSID or port name.
+    #[serde(default)]
+    pub circuit_id: Option<String>,
+    /// Matches PXE clients reporting any of these system architectures (option 93), e.g. 0 for
+    /// BIOS or 7 for UEFI x64.
+    #[serde(default)]
+    pub arch: Vec<u16>,
+}
+
+/// A group of clients that gets its own settings. When several classes match, they are
+/// consulted in configuration order and the first one that sets a value wins.
+#[derive(Deserialize, Debug, Clone)]
+pub struct ClientClass {
+    pub name: String,
+    #[serde(rename = "match", default)]
+    pub matches: ClassMatch,
+    #[serde(default)]
+    pub lease_time: Option<String>,
+    #[serde(default)]
+    pub dns_servers: Option<Vec<Ipv4Addr>>,
+    #[serde(default)]
+    pub captive_portal_url: Option<String>,
+    #[serde(default)]
+    pub capwap_controllers: Option<Vec<Ipv4Addr>>,
+    #[serde(default)]
+    pub next_server: Option<Ipv4Addr>,
+    #[serde(default)]
+    pub tftp_servers: Option<Vec<Ipv4Addr>>,
+    #[serde(default)]
+    pub boot_file: Option<String>,
+    #[serde(default)]
+    pub echo_client_id: Option<bool>,
+    /// Option 43 payload, taking precedence over `vendor_profiles`.
+    #[serde(default)]
+    pub vendor_specific: Option<VendorSpecific>,
+    /// Routes sent on top of the subnet's `routes`; unlike other settings these add up
+    /// across matching classes.
+    #[serde(default)]
+    pub routes: Vec<Route>,
+    /// Raw options taking precedence over the top-level `options`.
+    #[serde(default)]
+    pub options: Vec<SubOption>,
+}
+
+/// A classless static route (option 121), `to: 10.8.0.0/16` `via: 192.168.10.2`.
+#[derive(Deserialize, Debug, Clone)]
+pub struct Route {
+    #[serde(deserialize_with = "deserialize_cidr")]
+    pub to: (Ipv4Addr, u8),
+    pub via: Ipv4Addr,
+}
+
+/// A separately served network behind one or more relays, or one of the `pools`, which are
+/// picked by subnet alone.
+///
+/// Its `conf` is the top-level configuration overlaid with the keys given in the tenant entry,
+/// so a tenant only needs to spell out what differs (pool, options, lease files).
+#[derive(Debug, Clone)]
+pub struct Tenant {
+    pub relays: Vec<Ipv4Addr>,
+    pub remote_ids: Vec<String>,
+    pub circuit_ids: Vec<String>,
+    pub conf: Config,
+}
+
+#[derive(Deserialize)]
+struct TenantSelector {
+    #[serde(default)]
+    relays: Vec<Ipv4Addr>,
+    #[serde(default)]
+    remote_ids: Vec<String>,
+    #[serde(default)]
+    circuit_ids: Vec<String>,
+}
+
+/// Option payload written in one of a few typed forms, e.g. `text: "abc"` or `hex: "01:02"`.
+#[derive(Deserialize, Debug, Clone)]
+#[serde(rename_all = "lowercase")]
+pub enum OptionValue {
+    Text(String),
+    #[serde(deserialize_with = "deserialize_hex")]
+    Hex(Vec<u8>),
+    Ip(Vec<Ipv4Addr>),
+    U8(u8),
+    U16(u16),
+    U32(u32),
+}
+
+impl OptionValue {
+    /// The payload length before placeholders are expanded.
+    pub fn size(&self) -> usize {
+        match self {
+            Self::Text(s) => s.len(),
+            Self::Hex(b) => b.len(),
+            Self::Ip(addrs) => 4 * addrs.len(),
+            Self::U8(_) => 1,
+            Self::U16(_) => 2,
+            Self::U32(_) => 4,
+        }
+    }
+
+    /// The payload for one client; placeholders in text values are expanded.
+    pub fn to_bytes(&self, vars: &template::Vars) -> Vec<u8> {
+        match self {
+            Self::Text(s) => template::expand(s, vars).into_bytes(),
+            Self::Hex(b) => b.clone(),
+            Self::Ip(addrs) => addrs.iter().flat_map(|a| a.octets()).collect(),
+            Self::U8(v) => vec![*v],
+            Self::U16(v) => v.to_be_bytes().to_vec(),
+            Self::U32(v) => v.to_be_bytes().to_vec(),
+        }
+    }
+}
+
+/// An option 43 payload: sub-options encoded as `code, length, value`, or one value as is.
+#[derive(Deserialize, Debug, Clone)]
+#[serde(untagged)]
+pub enum VendorSpecific {
+    Suboptions { suboptions: Vec<SubOption> },
+    Raw(OptionValue),
+}
+
+impl VendorSpecific {
+    pub fn to_bytes(&self, vars: &template::Vars) -> Vec<u8> {
+        match self {
+            Self::Suboptions { suboptions } => suboptions
+                .iter()
+                .flat_map(|o| {
+                    let data = o.value.to_bytes(vars);
+                    if data.len() > 255 {
+                        println!(
+                            "WARN: sub-option {} too long once expanded, left out",
+                            o.code
+                        );
+                        return vec![];
+                    }
+                    let mut tlv = vec![o.code, data.len() as u8];
+                    tlv.extend(data);
+                    tlv
+                })
+                .collect(),
+            Self::Raw(value) => value.to_bytes(vars),
+        }
+    }
+}
+
+#[derive(Deserialize, Debug, Clone)]
+pub struct SubOption {
+    pub code: u8,
+    #[serde(flatten)]
+    pub value: OptionValue,
+}
+
+/// Option 125 payload returned to clients identifying with `enterprise` in option 124/125.
+#[derive(Deserialize, Debug, Clone)]
+pub struct VendorOption {
+    pub enterprise: u32,
+    pub suboptions: Vec<SubOption>,
+}
+
+fn deserialize_hex<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Vec<u8>, D::Error> {
+    let s = String::deserialize(deserializer)?;
+    utils::parse_hex(&s).ok_or_else(|| serde::de::Error::custom(format!("invalid hex: {}", s)))
+}
+
+fn deserialize_cidr<'de, D: Deserializer<'de>>(
+    deserializer: D,
+) -> Result<(Ipv4Addr, u8), D::Error> {
+    let s = String::deserialize(deserializer)?;
+    let invalid = || serde::de::Error::custom(format!("invalid prefix: {}", s));
+    let (addr, len) = s.split_once('/').ok_or_else(invalid)?;
+    let addr: Ipv4Addr = addr.parse().map_err(|_| invalid())?;
+    let len: u8 = len.parse().map_err(|_| invalid())?;
+    if len > 32 {
+        return Err(invalid());
+    }
+    let mask = u32::MAX.checked_shl(32 - len as u32).unwrap_or(0);
+    Ok((Ipv4Addr::from(u32::from(addr) & mask), len))
+}
+
+fn deserialize_ranges<'de, D: Deserializer<'de>>(
+    deserializer: D,
+) -> Result<Vec<(Ipv4Addr, Ipv4Addr)>, D::Error> {
+    Vec::<String>::deserialize(deserializer)?
+        .iter()
+        .map(|s| match s.parse::<Ipv4Addr>() {
+            Ok(ip) => Ok((ip, ip)),
+            Err(_) => args::parse_range(s).map_err(|e| format!("invalid range {}: {}", s, e)),
+        })
+        .collect::<Result<_, _>>()
+        .map_err(serde::de::Error::custom)
+}
+
+/// Instances in one process must not share an interface or a lease file.
+pub fn check_instances(confs: &[Config]) -> Result<(), Box<dyn Error>> {
+    if confs.len() < 2 {
+        return Ok(());
+    }
+    let mut intfs = vec![];
+    let mut lease_files = vec![];
+    for conf in confs {
+        if intfs.contains(&&conf.intf) {
+            return Err(format!("intf {} is used by two instances", conf.intf).into());
+        }
+        intfs.push(&conf.intf);
+        let files = std::iter::once(&conf.lease_file)
+            .chain(conf.tenants.iter().map(|t| &t.conf.lease_file));
+        for f in files {
+            if lease_files.contains(&f) {
+                return Err(format!("lease_file {} is used by two instances", f).into());
+            }
+            lease_files.push(f);
+        }
+    }
+    Ok(())
+}
+
+/// Reads the configuration file with `overrides` (command line settings) applied on top.
+///
+/// Without a file the configuration is built from the overrides alone, completed from the
+/// address of the given interface.
+pub fn load<P: AsRef<Path>>(
+    path: Option<P>,
+    overrides: &Mapping,
+) -> Result<Config, Box<dyn Error>> {
+    let mut value = match &path {
+        Some(path) => serde_yaml::from_reader(BufReader::new(File::open(path)?))?,
+        None => Value::Mapping(Mapping::new()),
+    };
+    let Value::Mapping(m) = &mut value else {
+        return Err("configuration must be a mapping".into());
+    };
+    m.extend(overrides.clone());
+    if path.is_none() {
+        interface_defaults(m)?;
+    }
+    from_value(value)
+}
+
+/// Settings from `RDHCPD_<KEY>` environment variables, each value read as YAML.
+pub fn env_overrides() -> Result<Mapping, Box<dyn Error>> {
+    let mut m = Mapping::new();
+    for (k, v) in std::env::vars_os() {
+        // variables of other programs need not be UTF-8
+        if !k.as_encoded_bytes().starts_with(b"RDHCPD_") {
+            continue;
+        }
+        let (Some(k), Some(v)) = (k.to_str(), v.to_str()) else {
+            return Err(format!("{}: not valid UTF-8", k.to_string_lossy()).into());
+        };
+        let key = &k["RDHCPD_".len()..];
+        let value: Value = serde_yaml::from_str(v).map_err(|e| format!("{}: {}", k, e))?;
+        m.insert(key.to_lowercase().into(), value);
+    }
+    Ok(m)
+}
+
+/// Fills what an ad-hoc configuration left out from the address of its interface.
+fn interface_defaults(m: &mut Mapping) -> Result<(), Box<dyn Error>> {
+    let intf = m
+        .get("intf")
+        .and_then(|v| v.as_str())
+        .ok_or("an interface is needed without a configuration file")?
+        .to_string();
+    let (addr, mask) = utils::interface_addrs(&intf)?
+        .into_iter()
+        .next()
+        .ok_or_else(|| format!("interface {} has no IPv4 address", intf))?;
+    let broadcast = Ipv4Addr::from(u32::from(addr) | !u32::from(mask));
+    let defaults = [
+        ("listen_addr", Value::from(addr.to_string())),
+        ("netmask", Value::from(mask.to_string())),
+        ("broadcast", Value::from(broadcast.to_string())),
+        ("gateway", Value::from(addr.to_string())),
+        (
+            "dns_servers",
+            Value::Sequence(vec![Value::from(addr.to_string())]),
+        ),
+        ("lease_time", Value::from("12h")),
+        ("lease_file", Value::from(format!("rdhcpd-{}.leases", intf))),
+        (
+            "lease_static",
+            Value::from(format!("rdhcpd-{}.static", intf)),
+        ),
+    ];
+    for (k, v) in defaults {
+        if !m.contains_key(k) {
+            m.insert(Value::from(k), v);
+        }
+    }
+    Ok(())
 }
 
-pub fn read_config<P: AsRef<Path>>(path: P) -> Result<Config, Box<dyn Error>> {
-    let file = File::open(path)?;
-    let reader = BufReader::new(file);
-    let c = serde_yaml::from_reader(reader)?;
+/// Keys served once per instance, from the top-level configuration; a tenant or pool setting
+/// its own would be ignored.
+const INSTANCE_KEYS: &[&str] = &[
+    "intf",
+    "listen_addr",
+    "ddns",
+    "authentication",
+    "mgmt_listen",
+    "mgmt_tokens",
+    "leasequery",
+    "dhcp6",
+    "router_advertisements",
+    "mirror",
+    "docker",
+    "stats_file",
+    "metrics_push",
+    "snmp",
+    "io_batch",
+    "socket_filter",
+    "log_target",
+    "dump_file",
+    "startup_checks",
+    "tenants",
+    "pools",
+];
+
+/// Keys describing the top-level pool itself, which pool entries do not inherit.
+const POOL_KEYS: &[&str] = &["ranges", "exclude"];
+
+fn check_entry(kind: &str, entry: &Mapping) -> Result<(), Box<dyn Error>> {
+    match INSTANCE_KEYS.iter().find(|k| entry.contains_key(**k)) {
+        Some(k) => Err(format!("{} entries cannot set {}, only the top level can", kind, k).into()),
+        None => Ok(()),
+    }
+}
+
+pub fn from_value(mut value: Value) -> Result<Config, Box<dyn Error>> {
+    let tenants = value.as_mapping_mut().and_then(|m| m.remove("tenants"));
+    let pools = value.as_mapping_mut().and_then(|m| m.remove("pools"));
+    let mut c: Config = serde_yaml::from_value(value.clone())?;
+    c.check_settings()?;
+    if let Some(tenants) = tenants {
+        let Value::Sequence(tenants) = tenants else {
+            return Err("tenants must be a list".into());
+        };
+        for t in tenants {
+            let (Value::Mapping(tm), Value::Mapping(mut merged)) = (t, value.clone()) else {
+                return Err("tenant entries must be mappings".into());
+            };
+            check_entry("tenant", &tm)?;
+            merged.extend(tm);
+            let merged = Value::Mapping(merged);
+            let sel: TenantSelector = serde_yaml::from_value(merged.clone())?;
+            if sel.relays.is_empty() && sel.remote_ids.is_empty() && sel.circuit_ids.is_empty() {
+                return Err(
+                    "tenant needs at least one of relays, remote_ids or circuit_ids".into(),
+                );
+            }
+            let conf: Config = serde_yaml::from_value(merged)?;
+            conf.check_settings()?;
+            if conf.lease_file == c.lease_file
+                || c.tenants
+                    .iter()
+                    .any(|o| o.conf.lease_file == conf.lease_file)
+            {
+                return Err(format!("tenant lease_file {} is not unique", conf.lease_file).into());
+            }
+            c.tenants.push(Tenant {
+                relays: sel.relays,
+                remote_ids: sel.remote_ids,
+                circuit_ids: sel.circuit_ids,
+                conf,
+            });
+        }
+    }
+    if let Some(pools) = pools {
+        let Value::Sequence(pools) = pools else {
+            return Err("pools must be a list".into());
+        };
+        for p in pools {
+            let (Value::Mapping(mut pm), Value::Mapping(mut merged)) = (p, value.clone()) else {
+                return Err("pool entries must be mappings".into());
+            };
+            check_entry("pool", &pm)?;
+            if !pm.contains_key("lease_file") {
+                // kept next to the top-level lease file
+                let start = pm
+                    .get("start")
+                    .and_then(|v| v.as_str())
+                    .ok_or("pool needs a start")?;
+                let file = format!("{}.{}", c.lease_file, start);
+                pm.insert(Value::from("lease_file"), Value::from(file));
+            }
+            let own_broadcast = pm.contains_key("broadcast");
+            for k in POOL_KEYS {
+                merged.remove(*k);
+            }
+            merged.extend(pm);
+            let mut conf: Config = serde_yaml::from_value(Value::Mapping(merged))?;
+            if !own_broadcast {
+                conf.broadcast = Ipv4Addr::from(u32::from(conf.start) | !u32::from(conf.netmask));
+            }
+            conf.check_settings()?;
+            let others = std::iter::once(&c).chain(c.tenants.iter().map(|t| &t.conf));
+            for o in others {
+                if o.subnet_contains(conf.start) || conf.subnet_contains(o.start) {
+                    return Err(format!(
+                        "pool {}-{} overlaps the subnet of {}-{}",
+                        conf.start, conf.end, o.start, o.end
+                    )
+                    .into());
+                }
+                if o.lease_file == conf.lease_file {
+                    return Err(format!("pool lease_file {} is not unique", conf.lease_file).into());
+                }
+            }
+            c.tenants.push(Tenant {
+                relays: vec![],
+                remote_ids: vec![],
+                circuit_ids: vec![],
+                conf,
+            });
+        }
+    }
     Ok(c)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const BASE: &str = "
+intf: lo
+listen_addr: 192.168.10.1
+start: 192.168.10.100
+end: 192.168.10.200
+netmask: 255.255.255.0
+gateway: 192.168.10.1
+broadcast: 192.168.10.255
+dns_servers: [192.168.10.1]
+lease_static: /tmp/rdhcpd-test.static
+lease_file: /tmp/rdhcpd-test.leases
+lease_time: 1h
+";
+
+    fn parse(extra: &str) -> Result<Config, Box<dyn Error>> {
+        from_value(serde_yaml::from_str(&format!("{}{}", BASE, extra)).unwrap())
+    }
+
+    #[test]
+    fn pools_do_not_inherit_ranges() {
+        let c = parse(
+            "ranges: [192.168.10.210-192.168.10.229]
+exclude: [192.168.10.150]
+pools:
+  - start: 10.2.0.100
+    end: 10.2.0.200
+    netmask: 255.255.255.0
+    gateway: 10.2.0.1
+",
+        )
+        .unwrap();
+        assert_eq!(c.ranges.len(), 1);
+        assert_eq!(c.exclude.len(), 1);
+        let pool = &c.tenants[0].conf;
+        assert!(pool.ranges.is_empty() && pool.exclude.is_empty());
+        assert_eq!(pool.broadcast, Ipv4Addr::new(10, 2, 0, 255));
+        assert_eq!(pool.lease_file, "/tmp/rdhcpd-test.leases.10.2.0.100");
+    }
+
+    #[test]
+    fn search_domains_may_exceed_one_option() {
+        // 52 bytes per name, nothing to compress
+        let names = |n: u8| {
+            let names: Vec<String> = (b'a'..b'a' + n)
+                .map(|c| (c as char).to_string().repeat(50))
+                .collect();
+            format!("search_domains: [{}]\n", names.join(", "))
+        };
+        assert_eq!(parse(&names(5)).unwrap().search_domains.len(), 5);
+        assert!(parse(&names(6)).is_err());
+    }
+}
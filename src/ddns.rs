@@ -0,0 +1,348 @@
+//! Dynamic DNS updates (RFC 2136) of the leases' A records and, with a `reverse_zone`, their PTR
+//! records.
+//!
+//! Updates are signed with a TSIG key, or with GSS-TSIG through BIND's `nsupdate` for Active
+//! Directory zones.
+//!
+//! Changes are queued to a background thread per instance, which sends them to the zone's primary
+//! server in order. An update the server does not answer, or answers with a transient error, stays
+//! at the head of the queue and is sent again after `retry`.
+
+use crate::config::{DdnsConf, GssConf};
+use crate::options;
+use crate::tsig;
+use crate::zone::Host;
+
+use std::cell::RefCell;
+use std::collections::VecDeque;
+use std::fmt;
+use std::io::{self, Write};
+use std::net::{Ipv4Addr, UdpSocket};
+use std::process::{Command, Stdio};
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError, Sender};
+use std::thread;
+use std::time::Duration;
+
+/// Updates kept while the server is unreachable; older ones are dropped first.
+const MAX_QUEUED: usize = 1024;
+
+const OPCODE_UPDATE: u16 = 5;
+const TYPE_A: u16 = 1;
+const TYPE_SOA: u16 = 6;
+const TYPE_PTR: u16 = 12;
+const CLASS_IN: u16 = 1;
+const CLASS_NONE: u16 = 254;
+const CLASS_ANY: u16 = 255;
+
+const RCODE_FORMERR: u16 = 1;
+const RCODE_REFUSED: u16 = 5;
+const RCODE_NOTAUTH: u16 = 9;
+const RCODE_NOTZONE: u16 = 10;
+
+/// A record to publish or withdraw, by its label in the zone.
+#[derive(Debug, Clone)]
+pub enum Change {
+    Add(Host),
+    Remove(Host),
+}
+
+impl Change {
+    fn host(&self) -> &Host {
+        match self {
+            Change::Add(host) | Change::Remove(host) => host,
+        }
+    }
+}
+
+/// A change as applied to the forward or to the reverse zone, one UPDATE message each.
+#[derive(Debug)]
+struct Update {
+    change: Change,
+    reverse: bool,
+}
+
+impl fmt::Display for Update {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let verb = match self.change {
+            Change::Add(_) => "add",
+            Change::Remove(_) => "remove",
+        };
+        let (name, ip) = self.change.host();
+        if self.reverse {
+            write!(f, "{} {} PTR {}", verb, ip, name)
+        } else {
+            write!(f, "{} {} A {}", verb, name, ip)
+        }
+    }
+}
+
+thread_local! {
+    /// The update queue of the instance served by this thread, see `instance.rs`.
+    static QUEUE: RefCell<Option<Sender<Change>>> = const { RefCell::new(None) };
+}
+
+/// Starts the update thread for the calling thread's instance.
+pub fn spawn(conf: DdnsConf) {
+    let (tx, rx) = mpsc::channel();
+    QUEUE.with(|q| *q.borrow_mut() = Some(tx));
+    thread::spawn(move || run(conf, rx));
+}
+
+/// Queues `change`; a no-op unless `ddns` is configured.
+pub fn update(change: Change) {
+    QUEUE.with(|q| {
+        if let Some(tx) = &*q.borrow() {
+            let _ = tx.send(change);
+        }
+    });
+}
+
+fn run(conf: DdnsConf, rx: Receiver<Change>) {
+    let retry = duration_str::parse(conf.retry.as_str()).unwrap();
+    let key = conf.key.as_ref().map(|k| k.key().unwrap());
+    let mut queue: VecDeque<Update> = VecDeque::new();
+    let enqueue = |queue: &mut VecDeque<Update>, change: Change| {
+        queue.push_back(Update {
+            change: change.clone(),
+            reverse: false,
+        });
+        if conf.reverse_zone.is_some() {
+            queue.push_back(Update {
+                change,
+                reverse: true,
+            });
+        }
+    };
+    let mut id: u16 = 0;
+    loop {
+        let next = if queue.is_empty() {
+            rx.recv().map_err(|_| RecvTimeoutError::Disconnected)
+        } else {
+            rx.recv_timeout(retry)
+        };
+        match next {
+            Ok(change) => enqueue(&mut queue, change),
+            Err(RecvTimeoutError::Timeout) => {}
+            Err(RecvTimeoutError::Disconnected) => return,
+        }
+        for change in rx.try_iter() {
+            enqueue(&mut queue, change);
+        }
+        while queue.len() > MAX_QUEUED {
+            let dropped = queue.pop_front().unwrap();
+            println!("WARN: ddns: queue full, dropped {}", dropped);
+        }
+        while let Some(update) = queue.front() {
+            id = id.wrapping_add(1);
+            let sent = match &conf.gss {
+                Some(gss) => send_gss(&conf, gss, update),
+                None => send(&conf, key.as_ref(), id, update),
+            };
+            match sent {
+                Ok(()) => {
+                    println!("ddns: {} done", update);
+                    queue.pop_front();
+                }
+                Err(Failure::Rejected(e)) => {
+                    println!("ERROR: ddns: {} rejected: {}", update, e);
+                    queue.pop_front();
+                }
+                Err(Failure::Retry(e)) => {
+                    println!(
+                        "WARN: ddns: {} failed, {} queued, retry in {:?}: {}",
+                        update,
+                        queue.len(),
+                        retry,
+                        e
+                    );
+                    break;
+                }
+            }
+        }
+    }
+}
+
+enum Failure {
+    /// The server refused the update; sending it again will not help.
+    Rejected(String),
+    Retry(String),
+}
+
+impl From<io::Error> for Failure {
+    fn from(e: io::Error) -> Self {
+        match e.kind() {
+            io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut => {
+                Failure::Retry("no answer".to_string())
+            }
+            _ => Failure::Retry(e.to_string()),
+        }
+    }
+}
+
+/// Sends one update and waits for its answer; with a key, answers not signed with it are
+/// dropped.
+fn send(conf: &DdnsConf, key: Option<&tsig::Key>, id: u16, update: &Update) -> Result<(), Failure> {
+    let mut msg = message(conf, id, update);
+    let request_mac = key.map(|key| key.sign(&mut msg));
+    let socket = UdpSocket::bind(("0.0.0.0", 0))?;
+    socket.connect(conf.server)?;
+    socket.set_read_timeout(Some(Duration::from_secs(5)))?;
+    socket.send(&msg)?;
+    let mut buf = [0u8; 512];
+    let mut dropped = None;
+    loop {
+        let n = match socket.recv(&mut buf) {
+            Ok(n) => n,
+            Err(e) => return Err(dropped.map_or_else(|| e.into(), Failure::Retry)),
+        };
+        if n < 12 || buf[..2] != id.to_be_bytes() || buf[2] & 0x80 == 0 {
+            // not the answer to this update
+            continue;
+        }
+        if let (Some(key), Some(mac)) = (key, &request_mac) {
+            if let Err(e) = key.verify(&buf[..n], mac) {
+                dropped = Some(e);
+                continue;
+            }
+        }
+        return match u16::from_be_bytes([buf[2], buf[3]]) & 0x0f {
+            0 => Ok(()),
+            rcode @ (RCODE_FORMERR | RCODE_REFUSED | RCODE_NOTAUTH | RCODE_NOTZONE) => {
+                Err(Failure::Rejected(format!("rcode {}", rcode)))
+            }
+            rcode => Err(Failure::Retry(format!("rcode {}", rcode))),
+        };
+    }
+}
+
+/// Sends one update through `nsupdate -g`, which signs it with GSS-TSIG, getting a Kerberos
+/// ticket for the principal from the keytab first if the cached one ran out.
+fn send_gss(conf: &DdnsConf, gss: &GssConf, update: &Update) -> Result<(), Failure> {
+    let ccache = format!("FILE:{}", gss.ccache);
+    let cached = Command::new("klist")
+        .arg("-s")
+        .env("KRB5CCNAME", &ccache)
+        .status()
+        .is_ok_and(|s| s.success());
+    if !cached {
+        let out = Command::new("kinit")
+            .args(["-k", "-t", &gss.keytab, &gss.principal])
+            .env("KRB5CCNAME", &ccache)
+            .output()?;
+        if !out.status.success() {
+            let err = String::from_utf8_lossy(&out.stderr);
+            return Err(Failure::Retry(format!("kinit: {}", err.trim())));
+        }
+    }
+    let (zone, owner, rdata) = record_of(conf, update);
+    let (rtype, rdata) = match rdata {
+        Rdata::A(ip) => ("A", ip.to_string()),
+        Rdata::Ptr(name) => ("PTR", format!("{}.", name)),
+    };
+    let server = match &gss.server_name {
+        Some(name) => format!("{} {}", name, conf.server.port()),
+        None => format!("{} {}", conf.server.ip(), conf.server.port()),
+    };
+    let mut script = format!("server {}\nzone {}.\n", server, zone);
+    match update.change {
+        Change::Add(_) => script.push_str(&format!(
+            "update delete {owner}. {rtype}\nupdate add {owner}. {ttl} {rtype} {rdata}\n",
+            owner = owner,
+            rtype = rtype,
+            ttl = conf.ttl,
+            rdata = rdata
+        )),
+        Change::Remove(_) => {
+            script.push_str(&format!("update delete {}. {} {}\n", owner, rtype, rdata))
+        }
+    }
+    script.push_str("send\n");
+    let mut child = Command::new("nsupdate")
+        .arg("-g")
+        .env("KRB5CCNAME", &ccache)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::null())
+        .stderr(Stdio::piped())
+        .spawn()?;
+    child.stdin.take().unwrap().write_all(script.as_bytes())?;
+    let out = child.wait_with_output()?;
+    if out.status.success() {
+        return Ok(());
+    }
+    let err = String::from_utf8_lossy(&out.stderr).trim().to_string();
+    if ["FORMERR", "REFUSED", "NOTAUTH", "NOTZONE"]
+        .iter()
+        .any(|r| err.contains(r))
+    {
+        Err(Failure::Rejected(err))
+    } else {
+        Err(Failure::Retry(err))
+    }
+}
+
+/// The data of the record an update is about.
+enum Rdata {
+    A(Ipv4Addr),
+    /// The full name of the lease.
+    Ptr(String),
+}
+
+/// The zone `update` goes to, the owner of its record and the record's data.
+fn record_of(conf: &DdnsConf, update: &Update) -> (String, String, Rdata) {
+    let zone = conf.zone.trim_end_matches('.');
+    let (name, ip) = update.change.host();
+    let fqdn = format!("{}.{}", name, zone);
+    match (update.reverse, &conf.reverse_zone) {
+        (true, Some(reverse)) => {
+            let o = ip.octets();
+            let owner = format!("{}.{}.{}.{}.in-addr.arpa", o[3], o[2], o[1], o[0]);
+            (
+                reverse.trim_end_matches('.').to_string(),
+                owner,
+                Rdata::Ptr(fqdn),
+            )
+        }
+        _ => (zone.to_string(), fqdn, Rdata::A(*ip)),
+    }
+}
+
+/// The UPDATE message for `update`. Adding replaces whatever records the name had; removing
+/// deletes only the record of the lease, in case the name or the address already moved on.
+fn message(conf: &DdnsConf, id: u16, update: &Update) -> Vec<u8> {
+    let (zone, owner, rdata) = record_of(conf, update);
+    let (rtype, rdata) = match rdata {
+        Rdata::A(ip) => (TYPE_A, ip.octets().to_vec()),
+        Rdata::Ptr(name) => (TYPE_PTR, options::encode_domain_list(&[name])),
+    };
+    let updates = match update.change {
+        Change::Add(_) => 2u16,
+        Change::Remove(_) => 1,
+    };
+    let mut m = vec![];
+    m.extend(id.to_be_bytes());
+    m.extend((OPCODE_UPDATE << 11).to_be_bytes());
+    // one zone, no prerequisites, the updates, no additional records
+    for count in [1, 0, updates, 0u16] {
+        m.extend(count.to_be_bytes());
+    }
+    m.extend(options::encode_domain_list(&[zone]));
+    m.extend(TYPE_SOA.to_be_bytes());
+    m.extend(CLASS_IN.to_be_bytes());
+    match update.change {
+        Change::Add(_) => {
+            record(&mut m, &owner, rtype, CLASS_ANY, 0, &[]);
+            record(&mut m, &owner, rtype, CLASS_IN, conf.ttl, &rdata);
+        }
+        Change::Remove(_) => record(&mut m, &owner, rtype, CLASS_NONE, 0, &rdata),
+    }
+    m
+}
+
+fn record(m: &mut Vec<u8>, owner: &str, rtype: u16, class: u16, ttl: u32, rdata: &[u8]) {
+    m.extend(options::encode_domain_list(&[owner.to_string()]));
+    m.extend(rtype.to_be_bytes());
+    m.extend(class.to_be_bytes());
+    m.extend(ttl.to_be_bytes());
+    m.extend((rdata.len() as u16).to_be_bytes());
+    m.extend(rdata);
+}
@@ -0,0 +1,468 @@
+//! DHCPv6 (RFC 8415): addresses from a pool of their own (IA_NA) bound to the client's DUID and
+//! IAID, and DNS settings, so one daemon serves both families of a dual-stack network.
+//!
+//! The socket on port 547 is served by the instance's serve loop next to the DHCPv4 one; relayed
+//! messages are answered through their relay agents. Leases are kept in a lease file of their own.
+//!
+//! With `dhcp4o6`, DHCPv4 messages carried over DHCPv6 (RFC 7341) from clients on IPv6-only
+//! access networks are handed to the DHCPv4 server and its replies sent back the same way.
+
+use crate::config::{Config, Dhcp6Conf};
+use crate::hwaddr::HwAddr;
+use crate::mirror;
+use crate::options;
+use crate::utils;
+
+use serde::{Deserialize, Serialize};
+
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::fs::{self, File};
+use std::io::{self, BufWriter};
+use std::net::{Ipv6Addr, UdpSocket};
+
+pub const PORT: u16 = 547;
+const ALL_SERVERS: Ipv6Addr = Ipv6Addr::new(0xff02, 0, 0, 0, 0, 0, 1, 2);
+
+const SOLICIT: u8 = 1;
+const ADVERTISE: u8 = 2;
+const REQUEST: u8 = 3;
+const CONFIRM: u8 = 4;
+const RENEW: u8 = 5;
+const REBIND: u8 = 6;
+const REPLY: u8 = 7;
+const RELEASE: u8 = 8;
+const DECLINE: u8 = 9;
+const INFORMATION_REQUEST: u8 = 11;
+const RELAY_FORW: u8 = 12;
+const RELAY_REPL: u8 = 13;
+const DHCPV4_QUERY: u8 = 20;
+const DHCPV4_RESPONSE: u8 = 21;
+
+const OPTION_CLIENTID: u16 = 1;
+const OPTION_SERVERID: u16 = 2;
+const OPTION_IA_NA: u16 = 3;
+const OPTION_IAADDR: u16 = 5;
+const OPTION_ORO: u16 = 6;
+const OPTION_RELAY_MSG: u16 = 9;
+const OPTION_STATUS_CODE: u16 = 13;
+const OPTION_RAPID_COMMIT: u16 = 14;
+const OPTION_INTERFACE_ID: u16 = 18;
+const OPTION_DNS_SERVERS: u16 = 23;
+const OPTION_DOMAIN_LIST: u16 = 24;
+const OPTION_DHCPV4_MSG: u16 = 87;
+const OPTION_DHCP4_O_DHCP6_SERVER: u16 = 88;
+
+const STATUS_SUCCESS: u16 = 0;
+const STATUS_NO_ADDRS_AVAIL: u16 = 2;
+const STATUS_NO_BINDING: u16 = 3;
+const STATUS_NOT_ON_LINK: u16 = 4;
+
+type Options = Vec<(u16, Vec<u8>)>;
+
+#[derive(Deserialize, Serialize, Debug, Clone)]
+struct Lease {
+    /// Client DUID in hex; empty for an address a client declined.
+    duid: String,
+    iaid: u32,
+    expiry: u128,
+}
+
+struct State {
+    socket: UdpSocket,
+    conf: Dhcp6Conf,
+    duid: Vec<u8>,
+    /// Valid and preferred lifetime (s).
+    valid: u32,
+    preferred: u32,
+    rapid_commit: bool,
+    search_domains: Vec<String>,
+    leases: HashMap<Ipv6Addr, Lease>,
+}
+
+thread_local! {
+    /// The DHCPv6 service of the instance served by this thread, see `instance.rs`.
+    static STATE: RefCell<Option<State>> = const { RefCell::new(None) };
+}
+
+/// Listens on port 547 for the calling thread's instance, with the leases of `dhcp6.lease_file`.
+pub fn spawn(conf: &Config, dhcp6: Dhcp6Conf) -> io::Result<()> {
+    let socket = UdpSocket::bind((Ipv6Addr::UNSPECIFIED, PORT))?;
+    socket.set_nonblocking(true)?;
+    match utils::interface_index(&conf.intf) {
+        Some(index) => {
+            if let Err(e) = socket.join_multicast_v6(&ALL_SERVERS, index) {
+                println!(
+                    "WARN: dhcp6: cannot join {} on {}: {:?}",
+                    ALL_SERVERS, conf.intf, e
+                );
+            }
+        }
+        None => println!(
+            "WARN: dhcp6: no interface {}, only unicast is served",
+            conf.intf
+        ),
+    }
+    let lifetime = |d: &Option<String>, or: &str| {
+        let d = d.as_deref().unwrap_or(or);
+        duration_str::parse(d)
+            .unwrap()
+            .as_secs()
+            .min(u32::MAX as u64) as u32
+    };
+    let valid = lifetime(&dhcp6.lease_time, &conf.lease_time);
+    let preferred = lifetime(&dhcp6.preferred_lifetime, &conf.lease_time).min(valid);
+    let leases = fs::read_to_string(&dhcp6.lease_file)
+        .ok()
+        .and_then(|s| serde_json::from_str(&s).ok())
+        .unwrap_or_default();
+    let state = State {
+        socket,
+        duid: server_duid(conf),
+        valid,
+        preferred,
+        rapid_commit: conf.rapid_commit,
+        search_domains: conf.search_domains.clone(),
+        leases,
+        conf: dhcp6,
+    };
+    println!(
+        "dhcp6 listening on port {}, pool {}-{}",
+        PORT, state.conf.start, state.conf.end
+    );
+    STATE.with(|s| *s.borrow_mut() = Some(state));
+    Ok(())
+}
+
+/// The addresses bound to the host holding the DHCPv4 lease of `mac` and `client_id` (hex),
+/// matched by the hardware address in its DUID-LLT or DUID-LL (RFC 8415 11.2, 11.4) or by the
+/// DUID in an RFC 4361 client identifier.
+pub fn host_addresses(mac: &HwAddr, client_id: Option<&str>) -> Vec<Ipv6Addr> {
+    let now = utils::now_timestamp_ms();
+    let client_id = client_id.and_then(utils::parse_hex);
+    STATE.with(|s| {
+        let Some(state) = &*s.borrow() else {
+            return vec![];
+        };
+        let mut addrs: Vec<Ipv6Addr> = state
+            .leases
+            .iter()
+            .filter(|(_, l)| l.expiry >= now)
+            .filter(|(_, l)| {
+                utils::parse_hex(&l.duid).is_some_and(|d| same_host(&d, mac, client_id.as_deref()))
+            })
+            .map(|(a, _)| *a)
+            .collect();
+        addrs.sort();
+        addrs
+    })
+}
+
+fn same_host(duid: &[u8], mac: &HwAddr, client_id: Option<&[u8]>) -> bool {
+    if duid.is_empty() {
+        return false;
+    }
+    // type 255, the IAID, then the DUID
+    if let Some([255, _, _, _, _, d @ ..]) = client_id {
+        return d == duid;
+    }
+    match duid {
+        [0, 1, t0, t1, _, _, _, _, ll @ ..] | [0, 3, t0, t1, ll @ ..] => {
+            u16::from_be_bytes([*t0, *t1]) == mac.htype as u16 && ll == mac.as_bytes()
+        }
+        _ => false,
+    }
+}
+
+/// Answers the messages that arrived since the last call; `dhcp4` handles the DHCPv4 messages
+/// among them and returns the encoded replies.
+pub fn poll(mut dhcp4: impl FnMut(&[u8]) -> Vec<Vec<u8>>) {
+    STATE.with(|s| {
+        let Some(state) = &mut *s.borrow_mut() else {
+            return;
+        };
+        let mut buf = [0u8; 1500];
+        while let Ok((n, src)) = state.socket.recv_from(&mut buf) {
+            mirror::packet("in", src, &buf[..n]);
+            let Some(reply) = state.answer(&buf[..n], &mut dhcp4) else {
+                continue;
+            };
+            mirror::packet("out", src, &reply);
+            if let Err(e) = state.socket.send_to(&reply, src) {
+                println!("ERROR: dhcp6 reply to {}: {:?}", src, e);
+            }
+        }
+    });
+}
+
+/// DUID-LL (RFC 8415 11.4) of the interface, or of a locally administered address made from
+/// `listen_addr` when it has none.
+fn server_duid(conf: &Config) -> Vec<u8> {
+    let mac = utils::interface_mac(&conf.intf)
+        .filter(|mac| mac.iter().any(|b| *b != 0))
+        .unwrap_or_else(|| {
+            let o = conf.listen_addr.octets();
+            vec![0x02, 0, o[0], o[1], o[2], o[3]]
+        });
+    let mut duid = vec![0, 3, 0, 1];
+    duid.extend(mac);
+    duid
+}
+
+impl State {
+    /// The reply to `data`, wrapped again for the relay agents it came through.
+    fn answer(
+        &mut self,
+        data: &[u8],
+        dhcp4: &mut dyn FnMut(&[u8]) -> Vec<Vec<u8>>,
+    ) -> Option<Vec<u8>> {
+        if *data.first()? == RELAY_FORW {
+            // hop count, link address and peer address go back as received
+            let head = data.get(..34)?;
+            let opts = parse_options(&data[34..])?;
+            let inner = self.answer(option(&opts, OPTION_RELAY_MSG)?, dhcp4)?;
+            let mut out = vec![RELAY_REPL];
+            out.extend(&head[1..]);
+            if let Some(id) = option(&opts, OPTION_INTERFACE_ID) {
+                put(&mut out, OPTION_INTERFACE_ID, id);
+            }
+            put(&mut out, OPTION_RELAY_MSG, &inner);
+            return Some(out);
+        }
+        let opts = parse_options(data.get(4..)?)?;
+        if data[0] == DHCPV4_QUERY {
+            if !self.conf.dhcp4o6 {
+                return None;
+            }
+            let reply = dhcp4(option(&opts, OPTION_DHCPV4_MSG)?)
+                .into_iter()
+                .next()?;
+            // RFC 7341 6.2: no flags are defined for responses
+            let mut out = vec![DHCPV4_RESPONSE, 0, 0, 0];
+            put(&mut out, OPTION_DHCPV4_MSG, &reply);
+            return Some(out);
+        }
+        self.reply(data[0], &data[1..4], &opts)
+    }
+
+    fn reply(&mut self, kind: u8, xid: &[u8], opts: &Options) -> Option<Vec<u8>> {
+        let client = option(opts, OPTION_CLIENTID);
+        let server = option(opts, OPTION_SERVERID);
+        let ours = server == Some(&self.duid[..]);
+        // RFC 8415 16: which messages must, and must not, name this server
+        let valid = match kind {
+            SOLICIT | CONFIRM | REBIND => client.is_some() && server.is_none(),
+            REQUEST | RENEW | RELEASE | DECLINE => client.is_some() && ours,
+            INFORMATION_REQUEST => server.is_none() || ours,
+            _ => false,
+        };
+        if !valid {
+            return None;
+        }
+        let duid: String = client
+            .unwrap_or_default()
+            .iter()
+            .map(|b| format!("{:02x}", b))
+            .collect();
+        let rapid =
+            kind == SOLICIT && self.rapid_commit && option(opts, OPTION_RAPID_COMMIT).is_some();
+        let mut out = vec![if kind == SOLICIT && !rapid {
+            ADVERTISE
+        } else {
+            REPLY
+        }];
+        out.extend(xid);
+        if let Some(c) = client {
+            put(&mut out, OPTION_CLIENTID, c);
+        }
+        put(&mut out, OPTION_SERVERID, &self.duid);
+        if rapid {
+            put(&mut out, OPTION_RAPID_COMMIT, &[]);
+        }
+        let ias = opts.iter().filter(|(c, _)| *c == OPTION_IA_NA);
+        match kind {
+            CONFIRM => {
+                let addrs: Vec<Ipv6Addr> = ias.flat_map(|(_, ia)| ia_addrs(ia)).collect();
+                if addrs.is_empty() {
+                    return None;
+                }
+                if addrs.iter().all(|a| self.in_pool(a)) {
+                    status(&mut out, STATUS_SUCCESS, "addresses on link");
+                } else {
+                    status(&mut out, STATUS_NOT_ON_LINK, "addresses not on link");
+                }
+            }
+            INFORMATION_REQUEST => {}
+            _ => {
+                let commit = kind != SOLICIT || rapid;
+                let mut changed = false;
+                for (_, ia) in ias {
+                    if let Some(answer) = self.answer_ia(kind, commit, &duid, ia, &mut changed) {
+                        put(&mut out, OPTION_IA_NA, &answer);
+                    }
+                }
+                if changed {
+                    self.save();
+                }
+                if matches!(kind, RELEASE | DECLINE) {
+                    status(&mut out, STATUS_SUCCESS, "done");
+                    return Some(out);
+                }
+            }
+        }
+        let requested = |code: u16| {
+            option(opts, OPTION_ORO)
+                .is_some_and(|oro| oro.chunks(2).any(|c| c == code.to_be_bytes()))
+        };
+        if requested(OPTION_DNS_SERVERS) && !self.conf.dns_servers.is_empty() {
+            let addrs: Vec<u8> = self
+                .conf
+                .dns_servers
+                .iter()
+                .flat_map(|a| a.octets())
+                .collect();
+            put(&mut out, OPTION_DNS_SERVERS, &addrs);
+        }
+        if requested(OPTION_DOMAIN_LIST) && !self.search_domains.is_empty() {
+            let list = options::encode_domain_names(&self.search_domains);
+            put(&mut out, OPTION_DOMAIN_LIST, &list);
+        }
+        if requested(OPTION_DHCP4_O_DHCP6_SERVER) && self.conf.dhcp4o6 {
+            // no addresses: the multicast address reaches this server
+            put(&mut out, OPTION_DHCP4_O_DHCP6_SERVER, &[]);
+        }
+        Some(out)
+    }
+
+    /// The IA_NA answering `ia` of client `duid`: its address, bound when `commit`, or released
+    /// or declined as `kind` asks.
+    fn answer_ia(
+        &mut self,
+        kind: u8,
+        commit: bool,
+        duid: &str,
+        ia: &[u8],
+        changed: &mut bool,
+    ) -> Option<Vec<u8>> {
+        let iaid = u32::from_be_bytes(ia.get(..4)?.try_into().ok()?);
+        let mut out = iaid.to_be_bytes().to_vec();
+        let now = utils::now_timestamp_ms();
+        let bound = self
+            .leases
+            .iter()
+            .find(|(_, l)| l.duid == duid && l.iaid == iaid)
+            .map(|(a, _)| *a);
+        match (kind, bound) {
+            (RELEASE | DECLINE, Some(addr)) => {
+                if kind == RELEASE {
+                    self.leases.remove(&addr);
+                    println!("dhcp6: {} released by {}", addr, duid);
+                } else {
+                    // kept from other clients for a lease time
+                    let expiry = now + self.valid as u128 * 1000;
+                    let lease = Lease {
+                        duid: String::new(),
+                        iaid: 0,
+                        expiry,
+                    };
+                    self.leases.insert(addr, lease);
+                    println!("WARN: dhcp6: {} declined by {}", addr, duid);
+                }
+                *changed = true;
+                return None;
+            }
+            (RELEASE | DECLINE | RENEW | REBIND, None) => {
+                out.extend([0; 8]);
+                status(&mut out, STATUS_NO_BINDING, "no binding for this IA");
+                return Some(out);
+            }
+            _ => {}
+        }
+        let Some(addr) = bound.or_else(|| self.free(now)) else {
+            out.extend([0; 8]);
+            status(&mut out, STATUS_NO_ADDRS_AVAIL, "no addresses available");
+            return Some(out);
+        };
+        if commit {
+            let lease = Lease {
+                duid: duid.to_string(),
+                iaid,
+                expiry: now + self.valid as u128 * 1000,
+            };
+            self.leases.insert(addr, lease);
+            *changed = true;
+            println!("dhcp6: {} bound to {} iaid {}", addr, duid, iaid);
+        }
+        // T1 and T2 at half and four fifths of the preferred lifetime
+        out.extend((self.preferred / 2).to_be_bytes());
+        out.extend((self.preferred / 5 * 4).to_be_bytes());
+        let mut iaaddr = addr.octets().to_vec();
+        iaaddr.extend(self.preferred.to_be_bytes());
+        iaaddr.extend(self.valid.to_be_bytes());
+        put(&mut out, OPTION_IAADDR, &iaaddr);
+        Some(out)
+    }
+
+    fn in_pool(&self, addr: &Ipv6Addr) -> bool {
+        (u128::from(self.conf.start)..=u128::from(self.conf.end)).contains(&u128::from(*addr))
+    }
+
+    /// The first address of the pool without a lease, or whose lease ran out.
+    fn free(&self, now: u128) -> Option<Ipv6Addr> {
+        (u128::from(self.conf.start)..=u128::from(self.conf.end))
+            .map(Ipv6Addr::from)
+            .find(|a| self.leases.get(a).is_none_or(|l| l.expiry < now))
+    }
+
+    fn save(&self) {
+        let r = File::create(&self.conf.lease_file)
+            .map_err(|e| e.to_string())
+            .and_then(|f| {
+                serde_json::to_writer(BufWriter::new(f), &self.leases).map_err(|e| e.to_string())
+            });
+        if let Err(e) = r {
+            println!(
+                "ERROR: dhcp6: save leases to {}: {}",
+                self.conf.lease_file, e
+            );
+        }
+    }
+}
+
+/// The addresses an IA_NA holds.
+fn ia_addrs(ia: &[u8]) -> Vec<Ipv6Addr> {
+    let Some(opts) = ia.get(12..).and_then(parse_options) else {
+        return vec![];
+    };
+    opts.iter()
+        .filter(|(c, _)| *c == OPTION_IAADDR)
+        .filter_map(|(_, a)| Some(Ipv6Addr::from(<[u8; 16]>::try_from(a.get(..16)?).ok()?)))
+        .collect()
+}
+
+fn parse_options(mut data: &[u8]) -> Option<Options> {
+    let mut opts = vec![];
+    while !data.is_empty() {
+        let code = u16::from_be_bytes([*data.first()?, *data.get(1)?]);
+        let len = u16::from_be_bytes([*data.get(2)?, *data.get(3)?]) as usize;
+        opts.push((code, data.get(4..4 + len)?.to_vec()));
+        data = &data[4 + len..];
+    }
+    Some(opts)
+}
+
+fn option(opts: &Options, code: u16) -> Option<&[u8]> {
+    opts.iter().find(|(c, _)| *c == code).map(|(_, v)| &v[..])
+}
+
+fn put(out: &mut Vec<u8>, code: u16, value: &[u8]) {
+    out.extend(code.to_be_bytes());
+    out.extend((value.len() as u16).to_be_bytes());
+    out.extend(value);
+}
+
+fn status(out: &mut Vec<u8>, code: u16, message: &str) {
+    let mut value = code.to_be_bytes().to_vec();
+    value.extend(message.as_bytes());
+    put(out, OPTION_STATUS_CODE, &value);
+}
@@ -1,77 +1,452 @@
-use crate::config::Config;
+use crate::auth;
+use crate::config::{Allocation, ClientClass, Config};
+use crate::ddns;
+use crate::dhcp6;
+use crate::docker;
+use crate::hwaddr::HwAddr;
+use crate::leasequery;
+use crate::log;
+use crate::mgmt;
 use crate::options;
 use crate::packet;
+use crate::profiles;
+use crate::reservations;
 use crate::server;
+use crate::stats;
+use crate::template;
 use crate::utils;
+use crate::webhook;
+use crate::zone;
 
 use duration_str::parse;
-use mac_address::MacAddress;
 use serde::{Deserialize, Serialize};
-use serde_json;
 
+use std::cell::{Cell, RefCell};
 use std::collections::HashMap;
 use std::error::Error;
-use std::fs::File;
-use std::io::{BufRead, BufReader, BufWriter};
+use std::fs::{File, OpenOptions};
+use std::io::{BufReader, BufWriter};
 use std::net::Ipv4Addr;
 use std::path::Path;
 use std::str::FromStr;
-use std::time::Duration;
+use std::time::{Duration, SystemTime};
 
 const INFINITE_LEASE: u128 = 1000 * 86400 * 365; //10 years as ms
+const MIN_V6ONLY_WAIT: u32 = 300; // RFC 8925
+const DEFAULT_DRAIN_LEASE: &str = "5m";
+/// How often the names of expired leases are withdrawn (ms).
+const SWEEP_INTERVAL: u128 = 10_000;
+
+pub type Leases = HashMap<Ipv4Addr, Lease>;
+
+/// Version 2 added `tenants`; version 1 snapshots are still accepted.
+const SNAPSHOT_VERSION: u32 = 2;
+
+/// Pool boundaries set through the management interface, kept in `<lease_file>.pool`.
+#[derive(Deserialize, Serialize, Debug)]
+pub struct PoolRange {
+    pub start: Ipv4Addr,
+    pub end: Ipv4Addr,
+}
+
+/// The portable lease state exchanged by `GET`/`PUT /snapshot`.
+#[derive(Deserialize, Serialize, Debug)]
+pub struct Snapshot {
+    pub version: u32,
+    pub taken: u128,
+    pub leases: Leases,
+    /// Leases of each tenant and pool keyed by its `lease_file`, since tenant subnets may overlap.
+    #[serde(default, skip_serializing_if = "HashMap::is_empty")]
+    pub tenants: HashMap<String, Leases>,
+}
+
+impl Snapshot {
+    pub fn new(leases: Leases) -> Snapshot {
+        Snapshot {
+            version: SNAPSHOT_VERSION,
+            taken: utils::now_timestamp_ms(),
+            leases,
+            tenants: HashMap::new(),
+        }
+    }
+
+    /// Parses a snapshot body, rejecting versions this build does not know.
+    pub fn parse(body: &[u8]) -> Result<Snapshot, mgmt::Response> {
+        let snapshot: Snapshot = serde_json::from_slice(body)
+            .map_err(|e| mgmt::Response::error(400, &format!("invalid snapshot: {}", e)))?;
+        if snapshot.version == 0 || snapshot.version > SNAPSHOT_VERSION {
+            return Err(mgmt::Response::error(400, "unsupported snapshot version"));
+        }
+        Ok(snapshot)
+    }
+}
 
 #[derive(Deserialize, Serialize, Debug, Clone)]
-struct Lease {
-    mac: [u8; 6],
-    expiry: u128,
+pub struct Lease {
+    pub mac: HwAddr,
+    pub expiry: u128,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub mud_url: Option<String>,
+    /// PXE client system architectures (option 93).
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub client_arch: Vec<u16>,
+    /// PXE network interface identifier (option 94) as `type major.minor`, e.g. `UNDI 2.1`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub client_nii: Option<String>,
+    /// PXE machine UUID (option 97).
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub client_uuid: Option<String>,
+    /// Host name the client sent (option 12, else option 81).
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub hostname: Option<String>,
+    /// `hostname` made a valid DNS label, unique among the active leases.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub dns_name: Option<String>,
+    /// Fully qualified name the client sent (option 81).
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub fqdn: Option<String>,
+    /// Client identifier (option 61) in hex; when set, the lease belongs to it rather than
+    /// to `mac`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub client_id: Option<String>,
 }
 impl Lease {
-    pub fn new(mac: [u8; 6], expiry: u128) -> Lease {
-        Lease { mac, expiry }
+    pub fn new(mac: HwAddr, expiry: u128) -> Lease {
+        Lease {
+            mac,
+            expiry,
+            mud_url: None,
+            client_arch: vec![],
+            client_nii: None,
+            client_uuid: None,
+            hostname: None,
+            dns_name: None,
+            fqdn: None,
+            client_id: None,
+        }
+    }
+
+    /// Records the PXE identity options (93, 94, 97) the client sent, if any.
+    fn record_pxe_identity(&mut self, req_packet: &packet::Packet) {
+        if let Some(options::DhcpOption::ClientArchitecture(archs)) =
+            req_packet.option(options::CLIENT_ARCHITECTURE)
+        {
+            self.client_arch = archs.clone();
+        }
+        if let Some(options::DhcpOption::ClientNetworkInterface(nii)) =
+            req_packet.option(options::CLIENT_NETWORK_INTERFACE_ID)
+        {
+            if let [t, major, minor] = nii[..] {
+                let kind = if t == 1 {
+                    "UNDI".to_string()
+                } else {
+                    t.to_string()
+                };
+                self.client_nii = Some(format!("{} {}.{}", kind, major, minor));
+            }
+        }
+        if let Some(options::DhcpOption::ClientMachineId(id)) =
+            req_packet.option(options::CLIENT_MACHINE_ID)
+        {
+            // type 0 followed by a 16 byte UUID (RFC 4578)
+            if let [0, uuid @ ..] = &id[..] {
+                if uuid.len() == 16 {
+                    let h: Vec<String> = uuid.iter().map(|b| format!("{:02x}", b)).collect();
+                    self.client_uuid = Some(format!(
+                        "{}-{}-{}-{}-{}",
+                        h[0..4].concat(),
+                        h[4..6].concat(),
+                        h[6..8].concat(),
+                        h[8..10].concat(),
+                        h[10..16].concat()
+                    ));
+                }
+            }
+        }
+    }
+
+    /// The lease as presented on the management interface.
+    fn to_json(&self, ip: &Ipv4Addr) -> serde_json::Value {
+        serde_json::json!({
+            "ip": ip,
+            "mac": self.mac.to_string(),
+            "expiry": self.expiry,
+            "mud_url": self.mud_url,
+            "client_arch": self.client_arch,
+            "client_nii": self.client_nii,
+            "client_uuid": self.client_uuid,
+            "hostname": self.hostname,
+            "dns_name": self.dns_name,
+            "fqdn": self.fqdn,
+            "client_id": self.client_id,
+        })
+    }
+
+    /// The lease as the management API shows it, with the DHCPv6 addresses of the same host.
+    fn host_json(&self, ip: &Ipv4Addr) -> serde_json::Value {
+        let mut v = self.to_json(ip);
+        v["ipv6"] = serde_json::json!(dhcp6::host_addresses(&self.mac, self.client_id.as_deref()));
+        v
+    }
+}
+
+/// Who a request comes from: its client identifier (option 61) if it sent one, and its
+/// hardware address.
+#[derive(Debug, Clone)]
+pub struct ClientId {
+    mac: HwAddr,
+    id: Option<String>,
+}
+impl ClientId {
+    pub fn of(p: &packet::Packet) -> ClientId {
+        ClientId {
+            mac: p.chaddr,
+            id: p
+                .client_id()
+                .map(|id| id.iter().map(|b| format!("{:02x}", b)).collect()),
+        }
+    }
+
+    /// A client known only by its hardware address, as on the management interface.
+    pub fn mac(mac: HwAddr) -> ClientId {
+        ClientId { mac, id: None }
+    }
+
+    /// Whether `lease` is this client's. Leases are matched by identifier when both sides have
+    /// one, else by hardware address (MAC reservations, leases from older lease files).
+    fn owns(&self, lease: &Lease) -> bool {
+        match (&self.id, &lease.client_id) {
+            (Some(id), Some(theirs)) => id == theirs,
+            _ => lease.mac == self.mac,
+        }
+    }
+
+    /// A stable number for the client (32-bit FNV-1a of its identifier, else of its hardware
+    /// address).
+    fn fingerprint(&self) -> u32 {
+        let bytes = match &self.id {
+            Some(id) => id.as_bytes(),
+            None => self.mac.as_bytes(),
+        };
+        bytes
+            .iter()
+            .fold(0x811c9dc5, |h, b| (h ^ *b as u32).wrapping_mul(0x01000193))
+    }
+
+    /// Binds `lease` to this client, following a change of hardware address.
+    fn stamp(&self, lease: &mut Lease) {
+        lease.mac = self.mac;
+        if self.id.is_some() {
+            lease.client_id = self.id.clone();
+        }
     }
 }
 
 #[derive(Debug)]
 pub struct Dhcpd {
     conf: Config,
-    leases: HashMap<Ipv4Addr, Lease>,
+    leases: Leases,
+    /// Pool index, as in `nth_address`, of the last address handed out.
     last_lease: u32,
     lease_duration: Duration,
+    recycle_after: Option<Duration>,
+    v6_only_wait: Option<u32>,
+    /// End of the takeover period (ms); until then the server only learns leases.
+    active_after: u128,
+    /// Set while draining: no new leases, renewals get at most this lease time.
+    drain: Option<Duration>,
+    /// The hosts last written to the zone files.
+    zone_hosts: RefCell<Option<Vec<zone::Host>>>,
+    /// The hosts last queued to the DNS server, see `ddns.rs`.
+    ddns_hosts: RefCell<Vec<zone::Host>>,
+    /// When the lease file was last written successfully (ms).
+    last_saved: Cell<Option<u128>>,
+    /// When the names of expired leases were last withdrawn (ms).
+    last_swept: u128,
+    /// The active leases last pushed to active leasequeries: address, hardware address, expiry.
+    lq_seen: RefCell<HashMap<Ipv4Addr, (HwAddr, u128)>>,
+    /// The other subnets of the shared network, whose clients this server may serve too.
+    shared_subnets: Vec<Config>,
+    /// The pool as address ranges, each with its end excluded.
+    ranges: Vec<(u32, u32)>,
+    /// The reservations in this subnet, reread when `lease_static` changes.
+    reservations: Vec<reservations::Reservation>,
+    reservations_changed: Option<SystemTime>,
 }
 impl Dhcpd {
     pub fn new(conf: Config) -> Dhcpd {
-        let (hm, last_lease) = load_leases(
-            conf.lease_static.as_str(),
-            conf.lease_file.as_str(),
-            conf.start.into(),
-            conf.end.into(),
-        );
-        let ll = conf.lease_time.clone();
-        if hm.is_ok() {
-            // println!("loaded leases count: {}", hm.len());
-            Dhcpd {
-                conf,
-                leases: hm.unwrap(),
-                last_lease: last_lease,
-                lease_duration: parse(ll.as_str()).unwrap(),
+        let reserved = reservations::for_subnet(&conf);
+        let reservations_changed = modified(&conf.lease_static);
+        let hm = load_leases(&conf, &reserved);
+        let lease_duration = parse(conf.lease_time.as_str()).unwrap();
+        let recycle_after = conf
+            .recycle_after
+            .as_ref()
+            .map(|d| parse(d.as_str()).unwrap());
+        let v6_only_wait = conf
+            .v6_only_wait
+            .as_ref()
+            .map(|w| (parse(w.as_str()).unwrap().as_secs() as u32).max(MIN_V6ONLY_WAIT));
+        let mut conf = conf;
+        if let Some(pool) = load_pool(&pool_file(&conf)) {
+            println!(
+                "pool {} - {} from {}",
+                pool.start,
+                pool.end,
+                pool_file(&conf)
+            );
+            conf.start = pool.start;
+            conf.end = pool.end;
+        }
+        let active_after = match &conf.takeover {
+            Some(d) => utils::now_timestamp_ms() + parse(d.as_str()).unwrap().as_millis(),
+            None => 0,
+        };
+        let ranges = pool_ranges(&conf);
+        let dhcpd = match hm {
+            Ok(leases) => {
+                // println!("loaded leases count: {}", leases.len());
+                Dhcpd {
+                    conf,
+                    last_lease: last_index(&ranges, &leases),
+                    leases,
+                    lease_duration,
+                    recycle_after,
+                    v6_only_wait,
+                    active_after,
+                    drain: None,
+                    zone_hosts: RefCell::new(None),
+                    ddns_hosts: RefCell::new(vec![]),
+                    last_saved: Cell::new(None),
+                    last_swept: 0,
+                    lq_seen: RefCell::new(HashMap::new()),
+                    shared_subnets: vec![],
+                    ranges,
+                    reservations: reserved,
+                    reservations_changed,
+                }
             }
-        } else {
-            Dhcpd {
+            Err(_) => Dhcpd {
                 conf,
                 leases: HashMap::new(),
                 last_lease: 0,
-                lease_duration: parse(ll.as_str()).unwrap(),
+                lease_duration,
+                recycle_after,
+                v6_only_wait,
+                active_after,
+                drain: None,
+                zone_hosts: RefCell::new(None),
+                ddns_hosts: RefCell::new(vec![]),
+                last_saved: Cell::new(None),
+                last_swept: 0,
+                lq_seen: RefCell::new(HashMap::new()),
+                shared_subnets: vec![],
+                ranges,
+                reservations: reserved,
+                reservations_changed,
+            },
+        };
+        dhcpd.report_leases();
+        dhcpd.publish_zones();
+        dhcpd.update_dns();
+        dhcpd
+    }
+    pub fn conf(&self) -> &Config {
+        &self.conf
+    }
+    /// Rereads the reservations if `lease_static` changed, e.g. by an import.
+    fn reload_reservations(&mut self) {
+        let changed = modified(&self.conf.lease_static);
+        if changed != self.reservations_changed {
+            self.reservations = reservations::for_subnet(&self.conf);
+            self.reservations_changed = changed;
+            println!("reservations reloaded from {}", self.conf.lease_static);
+        }
+    }
+    /// Accepts requests relayed from the subnets of `others` too.
+    pub fn share_network(&mut self, others: Vec<Config>) {
+        self.shared_subnets = others;
+    }
+    /// Whether `addr` lies in this subnet or another one of its shared network.
+    fn on_network(&self, addr: Ipv4Addr) -> bool {
+        self.conf.subnet_contains(addr)
+            || self.shared_subnets.iter().any(|c| c.subnet_contains(addr))
+    }
+    /// A free address for the client. Addresses whose lease ran out less than `recycle_after`
+    /// ago are kept for their client while others are left; then the one that ran out first
+    /// is taken.
+    fn free_address(&mut self, client: &ClientId) -> Option<Ipv4Addr> {
+        if let Some(ip) = self.pick_address(client, Self::unclaimed) {
+            return Some(ip);
+        }
+        self.recycle_after?;
+        let ip = (0..self.lease_nums())
+            .map(|k| self.nth_address(k))
+            .filter(|ip| self.available(client, ip))
+            .min_by_key(|ip| self.leases.get(ip).map_or(0, |l| l.expiry))?;
+        if let Some(l) = self.leases.get(&ip) {
+            println!("pool tight, {} of {} recycled early", ip, l.mac);
+        }
+        Some(ip)
+    }
+    /// Available, and not held for the client whose lease on it ran out within
+    /// `recycle_after`.
+    fn unclaimed(&self, client: &ClientId, addr: &Ipv4Addr) -> bool {
+        if !self.available(client, addr) {
+            return false;
+        }
+        match (self.leases.get(addr), self.recycle_after) {
+            (Some(l), Some(keep)) if !client.owns(l) => {
+                l.expiry + keep.as_millis() <= utils::now_timestamp_ms()
+            }
+            _ => true,
+        }
+    }
+    /// The first address `ok` accepts, in the order `allocation` says.
+    fn pick_address(
+        &mut self,
+        client: &ClientId,
+        ok: fn(&Dhcpd, &ClientId, &Ipv4Addr) -> bool,
+    ) -> Option<Ipv4Addr> {
+        let n = self.lease_nums() as u64;
+        let first = match self.conf.allocation {
+            Allocation::Sequential => self.last_lease as u64 + 1,
+            Allocation::Hash => client.fingerprint() as u64,
+            Allocation::Random => utils::random_u32() as u64,
+        };
+        for i in 0..n {
+            let k = ((first + i) % n) as u32;
+            let ip = self.nth_address(k);
+            if ok(self, client, &ip) {
+                if self.conf.allocation == Allocation::Sequential {
+                    self.last_lease = k;
+                }
+                return Some(ip);
             }
         }
+        None
     }
-    fn start_num(&self) -> u32 {
-        self.conf.start.into()
+    /// Whether the client holds a lease here, expired or not.
+    pub fn knows(&self, p: &packet::Packet) -> bool {
+        self.current_lease(&ClientId::of(p)).is_some()
     }
-    fn end_num(&self) -> u32 {
-        self.conf.end.into()
+    /// Whether an address is left for the client without recycling one early.
+    pub fn has_free(&self, p: &packet::Packet) -> bool {
+        let client = ClientId::of(p);
+        (0..self.lease_nums()).any(|n| self.unclaimed(&client, &self.nth_address(n)))
     }
     fn lease_nums(&self) -> u32 {
-        self.end_num() - self.start_num()
+        self.ranges.iter().map(|(s, e)| e - s).sum()
+    }
+    /// The `n`th address of the pool, counting across its ranges.
+    fn nth_address(&self, mut n: u32) -> Ipv4Addr {
+        for &(s, e) in &self.ranges {
+            if n < e - s {
+                return Ipv4Addr::from(s + n);
+            }
+            n -= e - s;
+        }
+        self.conf.start
     }
     fn subnet_mask(&self) -> Ipv4Addr {
         self.conf.netmask
@@ -79,30 +454,276 @@ impl Dhcpd {
     fn gateway_ip(&self) -> Ipv4Addr {
         self.conf.gateway
     }
-    fn dns_servers(&self) -> Vec<Ipv4Addr> {
-        self.conf.dns_servers.clone()
+    fn dns_servers(&self, classes: &[&ClientClass]) -> Vec<Ipv4Addr> {
+        class_value(classes, |c| c.dns_servers.as_ref())
+            .unwrap_or(&self.conf.dns_servers)
+            .clone()
+    }
+    /// The class or subnet lease time, or the one the client asked for (option 51) if it lies
+    /// within `min_lease_time` and `max_lease_time`, else the nearer of the two.
+    fn lease_time_for(&self, classes: &[&ClientClass], req_packet: &packet::Packet) -> Duration {
+        let d = class_value(classes, |c| c.lease_time.as_ref())
+            .and_then(|t| parse(t.as_str()).ok())
+            .unwrap_or(self.lease_duration);
+        let Some(options::DhcpOption::IpAddressLeaseTime(secs)) =
+            req_packet.option(options::IP_ADDRESS_LEASE_TIME)
+        else {
+            return d;
+        };
+        let bound = |t: &Option<String>| t.as_ref().and_then(|t| parse(t.as_str()).ok());
+        let min = bound(&self.conf.min_lease_time).map_or(d, |m| m.min(d));
+        let max = bound(&self.conf.max_lease_time).map_or(d, |m| m.max(d));
+        Duration::from_secs(*secs as u64).clamp(min, max)
+    }
+    /// The lease time granted for `ip`. Leases left outside the pool by a resize run out
+    /// instead of being renewed.
+    fn lease_granted(
+        &self,
+        classes: &[&ClientClass],
+        req_packet: &packet::Packet,
+        ip: &Ipv4Addr,
+    ) -> Duration {
+        let mut d = self.lease_time_for(classes, req_packet);
+        if let Some(drain) = self.drain {
+            d = d.min(drain);
+        }
+        if self.retired(ip) {
+            let now = utils::now_timestamp_ms();
+            let left = self
+                .leases
+                .get(ip)
+                .map_or(0, |l| l.expiry.saturating_sub(now));
+            return d.min(Duration::from_millis(left as u64));
+        }
+        d
+    }
+    fn lease_secs(
+        &self,
+        classes: &[&ClientClass],
+        req_packet: &packet::Packet,
+        ip: &Ipv4Addr,
+    ) -> u32 {
+        self.lease_granted(classes, req_packet, ip).as_secs() as u32
+    }
+    /// T1 and T2 for a lease of `lease` seconds. Configured times that do not fit the lease
+    /// (T1 < T2 < lease time, e.g. for a short class or draining lease) give way to the
+    /// RFC 2131 defaults.
+    fn renew_rebind_secs(&self, lease: u32) -> (u32, u32) {
+        let secs = |t: &Option<String>| {
+            t.as_ref()
+                .and_then(|t| parse(t.as_str()).ok())
+                .map(|d| d.as_secs() as u32)
+        };
+        let t1 = secs(&self.conf.renew_time).unwrap_or(lease / 2);
+        let t2 = secs(&self.conf.rebind_time).unwrap_or((lease as u64 * 7 / 8) as u32);
+        if t1 < t2 && t2 < lease {
+            (t1, t2)
+        } else {
+            (lease / 2, (lease as u64 * 7 / 8) as u32)
+        }
+    }
+    fn lease_expiry(
+        &self,
+        classes: &[&ClientClass],
+        req_packet: &packet::Packet,
+        ip: &Ipv4Addr,
+    ) -> u128 {
+        utils::now_timestamp_ms() + self.lease_granted(classes, req_packet, ip).as_millis()
+    }
+    fn in_pool(&self, ip: &Ipv4Addr) -> bool {
+        let pos: u32 = (*ip).into();
+        self.ranges.iter().any(|(s, e)| (*s..*e).contains(&pos)) && !self.conf.excluded(*ip)
+    }
+    /// A dynamic lease that is no longer part of the pool.
+    fn retired(&self, ip: &Ipv4Addr) -> bool {
+        !self.in_pool(ip) && !self.reservations.iter().any(|r| r.ip == *ip)
+    }
+    /// Forgets the client's lease once it has run out outside the pool, so it gets a new one.
+    fn drop_retired(&mut self, client: &ClientId) {
+        if let Some(ip) = self.current_lease(client) {
+            let expired = self.leases[&ip].expiry <= utils::now_timestamp_ms();
+            if expired && self.retired(&ip) {
+                println!("{} left the pool, released", ip);
+                self.leases.remove(&ip);
+                self.save_leases();
+            }
+        }
+    }
+    /// Binds the address reserved for the client's identifier or DUID to its current hardware
+    /// address, so a client with a randomized MAC keeps its address.
+    fn claim_reservation(&mut self, p: &packet::Packet) {
+        let Some(client_id) = p.client_id() else {
+            return;
+        };
+        let Some(r) = self
+            .reservations
+            .iter()
+            .find(|r| r.key.mac().is_none() && r.key.matches(&p.chaddr, Some(client_id)))
+            .cloned()
+        else {
+            return;
+        };
+        if self.leases.get(&r.ip).map(|l| l.mac) == Some(p.chaddr) {
+            return;
+        }
+        println!("{} reserved for {}, bound to {}", r.ip, r.key, p.chaddr);
+        let client = ClientId::of(p);
+        self.leases.retain(|_, l| !client.owns(l));
+        let mut lease = Lease::new(p.chaddr, utils::now_timestamp_ms() + INFINITE_LEASE);
+        client.stamp(&mut lease);
+        self.leases.insert(r.ip, lease);
+        self.save_leases();
     }
-    fn lease_secs(&self) -> u32 {
-        self.lease_duration.as_secs() as u32
+    /// The configured classes the client belongs to, in configuration order.
+    fn classes(&self, req_packet: &packet::Packet) -> Vec<&ClientClass> {
+        self.conf
+            .classes
+            .iter()
+            .filter(|c| class_matches(c, req_packet))
+            .collect()
     }
-    fn available(&self, chaddr: &[u8; 6], addr: &Ipv4Addr) -> bool {
-        let pos: u32 = (*addr).into();
-        pos >= self.start_num()
-            && pos < self.start_num() + self.lease_nums()
+    fn available(&self, client: &ClientId, addr: &Ipv4Addr) -> bool {
+        self.in_pool(addr)
+            && !docker::excluded(addr)
             && match self.leases.get(addr) {
-                Some(lease) => lease.mac == *chaddr || utils::now_timestamp_ms() > lease.expiry,
+                Some(lease) => client.owns(lease) || utils::now_timestamp_ms() > lease.expiry,
                 None => true,
             }
     }
-    fn current_lease(&self, chaddr: &[u8; 6]) -> Option<Ipv4Addr> {
+    fn current_lease(&self, client: &ClientId) -> Option<Ipv4Addr> {
         for (i, v) in &self.leases {
-            if v.mac == *chaddr {
+            if client.owns(v) {
                 return Some(*i);
             }
         }
         None
     }
+    /// Binds `ip` to the requesting client, or extends its binding, and records what the client
+    /// told us about itself.
+    fn commit(&mut self, ip: Ipv4Addr, req_packet: &packet::Packet) {
+        let expiry = self.lease_expiry(&self.classes(req_packet), req_packet, &ip);
+        let client = ClientId::of(req_packet);
+        let lease = match self.leases.get_mut(&ip) {
+            Some(lease) if client.owns(lease) => {
+                // never shorten a reservation's lease
+                if lease.expiry < expiry {
+                    lease.expiry = expiry;
+                }
+                lease
+            }
+            _ => {
+                self.lease_event("bound", req_packet.chaddr, ip, Some(expiry));
+                stats::bound();
+                self.leases
+                    .insert(ip, Lease::new(req_packet.chaddr, expiry));
+                self.leases.get_mut(&ip).unwrap()
+            }
+        };
+        client.stamp(lease);
+        let mud_url = match req_packet.option(options::MUD_URL) {
+            Some(options::DhcpOption::MudUrl(url)) => Some(url.clone()),
+            _ => None,
+        };
+        lease.record_pxe_identity(req_packet);
+        if let Some(name) = req_packet.host_name() {
+            lease.hostname = Some(name.to_string());
+        }
+        if let Some(options::DhcpOption::ClientFqdn(fqdn)) = req_packet.option(options::CLIENT_FQDN)
+        {
+            lease.fqdn = Some(fqdn.name.clone()).filter(|n| !n.is_empty());
+        }
+        let mud_changed = mud_url.is_some() && lease.mud_url != mud_url;
+        if mud_changed {
+            lease.mud_url = mud_url;
+        }
+        let dns_name = self.dns_name_for(&ip);
+        self.leases.get_mut(&ip).unwrap().dns_name = dns_name;
+        self.save_leases();
+        if let (true, Some(hook)) = (mud_changed, &self.conf.mud_webhook) {
+            webhook::post(
+                hook,
+                serde_json::json!({
+                    "mac": req_packet.chaddr.to_string(),
+                    "ip": ip,
+                    "mud_url": self.leases[&ip].mud_url,
+                }),
+            );
+        }
+    }
+    /// The DNS name for the lease on `ip` from its host name, resolving collisions with the other
+    /// active leases by a numeric suffix.
+    fn dns_name_for(&self, ip: &Ipv4Addr) -> Option<String> {
+        let lease = &self.leases[ip];
+        let base = zone::sanitize(lease.hostname.as_ref()?)?;
+        let now = utils::now_timestamp_ms();
+        let taken = |name: &str| {
+            self.leases.iter().any(|(other, l)| {
+                other != ip && l.expiry > now && l.dns_name.as_deref() == Some(name)
+            })
+        };
+        Some(zone::unique_name(&base, lease.dns_name.as_deref(), taken))
+    }
+    /// Tells `lease_webhook` that a binding started or ended.
+    fn lease_event(&self, event: &str, mac: HwAddr, ip: Ipv4Addr, expiry: Option<u128>) {
+        if let Some(hook) = &self.conf.lease_webhook {
+            webhook::post(
+                hook,
+                serde_json::json!({
+                    "event": event,
+                    "intf": self.conf.intf,
+                    "mac": mac.to_string(),
+                    "ip": ip,
+                    "expiry": expiry,
+                }),
+            );
+        }
+    }
+    /// The named active leases, by address.
+    fn named_hosts(&self) -> Vec<zone::Host> {
+        let now = utils::now_timestamp_ms();
+        let mut hosts: Vec<zone::Host> = self
+            .leases
+            .iter()
+            .filter(|(_, l)| l.expiry > now)
+            .filter_map(|(ip, l)| Some((l.dns_name.clone()?, *ip)))
+            .collect();
+        hosts.sort_by_key(|(_, ip)| *ip);
+        hosts
+    }
+    /// Rewrites the zone files if the named active leases changed since they were last written.
+    fn publish_zones(&self) {
+        let Some(zones) = &self.conf.zones else {
+            return;
+        };
+        let hosts = self.named_hosts();
+        let mut last = self.zone_hosts.borrow_mut();
+        if last.as_ref() == Some(&hosts) {
+            return;
+        }
+        let net = Ipv4Addr::from(u32::from(self.conf.start) & u32::from(self.conf.netmask));
+        zone::publish(zones, net, self.conf.netmask, &hosts);
+        *last = Some(hosts);
+    }
+    /// Queues DNS updates for the names that appeared or went away since the last call.
+    fn update_dns(&self) {
+        if self.conf.ddns.is_none() {
+            return;
+        }
+        let hosts = self.named_hosts();
+        let mut last = self.ddns_hosts.borrow_mut();
+        for gone in last.iter().filter(|h| !hosts.contains(h)) {
+            ddns::update(ddns::Change::Remove(gone.clone()));
+        }
+        for new in hosts.iter().filter(|h| !last.contains(h)) {
+            ddns::update(ddns::Change::Add(new.clone()));
+        }
+        *last = hosts;
+    }
     fn save_leases(&self) {
+        self.report_leases();
+        self.publish_zones();
+        self.update_dns();
+        self.notify_leasequery();
         if let Ok(file) = File::create(self.conf.lease_file.as_str()) {
             let writer = BufWriter::new(file);
             let r = serde_json::to_writer(writer, &self.leases);
@@ -110,8 +731,359 @@ impl Dhcpd {
                 println!("ERROR: {:?}", r);
             } else {
                 println!("save leases to {} success.", self.conf.lease_file.as_str());
+                self.last_saved.set(Some(utils::now_timestamp_ms()));
+            }
+        }
+    }
+    /// Whether this server should be sent traffic, with the details behind the answer.
+    pub fn readiness(&self) -> (bool, serde_json::Value) {
+        let writable = OpenOptions::new()
+            .append(true)
+            .create(true)
+            .open(&self.conf.lease_file)
+            .is_ok();
+        let taking_over = utils::now_timestamp_ms() < self.active_after;
+        let ready = writable && !taking_over && self.drain.is_none();
+        (
+            ready,
+            serde_json::json!({
+                "intf": self.conf.intf,
+                "pool": format!("{}-{}", self.conf.start, self.conf.end),
+                "ready": ready,
+                // the serve loop answering this means the socket is bound and read
+                "socket": "bound",
+                "lease_store_writable": writable,
+                "last_saved": self.last_saved.get().map(|t| t as u64),
+                "taking_over": taking_over,
+                "draining": self.drain.is_some(),
+                "ha": "standalone",
+            }),
+        )
+    }
+    /// Moves the pool boundaries and persists them. Leases outside the new range are honoured
+    /// until they expire.
+    pub fn resize(&mut self, pool: PoolRange) -> mgmt::Response {
+        let (start, end) = (u32::from(pool.start), u32::from(pool.end));
+        let mask = u32::from(self.conf.netmask);
+        let net = u32::from(self.conf.listen_addr) & mask;
+        if start >= end || start & mask != net || (end - 1) & mask != net || start == net {
+            return mgmt::Response::error(400, "pool must be a non-empty range in the subnet");
+        }
+        let path = pool_file(&self.conf);
+        if let Err(e) = std::fs::write(&path, serde_json::to_vec(&pool).unwrap_or_default()) {
+            println!("ERROR: write {} failed: {:?}", path, e);
+            return mgmt::Response::error(500, "cannot persist pool");
+        }
+        println!("pool resized to {} - {}", pool.start, pool.end);
+        self.conf.start = pool.start;
+        self.conf.end = pool.end;
+        self.ranges = pool_ranges(&self.conf);
+        self.last_lease = 0;
+        self.pool()
+    }
+    /// Stops handing out new leases and caps renewals at `lease_time` so the pool empties.
+    pub fn start_drain(&mut self, lease_time: Duration) {
+        println!("draining, renewals limited to {:?}", lease_time);
+        self.drain = Some(lease_time);
+    }
+    pub fn resume(&mut self) {
+        if self.drain.take().is_some() {
+            println!("drain ended, serving normally");
+        }
+    }
+    pub fn pool(&self) -> mgmt::Response {
+        mgmt::Response::json(200, &vec![self.pool_json()])
+    }
+    pub fn pool_json(&self) -> serde_json::Value {
+        let now = utils::now_timestamp_ms();
+        let leased = self
+            .leases
+            .iter()
+            .filter(|(ip, l)| self.in_pool(ip) && l.expiry > now)
+            .count();
+        let retired = self
+            .leases
+            .iter()
+            .filter(|(ip, l)| l.expiry > now && self.retired(ip))
+            .count();
+        serde_json::json!({
+            "start": self.conf.start,
+            "end": self.conf.end,
+            "size": self.lease_nums(),
+            "leased": leased,
+            "outside": retired,
+            "draining": self.drain.is_some(),
+        })
+    }
+    pub fn snapshot(&self) -> Leases {
+        self.leases.clone()
+    }
+
+    /// Replaces the lease table with the entries of `leases` that lie in this subnet.
+    /// Local reservations always win over restored entries. Returns the number restored.
+    pub fn restore(&mut self, leases: &Leases) -> usize {
+        let mut restored: Leases = leases
+            .iter()
+            .filter(|(ip, _)| self.conf.subnet_contains(**ip))
+            .map(|(ip, l)| (*ip, l.clone()))
+            .collect();
+        let count = restored.len();
+        for r in &self.reservations {
+            let Some(mac) = r.key.mac() else {
+                continue;
+            };
+            if restored.get(&r.ip).map(|l| l.mac) != Some(mac) {
+                restored.insert(
+                    r.ip,
+                    Lease::new(mac, utils::now_timestamp_ms() + INFINITE_LEASE),
+                );
+            }
+        }
+        self.last_lease = last_index(&self.ranges, &restored);
+        self.leases = restored;
+        self.save_leases();
+        count
+    }
+    /// Takeover mode: records the address another server handed out instead of answering.
+    ///
+    /// ACKs overheard on port 68 carry the lease time; a client's REQUEST only names the
+    /// address it holds, so that lease gets our own lease time.
+    fn learn(&mut self, p: &packet::Packet) {
+        let (ip, secs) = match (p.reply, p.message_type()) {
+            (true, Ok(options::MessageType::Ack)) => match p.option(options::IP_ADDRESS_LEASE_TIME)
+            {
+                Some(options::DhcpOption::IpAddressLeaseTime(secs)) => (p.yiaddr, Some(*secs)),
+                _ => (p.yiaddr, None),
+            },
+            (false, Ok(options::MessageType::Request)) => (p.requested_ip(), None),
+            _ => return,
+        };
+        if ip.is_unspecified() || !self.conf.subnet_contains(ip) {
+            return;
+        }
+        let client = |r: &reservations::Reservation| r.key.matches(&p.chaddr, p.client_id());
+        if let Some(r) = self.reservations.iter().find(|r| r.ip == ip || client(r)) {
+            if r.ip != ip || !client(r) {
+                println!(
+                    "WARN: takeover: {} holds {} against reservation {},{}",
+                    p.chaddr, ip, r.key, r.ip
+                );
             }
+            return;
         }
+        let secs = secs.map_or(self.lease_duration, |s| Duration::from_secs(s as u64));
+        let client = ClientId::of(p);
+        self.leases.retain(|_, l| !client.owns(l));
+        let mut lease = Lease::new(p.chaddr, utils::now_timestamp_ms() + secs.as_millis());
+        client.stamp(&mut lease);
+        self.leases.insert(ip, lease);
+        println!("takeover: learned {} for {}", ip, p.chaddr);
+        self.save_leases();
+    }
+    fn report_leases(&self) {
+        let now = utils::now_timestamp_ms();
+        let active = self.leases.values().filter(|l| l.expiry > now).count();
+        stats::set_active_leases(&self.conf.lease_file, active, self.lease_nums() as usize);
+    }
+    fn vendor_specific(
+        &self,
+        req_packet: &packet::Packet,
+        vars: &template::Vars,
+    ) -> Option<options::DhcpOption> {
+        let enterprises = req_packet.vendor_enterprises();
+        let infos: Vec<options::VendorInfo> = self
+            .conf
+            .vendor_options
+            .iter()
+            .filter(|v| enterprises.contains(&v.enterprise))
+            .map(|v| {
+                let mut len = 0;
+                let suboptions = v
+                    .suboptions
+                    .iter()
+                    .map(|o| options::RawDhcpOption {
+                        code: o.code,
+                        data: o.value.to_bytes(vars),
+                    })
+                    // placeholders may have grown a value past what its length byte holds
+                    .filter(|o| {
+                        len += 2 + o.data.len();
+                        let fits = o.data.len() <= 255 && len <= 255;
+                        if !fits {
+                            println!(
+                                "WARN: sub-option {} too long once expanded, left out",
+                                o.code
+                            );
+                            len -= 2 + o.data.len();
+                        }
+                        fits
+                    })
+                    .collect();
+                options::VendorInfo {
+                    enterprise: v.enterprise,
+                    suboptions,
+                }
+            })
+            .collect();
+        if infos.is_empty() {
+            None
+        } else {
+            Some(options::DhcpOption::VendorSpecific(infos))
+        }
+    }
+    fn force_renew(&self, s: &server::Server, mac: &str) -> mgmt::Response {
+        let Ok(mac) = HwAddr::from_str(mac) else {
+            return mgmt::Response::error(400, "invalid hardware address");
+        };
+        let Some(ip) = self.current_lease(&ClientId::mac(mac)) else {
+            return mgmt::Response::not_found();
+        };
+        if self.leases[&ip].expiry < utils::now_timestamp_ms() {
+            return mgmt::Response::error(409, "lease expired");
+        }
+        // RFC 3203: FORCERENEW must be authenticated
+        if self.conf.authentication.is_none() || !auth::has_key(&mac) {
+            return mgmt::Response::error(409, "client does not authenticate");
+        }
+        match s.force_renew(mac, ip) {
+            Ok(_) => {
+                println!("sent FORCERENEW to {} at {:?}", mac, ip);
+                mgmt::Response::json(
+                    200,
+                    &serde_json::json!({ "mac": mac.to_string(), "ip": ip }),
+                )
+            }
+            Err(e) => mgmt::Response::error(503, &e.to_string()),
+        }
+    }
+    /// RFC 8925: a client that asks for option 108 is told to stay IPv6-only and gets no address.
+    fn v6_only(
+        &self,
+        s: &server::Server,
+        msg_type: options::MessageType,
+        req_packet: &packet::Packet,
+    ) -> bool {
+        let Some(wait) = self.v6_only_wait else {
+            return false;
+        };
+        if !req_packet.requests_option(options::IPV6_ONLY_PREFERRED) {
+            return false;
+        }
+        println!(
+            "{} prefers IPv6-only, no address offered",
+            req_packet.chaddr
+        );
+        let _ = s.reply(
+            msg_type,
+            vec![options::DhcpOption::Ipv6OnlyPreferred(wait)],
+            Ipv4Addr::new(0, 0, 0, 0),
+            req_packet.clone(),
+        );
+        true
+    }
+    /// The proxy DHCP answer to a PXE client's DISCOVER, or to its REQUEST on port 4011: no
+    /// address, only where to boot from. Discovery control 8 has the client load the file
+    /// right away instead of looking for boot servers.
+    fn proxy_packet(&self, req: &packet::Packet) -> Option<packet::Packet> {
+        if !req
+            .vendor_class()
+            .is_some_and(|vc| vc.starts_with(b"PXEClient"))
+        {
+            return None;
+        }
+        let t = match req.message_type() {
+            Ok(options::MessageType::Discover) => options::MessageType::Offer,
+            Ok(options::MessageType::Request | options::MessageType::Inform) => {
+                options::MessageType::Ack
+            }
+            _ => return None,
+        };
+        let classes = self.classes(req);
+        let Some(file) =
+            class_value(&classes, |c| c.boot_file.as_ref()).or(self.conf.boot_file.as_ref())
+        else {
+            println!("no boot_file for PXE client {}, ignored", req.chaddr);
+            return None;
+        };
+        let vars = template::Vars {
+            mac: req.chaddr,
+            ip: req.ciaddr,
+            hostname: None,
+        };
+        let file = template::expand(file, &vars);
+        let next_server = self.next_server(&classes).unwrap_or(self.conf.listen_addr);
+        let mut opts = vec![
+            options::DhcpOption::DhcpMessageType(t),
+            options::DhcpOption::ServerIdentifier(self.conf.listen_addr),
+            options::DhcpOption::VendorClassIdentifier(b"PXEClient".to_vec()),
+            options::DhcpOption::VendorSpecificInformation(vec![
+                options::PXE_DISCOVERY_CONTROL,
+                1,
+                8,
+                packet::END,
+            ]),
+        ];
+        if let Some(uuid) = req.option(options::CLIENT_MACHINE_ID) {
+            opts.push(uuid.clone());
+        }
+        opts.push(options::DhcpOption::BootfileName(file.clone()));
+        log::event(t, req.chaddr, req.ciaddr, req.xid, Some("proxy"));
+        Some(packet::Packet {
+            reply: true,
+            hops: 0,
+            xid: req.xid,
+            secs: 0,
+            broadcast: req.broadcast,
+            ciaddr: req.ciaddr,
+            yiaddr: Ipv4Addr::UNSPECIFIED,
+            siaddr: next_server,
+            giaddr: req.giaddr,
+            chaddr: req.chaddr,
+            sname: String::new(),
+            file,
+            options: opts,
+        })
+    }
+    fn next_server(&self, classes: &[&ClientClass]) -> Option<Ipv4Addr> {
+        class_value(classes, |c| c.next_server.as_ref())
+            .or(self.conf.next_server.as_ref())
+            .copied()
+    }
+    /// The subnet's routes followed by those of the matched classes, a later route to the same
+    /// destination replacing an earlier one. Clients that get option 121 ignore the router
+    /// option (RFC 3442), so the default route is added unless one is configured.
+    fn routes(&self, classes: &[&ClientClass]) -> Vec<options::StaticRoute> {
+        let mut routes: Vec<options::StaticRoute> = vec![];
+        let configured = self
+            .conf
+            .routes
+            .iter()
+            .chain(classes.iter().flat_map(|c| &c.routes));
+        for r in configured {
+            let route = options::StaticRoute {
+                dest: r.to.0,
+                prefix: r.to.1,
+                router: r.via,
+            };
+            match routes
+                .iter_mut()
+                .find(|o| o.dest == route.dest && o.prefix == route.prefix)
+            {
+                Some(o) => *o = route,
+                None => routes.push(route),
+            }
+        }
+        if !routes.is_empty() && !routes.iter().any(|r| r.prefix == 0) {
+            routes.insert(
+                0,
+                options::StaticRoute {
+                    dest: Ipv4Addr::UNSPECIFIED,
+                    prefix: 0,
+                    router: self.gateway_ip(),
+                },
+            );
+        }
+        routes
     }
     fn nak(&self, s: &server::Server, req_packet: packet::Packet, message: &str) {
         let _ = s.reply(
@@ -128,39 +1100,620 @@ impl Dhcpd {
         req_packet: packet::Packet,
         offer_ip: &Ipv4Addr,
     ) {
+        let classes = self.classes(&req_packet);
+        if !classes.is_empty() {
+            let names: Vec<&str> = classes.iter().map(|c| c.name.as_str()).collect();
+            println!("{} in classes {}", req_packet.chaddr, names.join(","));
+        }
+        let lease = self.lease_secs(&classes, &req_packet, offer_ip);
+        let (t1, t2) = self.renew_rebind_secs(lease);
+        let mut opts = vec![
+            options::DhcpOption::IpAddressLeaseTime(lease),
+            options::DhcpOption::RenewalTime(t1),
+            options::DhcpOption::RebindingTime(t2),
+        ];
+        if let Some(options::DhcpOption::ClientFqdn(fqdn)) = req_packet.option(options::CLIENT_FQDN)
+        {
+            opts.push(options::DhcpOption::ClientFqdn(
+                self.fqdn_reply(fqdn, offer_ip),
+            ));
+        }
+        // an ACK to a DISCOVER is a rapid commit, which the client needs to see
+        if msg_type == options::MessageType::Ack
+            && req_packet.message_type() == Ok(options::MessageType::Discover)
+        {
+            opts.push(options::DhcpOption::RapidCommit);
+        }
+        opts.extend(self.config_options(&classes, &req_packet, offer_ip));
+        let file = opts.iter().find_map(|o| match o {
+            options::DhcpOption::BootfileName(f) => Some(f.clone()),
+            _ => None,
+        });
+        let mut p = s.reply_packet(msg_type, opts, *offer_ip, req_packet);
+        if let Some(addr) = self.next_server(&classes) {
+            p.siaddr = addr;
+        }
+        p.file = file.unwrap_or_default();
+        let _ = s.send(p);
+    }
+
+    /// Answers a DHCPLEASEQUERY (RFC 4388) from a relay agent. Of several leases of one client
+    /// the longest running is returned, the others in option 92.
+    fn leasequery(&self, s: &server::Server, req: packet::Packet) {
+        if req.giaddr.is_unspecified() {
+            println!("LEASEQUERY without giaddr, ignored");
+            return;
+        }
+        let now = utils::now_timestamp_ms();
+        let found = self.leasequery_matches(&req);
+        let Some(&(ip, lease)) = found.first() else {
+            let t = if self.conf.subnet_contains(req.ciaddr) {
+                options::MessageType::LeaseUnassigned
+            } else {
+                options::MessageType::LeaseUnknown
+            };
+            let _ = s.reply(t, vec![], Ipv4Addr::UNSPECIFIED, req);
+            return;
+        };
+        let mut opts = self.lease_options(lease, now);
+        if found.len() > 1 {
+            let mut all: Vec<Ipv4Addr> = found.iter().map(|(ip, _)| *ip).collect();
+            all.sort();
+            opts.push(options::DhcpOption::AssociatedIp(all));
+        }
+        let mac = lease.mac;
+        let mut p = s.reply_packet(options::MessageType::LeaseActive, opts, ip, req);
+        p.ciaddr = ip;
+        p.yiaddr = Ipv4Addr::UNSPECIFIED;
+        p.chaddr = mac;
+        let _ = s.send(p);
+    }
+
+    /// The active leases a leasequery asks for, longest running first: by address when `ciaddr`
+    /// is set, else by client identifier, else by hardware address.
+    fn leasequery_matches(&self, query: &packet::Packet) -> Vec<(Ipv4Addr, &Lease)> {
+        let now = utils::now_timestamp_ms();
+        let mut found: Vec<(Ipv4Addr, &Lease)> = if !query.ciaddr.is_unspecified() {
+            self.leases
+                .get(&query.ciaddr)
+                .map(|l| (query.ciaddr, l))
+                .into_iter()
+                .collect()
+        } else if let Some(id) = query.client_id() {
+            let id: String = id.iter().map(|b| format!("{:02x}", b)).collect();
+            let id = Some(id);
+            self.leases
+                .iter()
+                .filter(|(_, l)| l.client_id == id)
+                .map(|(ip, l)| (*ip, l))
+                .collect()
+        } else {
+            self.leases
+                .iter()
+                .filter(|(_, l)| l.mac == query.chaddr)
+                .map(|(ip, l)| (*ip, l))
+                .collect()
+        };
+        found.retain(|(_, l)| l.expiry > now);
+        found.sort_by_key(|(_, l)| std::cmp::Reverse(l.expiry));
+        found
+    }
+
+    /// What a DHCPLEASEACTIVE tells about `lease`: the time left and the client identifier.
+    fn lease_options(&self, lease: &Lease, now: u128) -> Vec<options::DhcpOption> {
+        let remaining = ((lease.expiry - now) / 1000).min(u32::MAX as u128) as u32;
+        let mut opts = vec![options::DhcpOption::IpAddressLeaseTime(remaining)];
+        if let Some(id) = lease.client_id.as_deref().and_then(utils::parse_hex) {
+            opts.push(options::DhcpOption::ClientIdentifier(id));
+        }
+        opts
+    }
+
+    fn lease_active(
+        &self,
+        query: &packet::Packet,
+        ip: Ipv4Addr,
+        lease: &Lease,
+        now: u128,
+    ) -> packet::Packet {
+        let opts = self.lease_options(lease, now);
+        self.leasequery_packet(
+            options::MessageType::LeaseActive,
+            query,
+            ip,
+            lease.mac,
+            opts,
+        )
+    }
+
+    /// An answer about `ip` sent over a leasequery connection, see `leasequery.rs`.
+    fn leasequery_packet(
+        &self,
+        t: options::MessageType,
+        query: &packet::Packet,
+        ip: Ipv4Addr,
+        mac: HwAddr,
+        additional_options: Vec<options::DhcpOption>,
+    ) -> packet::Packet {
+        let mut opts = vec![
+            options::DhcpOption::DhcpMessageType(t),
+            options::DhcpOption::ServerIdentifier(self.conf.listen_addr),
+        ];
+        opts.extend(additional_options);
+        if let Some(options::DhcpOption::ParameterRequestList(prl)) =
+            query.option(options::PARAMETER_REQUEST_LIST)
+        {
+            server::filter_options_by_req(&mut opts, prl);
+        }
+        packet::Packet {
+            reply: true,
+            hops: 0,
+            xid: query.xid,
+            secs: 0,
+            broadcast: false,
+            ciaddr: ip,
+            yiaddr: Ipv4Addr::UNSPECIFIED,
+            siaddr: Ipv4Addr::UNSPECIFIED,
+            giaddr: query.giaddr,
+            chaddr: mac,
+            sname: String::new(),
+            file: String::new(),
+            options: opts,
+        }
+    }
+
+    /// The active leases as `lq_seen` keeps them.
+    fn active_leases(&self) -> HashMap<Ipv4Addr, (HwAddr, u128)> {
+        let now = utils::now_timestamp_ms();
+        self.leases
+            .iter()
+            .filter(|(_, l)| l.expiry > now)
+            .map(|(ip, l)| (*ip, (l.mac, l.expiry)))
+            .collect()
+    }
+
+    /// Pushes the leases that started, were renewed or ended since the last call to the active
+    /// leasequeries, an ended one as DHCPLEASEUNASSIGNED.
+    fn notify_leasequery(&self) {
+        if !leasequery::subscribed() {
+            return;
+        }
+        let now = utils::now_timestamp_ms();
+        let active = self.active_leases();
+        let mut seen = self.lq_seen.borrow_mut();
+        // the transaction id is each subscriber's own, filled in by `publish`
+        let query = packet::Packet {
+            reply: false,
+            hops: 0,
+            xid: 0,
+            secs: 0,
+            broadcast: false,
+            ciaddr: Ipv4Addr::UNSPECIFIED,
+            yiaddr: Ipv4Addr::UNSPECIFIED,
+            siaddr: Ipv4Addr::UNSPECIFIED,
+            giaddr: Ipv4Addr::UNSPECIFIED,
+            chaddr: HwAddr::default(),
+            sname: String::new(),
+            file: String::new(),
+            options: vec![],
+        };
+        for (ip, v) in &active {
+            if seen.get(ip) != Some(v) {
+                leasequery::publish(&self.lease_active(&query, *ip, &self.leases[ip], now));
+            }
+        }
+        for ip in seen.keys().filter(|ip| !active.contains_key(ip)) {
+            let t = options::MessageType::LeaseUnassigned;
+            leasequery::publish(&self.leasequery_packet(t, &query, *ip, HwAddr::default(), vec![]));
+        }
+        *seen = active;
+    }
+
+    /// Answers a BOOTP request. BOOTP clients never renew or release, so only the addresses
+    /// reserved for their hardware address are given out, for good.
+    fn bootp(&mut self, s: &server::Server, req: packet::Packet) {
+        let Some(r) = self
+            .reservations
+            .iter()
+            .find(|r| r.key.mac().is_some() && r.key.matches(&req.chaddr, None))
+            .cloned()
+        else {
+            println!(
+                "BOOTP request from {} without a reservation, ignored",
+                req.chaddr
+            );
+            return;
+        };
+        let client = ClientId::mac(req.chaddr);
+        if !self.leases.get(&r.ip).is_some_and(|l| client.owns(l)) {
+            let lease = Lease::new(req.chaddr, utils::now_timestamp_ms() + INFINITE_LEASE);
+            self.leases.insert(r.ip, lease);
+            self.save_leases();
+        }
+        let classes = self.classes(&req);
+        let opts = self.config_options(&classes, &req, &r.ip);
+        let file = opts.iter().find_map(|o| match o {
+            options::DhcpOption::BootfileName(f) => Some(f.clone()),
+            _ => None,
+        });
+        let mut p = s.bootp_reply(opts, r.ip, req);
+        if let Some(addr) = self.next_server(&classes) {
+            p.siaddr = addr;
+        }
+        p.file = file.unwrap_or_default();
+        let _ = s.send(p);
+    }
+
+    /// The option 81 answer to `fqdn`. With dynamic updates or a forward zone the server
+    /// publishes the A record under the lease's DNS name; otherwise it updates nothing and the
+    /// client is left to do its own updates.
+    fn fqdn_reply(&self, fqdn: &options::ClientFqdn, ip: &Ipv4Addr) -> options::ClientFqdn {
+        let encoding = fqdn.flags & options::FQDN_E;
+        let zones = self.conf.zones.as_ref();
+        let domain = self.conf.ddns.as_ref().map(|d| &d.zone).or(zones
+            .filter(|z| z.forward.is_some())
+            .and_then(|z| z.domain.as_ref()));
+        let Some(domain) = domain else {
+            return options::ClientFqdn {
+                flags: encoding | options::FQDN_N,
+                name: fqdn.name.clone(),
+            };
+        };
+        let label = self
+            .leases
+            .get(ip)
+            .and_then(|l| l.dns_name.clone())
+            .or_else(|| zone::sanitize(&fqdn.name));
+        let overridden = if fqdn.flags & options::FQDN_S == 0 {
+            options::FQDN_O
+        } else {
+            0
+        };
+        options::ClientFqdn {
+            flags: encoding | options::FQDN_S | overridden,
+            name: match label {
+                Some(label) => format!("{}.{}", label, domain.trim_end_matches('.')),
+                None => fqdn.name.clone(),
+            },
+        }
+    }
+
+    /// Offers `ip`, or binds it at once if the client asked for rapid commit and it is enabled.
+    fn offer(&mut self, s: &server::Server, req_packet: packet::Packet, ip: Ipv4Addr) {
+        if self.conf.rapid_commit && req_packet.option(options::RAPID_COMMIT).is_some() {
+            self.commit(ip, &req_packet);
+            self.reply(s, options::MessageType::Ack, req_packet, &ip);
+            return;
+        }
+        self.reply(s, options::MessageType::Offer, req_packet, &ip);
+    }
+
+    /// Answers a DHCPINFORM (RFC 2131 4.3.5): the configuration for the client's own address,
+    /// without a lease time and without touching the lease table.
+    fn inform(&self, s: &server::Server, req_packet: packet::Packet) {
+        let ip = req_packet.ciaddr;
+        if ip.is_unspecified() {
+            println!("INFORM from {} without ciaddr, ignored", req_packet.chaddr);
+            return;
+        }
+        let classes = self.classes(&req_packet);
+        let opts = self.config_options(&classes, &req_packet, &ip);
         let _ = s.reply(
-            msg_type,
-            vec![
-                options::DhcpOption::IpAddressLeaseTime(self.lease_secs()),
-                options::DhcpOption::SubnetMask(self.subnet_mask()),
-                options::DhcpOption::Router(vec![self.gateway_ip()]),
-                options::DhcpOption::DomainNameServer(self.dns_servers()),
-            ],
-            *offer_ip,
+            options::MessageType::Ack,
+            opts,
+            Ipv4Addr::UNSPECIFIED,
             req_packet,
         );
     }
+
+    /// Everything but the lease time that a client at `ip` gets configured with.
+    fn config_options(
+        &self,
+        classes: &[&ClientClass],
+        req_packet: &packet::Packet,
+        ip: &Ipv4Addr,
+    ) -> Vec<options::DhcpOption> {
+        let mut opts = vec![
+            options::DhcpOption::SubnetMask(self.subnet_mask()),
+            options::DhcpOption::BroadcastAddress(self.conf.broadcast),
+            options::DhcpOption::Router(vec![self.gateway_ip()]),
+            options::DhcpOption::DomainNameServer(self.dns_servers(classes)),
+        ];
+        let hostname = req_packet.host_name().and_then(zone::sanitize);
+        let vars = template::Vars {
+            mac: req_packet.chaddr,
+            ip: *ip,
+            hostname: self
+                .leases
+                .get(ip)
+                .filter(|l| ClientId::of(req_packet).owns(l))
+                .and_then(|l| l.dns_name.clone())
+                .or(hostname),
+        };
+        if let Some(vendor) = self.vendor_specific(req_packet, &vars) {
+            opts.push(vendor);
+        }
+        if let Some(addrs) = class_value(classes, |c| c.capwap_controllers.as_ref())
+            .or(self.conf.capwap_controllers.as_ref())
+        {
+            opts.push(options::DhcpOption::CapwapAcV4(addrs.clone()));
+        }
+        if let Some(vendor) = class_value(classes, |c| c.vendor_specific.as_ref()) {
+            opts.push(options::DhcpOption::VendorSpecificInformation(
+                vendor.to_bytes(&vars),
+            ));
+        }
+        // the first profile that applies wins (one option 43 per reply)
+        for profile in &self.conf.vendor_profiles {
+            if let Some(opt) = profiles::option_for(profile, req_packet) {
+                if !opts.iter().any(|o| o.code() == opt.code()) {
+                    opts.push(opt);
+                }
+            }
+        }
+        let routes = self.routes(classes);
+        if !routes.is_empty() {
+            opts.push(options::DhcpOption::ClasslessStaticRoute(routes));
+        }
+        if let Some(offset) = self.conf.time_offset {
+            opts.push(options::DhcpOption::TimeOffset(offset));
+        }
+        if let Some(tz) = &self.conf.tz_posix {
+            opts.push(options::DhcpOption::TzPosixString(tz.clone()));
+        }
+        if let Some(tz) = &self.conf.tz_database {
+            opts.push(options::DhcpOption::TzDatabaseString(tz.clone()));
+        }
+        if let Some(mtu) = self.conf.mtu {
+            opts.push(options::DhcpOption::InterfaceMtu(mtu));
+        }
+        if !self.conf.ntp_servers.is_empty() {
+            opts.push(options::DhcpOption::NtpServers(
+                self.conf.ntp_servers.clone(),
+            ));
+        }
+        if !self.conf.time_servers.is_empty() {
+            opts.push(options::DhcpOption::TimeServers(
+                self.conf.time_servers.clone(),
+            ));
+        }
+        if !self.conf.log_servers.is_empty() {
+            opts.push(options::DhcpOption::LogServers(
+                self.conf.log_servers.clone(),
+            ));
+        }
+        if let Ok(Some(sip)) = self.conf.sip_option() {
+            opts.push(options::DhcpOption::SipServers(sip));
+        }
+        if !self.conf.netbios_name_servers.is_empty() {
+            opts.push(options::DhcpOption::NetbiosNameServers(
+                self.conf.netbios_name_servers.clone(),
+            ));
+        }
+        if let Some(t) = self.conf.netbios_node_type {
+            opts.push(options::DhcpOption::NetbiosNodeType(t as u8));
+        }
+        if let Some(domain) = &self.conf.domain {
+            opts.push(options::DhcpOption::DomainName(domain.clone()));
+        }
+        if !self.conf.search_domains.is_empty() {
+            opts.push(options::DhcpOption::DomainSearch(
+                self.conf.search_domains.clone(),
+            ));
+        }
+        if let Some(url) = &self.conf.wpad_url {
+            opts.push(options::DhcpOption::Wpad(url.clone()));
+        }
+        let tftp_server = self
+            .conf
+            .tftp_server
+            .clone()
+            .or(self.next_server(classes).map(|a| a.to_string()));
+        if let Some(name) = tftp_server {
+            opts.push(options::DhcpOption::TftpServerName(name));
+        }
+        if let Some(addrs) =
+            class_value(classes, |c| c.tftp_servers.as_ref()).or(self.conf.tftp_servers.as_ref())
+        {
+            opts.push(options::DhcpOption::TftpServerAddrs(addrs.clone()));
+        }
+        if let Some(file) =
+            class_value(classes, |c| c.boot_file.as_ref()).or(self.conf.boot_file.as_ref())
+        {
+            opts.push(options::DhcpOption::BootfileName(template::expand(
+                file, &vars,
+            )));
+        }
+        if let Some(url) = class_value(classes, |c| c.captive_portal_url.as_ref())
+            .or(self.conf.captive_portal_url.as_ref())
+        {
+            opts.push(options::DhcpOption::CaptivePortal(template::expand(
+                url, &vars,
+            )));
+        }
+        // the first class that sets a code wins, then the top-level `options`
+        let raw = classes.iter().flat_map(|c| &c.options);
+        let mut seen = vec![];
+        for o in raw.chain(&self.conf.options) {
+            if seen.contains(&o.code) {
+                continue;
+            }
+            seen.push(o.code);
+            opts.retain(|x| x.code() != o.code);
+            opts.push(options::DhcpOption::Unrecognized(options::RawDhcpOption {
+                code: o.code,
+                data: o.value.to_bytes(&vars),
+            }));
+        }
+        opts
+    }
 }
 
 impl server::Handler for Dhcpd {
+    /// Takes the names of leases that ran out off the zones and the DNS server.
+    fn tick(&mut self) {
+        self.reload_reservations();
+        let now = utils::now_timestamp_ms();
+        if now < self.last_swept + SWEEP_INTERVAL {
+            return;
+        }
+        self.last_swept = now;
+        self.publish_zones();
+        self.update_dns();
+        self.notify_leasequery();
+    }
+
+    fn proxy_reply(&mut self, request: &packet::Packet) -> Option<packet::Packet> {
+        if !self.conf.proxy_dhcp || request.reply {
+            return None;
+        }
+        self.proxy_packet(request)
+    }
+
+    /// Answers a bulk leasequery with every lease it matches, and an active leasequery with
+    /// all active leases; the changes after that follow through `notify_leasequery`.
+    fn bulk_leasequery(
+        &mut self,
+        query: &packet::Packet,
+    ) -> Result<Vec<packet::Packet>, leasequery::Status> {
+        let now = utils::now_timestamp_ms();
+        if query.message_type() == Ok(options::MessageType::ActiveLeaseQuery) {
+            self.notify_leasequery();
+            let active = self.active_leases();
+            let mut replies = vec![];
+            for ip in active.keys() {
+                replies.push(self.lease_active(query, *ip, &self.leases[ip], now));
+            }
+            *self.lq_seen.borrow_mut() = active;
+            return Ok(replies);
+        }
+        for code in [options::AGENT_REMOTE_ID, options::AGENT_RELAY_ID] {
+            if query.relay_suboption(code).is_some() {
+                return Err((
+                    leasequery::STATUS_NOT_ALLOWED,
+                    "queries by remote-id or relay-id are not supported".to_string(),
+                ));
+            }
+        }
+        if query.ciaddr.is_unspecified()
+            && query.client_id().is_none()
+            && query.chaddr.is_unspecified()
+        {
+            return Err((
+                leasequery::STATUS_MALFORMED_QUERY,
+                "no address, client identifier or hardware address".to_string(),
+            ));
+        }
+        let found = self.leasequery_matches(query);
+        if found.is_empty() && !query.ciaddr.is_unspecified() {
+            let t = if self.conf.subnet_contains(query.ciaddr) {
+                options::MessageType::LeaseUnassigned
+            } else {
+                options::MessageType::LeaseUnknown
+            };
+            return Ok(vec![self.leasequery_packet(
+                t,
+                query,
+                query.ciaddr,
+                HwAddr::default(),
+                vec![],
+            )]);
+        }
+        Ok(found
+            .iter()
+            .map(|(ip, lease)| self.lease_active(query, *ip, lease, now))
+            .collect())
+    }
+
+    fn dump(&self, out: &mut String) {
+        let now = utils::now_timestamp_ms();
+        let pool = self.pool_json();
+        out.push_str(&format!(
+            "== {} pool {} - {}: {} of {} leased, {} outside{}\n",
+            self.conf.intf,
+            self.conf.start,
+            self.conf.end,
+            pool["leased"],
+            pool["size"],
+            pool["outside"],
+            if self.drain.is_some() {
+                ", draining"
+            } else {
+                ""
+            },
+        ));
+        let mut leases: Vec<(&Ipv4Addr, &Lease)> = self.leases.iter().collect();
+        leases.sort_by_key(|(ip, _)| **ip);
+        for (ip, l) in leases {
+            let expires = if l.expiry > now {
+                format!("{}s", (l.expiry - now) / 1000)
+            } else {
+                "expired".to_string()
+            };
+            out.push_str(&format!(
+                "{:<15} {} {:>12} {}\n",
+                ip,
+                l.mac,
+                expires,
+                l.dns_name.as_deref().unwrap_or("-"),
+            ));
+        }
+    }
+
     fn handle_request(&mut self, server: &server::Server, in_packet: packet::Packet) {
+        if utils::now_timestamp_ms() < self.active_after {
+            self.learn(&in_packet);
+            return;
+        }
+        // replies from other servers are only of interest while taking over
+        if in_packet.reply {
+            return;
+        }
+        // The client asked for a specific subnet (option 118 / 82.5) that is not ours
+        if let Some(subnet) = in_packet.selected_subnet() {
+            if !self.on_network(subnet) {
+                println!("{:?} not served here, ignored", subnet);
+                return;
+            }
+        } else if !in_packet.giaddr.is_unspecified() && !self.on_network(in_packet.giaddr) {
+            // without link selection the client sits on the relay's subnet
+            println!("relay {:?} not in our subnet, ignored", in_packet.giaddr);
+            return;
+        }
+        let echo = class_value(&self.classes(&in_packet), |c| c.echo_client_id.as_ref())
+            .copied()
+            .unwrap_or(self.conf.echo_client_id);
+        server.echo_client_id(echo);
+        if self.conf.proxy_dhcp {
+            if in_packet.message_type() == Ok(options::MessageType::Discover) {
+                if let Some(p) = self.proxy_packet(&in_packet) {
+                    let _ = server.send(p);
+                }
+            }
+            return;
+        }
         match in_packet.message_type() {
             Ok(options::MessageType::Discover) => {
+                if self.v6_only(server, options::MessageType::Offer, &in_packet) {
+                    return;
+                }
+                let client = ClientId::of(&in_packet);
+                self.drop_retired(&client);
+                self.claim_reservation(&in_packet);
                 // Otherwise prefer existing (including expired if available)
-                if let Some(ip) = self.current_lease(&in_packet.chaddr) {
+                if let Some(ip) = self.current_lease(&client) {
                     println!("Sending Reply to discover");
-                    self.reply(server, options::MessageType::Offer, in_packet, &ip);
+                    self.offer(server, in_packet, ip);
+                    return;
+                }
+                if self.drain.is_some() {
+                    println!("draining, no new lease for {}", in_packet.chaddr);
                     return;
                 }
                 // Otherwise choose a free ip if available
-                for _ in 0..self.lease_nums() {
-                    self.last_lease = (self.last_lease + 1) % self.lease_nums();
-                    let off_ip = (self.start_num() + &self.last_lease).into();
-                    if self.available(&in_packet.chaddr, &off_ip) {
-                        println!("{:?} is available, send to discover", off_ip);
-                        self.reply(server, options::MessageType::Offer, in_packet, &off_ip);
-                        break;
-                    }
+                if let Some(off_ip) = self.free_address(&client) {
+                    println!("{:?} is available, send to discover", off_ip);
+                    self.offer(server, in_packet, off_ip);
                 }
             }
 
@@ -171,29 +1724,34 @@ impl server::Handler for Dhcpd {
                     // return;
                 }
 
-                let req_ip = match in_packet.option(options::REQUESTED_IP_ADDRESS) {
-                    Some(options::DhcpOption::RequestedIpAddress(x)) => *x,
-                    _ => in_packet.ciaddr,
-                };
+                if self.v6_only(server, options::MessageType::Ack, &in_packet) {
+                    return;
+                }
+                let client = ClientId::of(&in_packet);
+                self.drop_retired(&client);
+                self.claim_reservation(&in_packet);
+
+                let req_ip = in_packet.requested_ip();
                 // for (ip, (mac, _)) in &self.leases {
                 //     println!("IP: {:?}, MAC: {:?}", ip, mac);
                 // }
-                if let Some(ip) = self.current_lease(&in_packet.chaddr) {
+                if let Some(ip) = self.current_lease(&client) {
                     println!("Found Current Lease: {:?}", &ip);
+                    self.commit(ip, &in_packet);
                     self.reply(server, options::MessageType::Ack, in_packet, &ip);
                     return;
                 }
-                if !&self.available(&in_packet.chaddr, &req_ip) {
+                if self.drain.is_some() {
+                    self.nak(server, in_packet, "Server is draining");
+                    return;
+                }
+                if !&self.available(&client, &req_ip) {
                     println!("Sending Reply by Request Msg for 'Requested IP not available'");
                     self.nak(server, in_packet, "Requested IP not available");
                     return;
                 }
                 println!("insert into leases: {:?}", req_ip);
-                self.leases.insert(
-                    req_ip,
-                    Lease::new(in_packet.chaddr, utils::now_timestamp_ms()),
-                );
-                self.save_leases();
+                self.commit(req_ip, &in_packet);
                 println!("Sending Reply by Request Msg for {:?}", &req_ip);
                 self.reply(server, options::MessageType::Ack, in_packet, &req_ip);
             }
@@ -203,60 +1761,300 @@ impl server::Handler for Dhcpd {
                 if !server.for_this_server(&in_packet) {
                     return;
                 }
-                if let Some(ip) = self.current_lease(&in_packet.chaddr) {
+                if let Some(ip) = self.current_lease(&ClientId::of(&in_packet)) {
+                    if let Ok(t) = in_packet.message_type() {
+                        log::event(t, in_packet.chaddr, ip, in_packet.xid, None);
+                    }
+                    self.lease_event("released", in_packet.chaddr, ip, None);
                     self.leases.remove(&ip);
                     self.save_leases();
                 }
             }
 
-            // TODO - not necessary but support for dhcp4r::INFORM might be nice
+            Ok(options::MessageType::Inform) => self.inform(server, in_packet),
+
+            Ok(options::MessageType::LeaseQuery) => self.leasequery(server, in_packet),
+
+            Err(_) if in_packet.option(options::DHCP_MESSAGE_TYPE).is_none() => {
+                self.bootp(server, in_packet)
+            }
+
             _ => {}
         }
     }
+
+    fn handle_mgmt(&mut self, server: &server::Server, req: mgmt::Request) -> mgmt::Response {
+        match (req.method.as_str(), req.segments().as_slice()) {
+            ("GET", ["leases"]) => {
+                let mut leases: Vec<(&Ipv4Addr, &Lease)> = self.leases.iter().collect();
+                leases.sort_by_key(|(ip, _)| **ip);
+                let leases: Vec<serde_json::Value> =
+                    leases.iter().map(|(ip, l)| l.host_json(ip)).collect();
+                mgmt::Response::json(200, &leases)
+            }
+            ("GET", ["leases", mac]) => match HwAddr::from_str(mac) {
+                Ok(mac) => match self.current_lease(&ClientId::mac(mac)) {
+                    Some(ip) => mgmt::Response::json(200, &self.leases[&ip].host_json(&ip)),
+                    None => mgmt::Response::not_found(),
+                },
+                Err(_) => mgmt::Response::error(400, "invalid hardware address"),
+            },
+            ("POST", ["leases", mac, "forcerenew"]) => self.force_renew(server, mac),
+            ("GET", ["healthz"]) => {
+                mgmt::Response::json(200, &serde_json::json!({ "status": "ok" }))
+            }
+            ("GET", ["readyz"]) => {
+                let (ready, details) = self.readiness();
+                mgmt::Response::json(if ready { 200 } else { 503 }, &details)
+            }
+            ("GET", ["stats"]) => mgmt::Response::json(200, &stats::snapshot()),
+            ("GET", ["pool"]) => self.pool(),
+            ("PUT", ["pool"]) => match serde_json::from_slice::<PoolRange>(&req.body) {
+                // another tenant's subnet: let the next server answer
+                Ok(pool) if !self.conf.subnet_contains(pool.start) => mgmt::Response::not_found(),
+                Ok(pool) => self.resize(pool),
+                Err(e) => mgmt::Response::error(400, &format!("invalid pool: {}", e)),
+            },
+            ("POST", ["drain"]) => match drain_lease_time(&req.body) {
+                Ok(d) => {
+                    self.start_drain(d);
+                    self.pool()
+                }
+                Err(response) => response,
+            },
+            ("POST", ["resume"]) => {
+                self.resume();
+                self.pool()
+            }
+            ("GET", ["snapshot"]) => mgmt::Response::json(200, &Snapshot::new(self.snapshot())),
+            ("PUT", ["snapshot"]) => match Snapshot::parse(&req.body) {
+                Ok(snapshot) => {
+                    let restored = self.restore(&snapshot.leases);
+                    println!("restored {} lease(s) from snapshot", restored);
+                    mgmt::Response::json(200, &serde_json::json!({ "restored": restored }))
+                }
+                Err(response) => response,
+            },
+            _ => mgmt::Response::not_found(),
+        }
+    }
+}
+
+/// The first value set by any of the matched classes.
+fn class_value<'a, T>(
+    classes: &[&'a ClientClass],
+    f: impl Fn(&'a ClientClass) -> Option<&'a T>,
+) -> Option<&'a T> {
+    classes.iter().find_map(|c| f(c))
+}
+
+fn class_matches(class: &ClientClass, req_packet: &packet::Packet) -> bool {
+    let m = &class.matches;
+    if !m.relay.is_empty() && !m.relay.contains(&req_packet.giaddr) {
+        return false;
+    }
+    if let Some(text) = &m.circuit_id {
+        let id = req_packet
+            .relay_suboption(options::AGENT_CIRCUIT_ID)
+            .unwrap_or_default();
+        if !text.is_empty() && !id.windows(text.len()).any(|w| w == text.as_bytes()) {
+            return false;
+        }
+    }
+    if !m.mac.is_empty()
+        && !m
+            .mac
+            .iter()
+            .filter_map(|mac| HwAddr::from_str(mac).ok())
+            .any(|mac| mac == req_packet.chaddr)
+    {
+        return false;
+    }
+    if let Some(prefix) = &m.vendor_class {
+        match req_packet.option(options::VENDOR_CLASS_IDENTIFIER) {
+            Some(options::DhcpOption::VendorClassIdentifier(vc))
+                if vc.starts_with(prefix.as_bytes()) => {}
+            _ => return false,
+        }
+    }
+    if !m.arch.is_empty() {
+        match req_packet.option(options::CLIENT_ARCHITECTURE) {
+            Some(options::DhcpOption::ClientArchitecture(archs))
+                if archs.iter().any(|a| m.arch.contains(a)) => {}
+            _ => return false,
+        }
+    }
+    if let Some(uc) = &m.user_class {
+        if !req_packet
+            .user_classes()
+            .iter()
+            .any(|c| c.as_slice() == uc.as_bytes())
+        {
+            return false;
+        }
+    }
+    true
 }
 
 fn load_leases(
-    leases_static: &str,
-    leases_file: &str,
-    start: u32,
-    end: u32,
-) -> (Result<HashMap<Ipv4Addr, Lease>, Box<dyn Error>>, u32) {
-    let mut leases: HashMap<Ipv4Addr, Lease> = HashMap::new();
-    let mut last_lease = 0;
+    conf: &Config,
+    reserved: &[reservations::Reservation],
+) -> Result<Leases, Box<dyn Error>> {
+    let mut leases = load_lease_file(&conf.lease_file);
+    // reservations by client identifier are bound when their client shows up
+    for r in reserved {
+        if let Some(mac) = r.key.mac() {
+            leases.insert(
+                r.ip,
+                Lease::new(mac, utils::now_timestamp_ms() + INFINITE_LEASE),
+            );
+        }
+    }
+
+    Ok(leases)
+}
+
+/// The renewal lease time of a drain request body, `{"lease_time": "5m"}`; an empty body
+/// gets the default.
+pub fn drain_lease_time(body: &[u8]) -> Result<Duration, mgmt::Response> {
+    #[derive(Deserialize)]
+    struct DrainRequest {
+        lease_time: Option<String>,
+    }
+    let lease_time = if body.is_empty() {
+        None
+    } else {
+        serde_json::from_slice::<DrainRequest>(body)
+            .map_err(|e| mgmt::Response::error(400, &format!("invalid drain request: {}", e)))?
+            .lease_time
+    };
+    parse(lease_time.as_deref().unwrap_or(DEFAULT_DRAIN_LEASE))
+        .map_err(|e| mgmt::Response::error(400, &format!("invalid lease_time: {}", e)))
+}
+
+fn modified(path: &str) -> Option<SystemTime> {
+    std::fs::metadata(path).and_then(|m| m.modified()).ok()
+}
+
+/// `start`-`end` followed by the further `ranges`, each with its end excluded.
+fn pool_ranges(conf: &Config) -> Vec<(u32, u32)> {
+    let extra = conf.ranges.iter();
+    std::iter::once((u32::from(conf.start), u32::from(conf.end)))
+        .chain(extra.map(|(s, e)| (u32::from(*s), u32::from(*e) + 1)))
+        .collect()
+}
+
+/// The position of `ip` among the addresses of `ranges`, the inverse of `nth_address`.
+fn pool_index(ranges: &[(u32, u32)], ip: Ipv4Addr) -> Option<u32> {
+    let ux = u32::from(ip);
+    let mut base = 0;
+    for &(s, e) in ranges {
+        if (s..e).contains(&ux) {
+            return Some(base + ux - s);
+        }
+        base += e - s;
+    }
+    None
+}
 
+/// The pool index of the highest leased address, where sequential allocation carries on.
+fn last_index(ranges: &[(u32, u32)], leases: &Leases) -> u32 {
+    leases
+        .keys()
+        .filter_map(|ip| pool_index(ranges, *ip))
+        .max()
+        .unwrap_or(0)
+}
+
+fn pool_file(conf: &Config) -> String {
+    format!("{}.pool", conf.lease_file)
+}
+
+fn load_pool(path: &str) -> Option<PoolRange> {
+    let file = File::open(path).ok()?;
+    serde_json::from_reader(BufReader::new(file)).ok()
+}
+
+/// Reads the persisted lease table, returning an empty table if it is missing or unreadable.
+pub fn load_lease_file(leases_file: &str) -> Leases {
     if Path::new(leases_file).exists() {
         if let Ok(lf) = File::open(leases_file) {
             let reader = BufReader::new(lf);
             if let Ok(obj) = serde_json::from_reader(reader) {
-                leases.clone_from(&obj);
+                return obj;
             }
         }
     }
+    HashMap::new()
+}
 
-    for (k, _) in &leases {
-        let ux: u32 = k.clone().into();
-        if ux > last_lease && ux > start && ux < end {
-            last_lease = ux;
-        }
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// `.100`-`.109` from `start`/`end`, `.200`-`.204` from `ranges`, `.105` excluded.
+    fn pool() -> Dhcpd {
+        let conf = crate::config::from_value(
+            serde_yaml::from_str(
+                "
+intf: lo
+listen_addr: 192.168.10.1
+start: 192.168.10.100
+end: 192.168.10.110
+ranges: [192.168.10.200-192.168.10.204]
+exclude: [192.168.10.105]
+netmask: 255.255.255.0
+gateway: 192.168.10.1
+broadcast: 192.168.10.255
+dns_servers: [192.168.10.1]
+lease_static: /nonexistent/rdhcpd-test.static
+lease_file: /nonexistent/rdhcpd-test.leases
+lease_time: 1h
+",
+            )
+            .unwrap(),
+        )
+        .unwrap();
+        Dhcpd::new(conf)
     }
-    if Path::new(leases_static).exists() {
-        if let Ok(file) = File::open(leases_static) {
-            let sreader = BufReader::new(file);
-            for line in sreader.lines() {
-                if let Ok(line) = line {
-                    let parts: Vec<&str> = line.split(',').collect();
-                    if parts.len() == 2 {
-                        let mac = MacAddress::from_str(parts[0]).unwrap();
-                        let ip = parts[1].trim().parse::<Ipv4Addr>().unwrap();
-                        leases.insert(
-                            ip,
-                            Lease::new(mac.bytes(), utils::now_timestamp_ms() + INFINITE_LEASE),
-                        );
-                    }
-                }
-            }
+
+    fn ip(last: u8) -> Ipv4Addr {
+        Ipv4Addr::new(192, 168, 10, last)
+    }
+
+    #[test]
+    fn ranges_include_their_last_address() {
+        let d = pool();
+        let net = u32::from(ip(0));
+        assert_eq!(d.ranges, [(net + 100, net + 110), (net + 200, net + 205)]);
+        assert!(d.in_pool(&ip(100)) && d.in_pool(&ip(109)) && !d.in_pool(&ip(110)));
+        assert!(d.in_pool(&ip(200)) && d.in_pool(&ip(204)) && !d.in_pool(&ip(205)));
+        assert!(!d.in_pool(&ip(105)));
+    }
+
+    #[test]
+    fn pool_index_counts_across_ranges() {
+        let d = pool();
+        for (n, last) in [(0, 100), (9, 109), (10, 200), (14, 204)] {
+            assert_eq!(pool_index(&d.ranges, ip(last)), Some(n));
+            assert_eq!(d.nth_address(n), ip(last));
+        }
+        for last in [99, 110, 199, 205] {
+            assert_eq!(pool_index(&d.ranges, ip(last)), None);
         }
     }
 
-    (Ok(leases), last_lease)
+    #[test]
+    fn last_index_ignores_addresses_outside_the_pool() {
+        let d = pool();
+        let mut leases = Leases::new();
+        assert_eq!(last_index(&d.ranges, &leases), 0);
+        for last in [103, 202, 250] {
+            leases.insert(
+                ip(last),
+                Lease::new(HwAddr::new(1, &[2, 0, 0, 0, 0, last]), 0),
+            );
+        }
+        assert_eq!(last_index(&d.ranges, &leases), 12);
+    }
 }
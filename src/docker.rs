@@ -0,0 +1,91 @@
+//! Keeps the addresses of Docker containers out of the pool.
+//!
+//! The containers attached to a Docker network are read from the Engine API on its unix socket
+//! every `interval`; their addresses are then never offered, even when they lie in the pool.
+
+use crate::config::DockerConf;
+
+use std::cell::RefCell;
+use std::collections::HashSet;
+use std::io::{self, Read, Write};
+use std::net::Ipv4Addr;
+use std::os::unix::net::UnixStream;
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+
+type Addrs = Arc<Mutex<HashSet<Ipv4Addr>>>;
+
+thread_local! {
+    /// The container addresses of the instance served by this thread, see `instance.rs`.
+    static EXCLUDED: RefCell<Option<Addrs>> = const { RefCell::new(None) };
+}
+
+/// Starts following the network's containers for the calling thread's instance.
+pub fn spawn(conf: DockerConf) {
+    let addrs = Addrs::default();
+    EXCLUDED.with(|e| *e.borrow_mut() = Some(addrs.clone()));
+    let interval = duration_str::parse(conf.interval.as_str()).unwrap();
+    thread::spawn(move || loop {
+        match containers(&conf.socket, &conf.network) {
+            Ok(found) => {
+                let mut addrs = addrs.lock().unwrap();
+                if *addrs != found {
+                    println!(
+                        "docker network {}: {} container address(es) excluded",
+                        conf.network,
+                        found.len()
+                    );
+                    *addrs = found;
+                }
+            }
+            Err(e) => println!("WARN: docker {}: {}", conf.socket, e),
+        }
+        thread::sleep(interval);
+    });
+}
+
+/// Whether `ip` belongs to a container on the followed network.
+pub fn excluded(ip: &Ipv4Addr) -> bool {
+    EXCLUDED.with(|e| match &*e.borrow() {
+        Some(addrs) => addrs.lock().unwrap().contains(ip),
+        None => false,
+    })
+}
+
+/// The IPv4 addresses of the containers attached to `network`, from `GET /networks/<network>`.
+fn containers(socket: &str, network: &str) -> io::Result<HashSet<Ipv4Addr>> {
+    let invalid = |m: String| io::Error::new(io::ErrorKind::InvalidData, m);
+    let mut stream = UnixStream::connect(socket)?;
+    stream.set_read_timeout(Some(Duration::from_secs(5)))?;
+    // HTTP/1.0 keeps the response unchunked
+    write!(
+        stream,
+        "GET /networks/{} HTTP/1.0\r\nHost: docker\r\n\r\n",
+        network
+    )?;
+    let mut response = vec![];
+    stream.read_to_end(&mut response)?;
+    let end = response
+        .windows(4)
+        .position(|w| w == b"\r\n\r\n")
+        .ok_or_else(|| invalid("truncated response".to_string()))?;
+    let head = String::from_utf8_lossy(&response[..end]);
+    let status = head.lines().next().unwrap_or("");
+    if status.split_whitespace().nth(1) != Some("200") {
+        return Err(invalid(status.to_string()));
+    }
+    let body: serde_json::Value =
+        serde_json::from_slice(&response[end + 4..]).map_err(|e| invalid(e.to_string()))?;
+    let mut addrs = HashSet::new();
+    if let Some(containers) = body["Containers"].as_object() {
+        for c in containers.values() {
+            // "172.18.0.2/16"
+            let addr = c["IPv4Address"].as_str().unwrap_or("");
+            if let Ok(ip) = addr.split('/').next().unwrap_or("").parse() {
+                addrs.insert(ip);
+            }
+        }
+    }
+    Ok(addrs)
+}
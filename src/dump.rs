@@ -0,0 +1,59 @@
+//! State dump on SIGUSR1: leases, pool usage and queue depths of every instance, written to
+//! stdout or to the instance's `dump_file`.
+
+use std::cell::RefCell;
+use std::fs;
+use std::sync::atomic::{AtomicU64, Ordering};
+
+/// Bumped by the signal handler; each serve loop dumps once per change.
+static REQUESTS: AtomicU64 = AtomicU64::new(0);
+
+thread_local! {
+    /// Where the instance served by this thread writes its dump, see `instance.rs`.
+    static TARGET: RefCell<Option<String>> = const { RefCell::new(None) };
+}
+
+extern "C" fn on_signal(_: libc::c_int) {
+    REQUESTS.fetch_add(1, Ordering::Relaxed);
+}
+
+/// Installs the SIGUSR1 handler for the whole process.
+pub fn install() {
+    unsafe {
+        let mut action: libc::sigaction = std::mem::zeroed();
+        action.sa_sigaction = on_signal as extern "C" fn(libc::c_int) as libc::sighandler_t;
+        action.sa_flags = libc::SA_RESTART;
+        libc::sigemptyset(&mut action.sa_mask);
+        if libc::sigaction(libc::SIGUSR1, &action, std::ptr::null_mut()) != 0 {
+            println!(
+                "WARN: SIGUSR1 handler not installed: {:?}",
+                std::io::Error::last_os_error()
+            );
+        }
+    }
+}
+
+/// Sends this thread's dumps to `path` instead of stdout.
+pub fn enter(path: Option<String>) {
+    TARGET.with(|t| *t.borrow_mut() = path);
+}
+
+/// Whether a dump was asked for since `seen` was last updated.
+pub fn requested(seen: &mut u64) -> bool {
+    let n = REQUESTS.load(Ordering::Relaxed);
+    if n == *seen {
+        return false;
+    }
+    *seen = n;
+    true
+}
+
+pub fn write(text: &str) {
+    TARGET.with(|t| match &*t.borrow() {
+        Some(path) => match fs::write(path, text) {
+            Ok(_) => println!("state dumped to {}", path),
+            Err(e) => println!("ERROR: dump to {} failed: {:?}", path, e),
+        },
+        None => print!("{}", text),
+    });
+}
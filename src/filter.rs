@@ -0,0 +1,61 @@
+//! In-kernel pre-filter for the DHCP socket (Linux classic BPF, `SO_ATTACH_FILTER`).
+//!
+//! Drops what the server would discard anyway so it never reaches userspace: anything shorter
+//! than a BOOTP header, BOOTREPLYs and hardware addresses longer than `chaddr`. The magic cookie
+//! is left to the parser, as BOOTP clients may send none (RFC 951). Helps under broadcast storms.
+
+use std::io;
+use std::net::UdpSocket;
+
+/// Offset of the DHCP message; a UDP socket filter sees the packet from the UDP header on.
+const O: u32 = 8;
+
+#[cfg(target_os = "linux")]
+pub fn attach(socket: &UdpSocket) -> io::Result<()> {
+    use std::os::fd::AsRawFd;
+
+    const LD_W_LEN: u16 = 0x80;
+    const LD_B_ABS: u16 = 0x30;
+    const JEQ_K: u16 = 0x15;
+    const JGE_K: u16 = 0x35;
+    const JGT_K: u16 = 0x25;
+    const RET_K: u16 = 0x06;
+
+    let op = |code: u16, jt: u8, jf: u8, k: u32| libc::sock_filter { code, jt, jf, k };
+    // jumps are relative; every failed check lands on the final `ret 0`
+    let mut prog = [
+        op(LD_W_LEN, 0, 0, 0),
+        op(JGE_K, 0, 5, O + 236),
+        op(LD_B_ABS, 0, 0, O),
+        op(JEQ_K, 0, 3, 1), // BOOTREQUEST
+        op(LD_B_ABS, 0, 0, O + 2),
+        op(JGT_K, 1, 0, 16), // hlen
+        op(RET_K, 0, 0, u32::MAX),
+        op(RET_K, 0, 0, 0),
+    ];
+    let fprog = libc::sock_fprog {
+        len: prog.len() as u16,
+        filter: prog.as_mut_ptr(),
+    };
+    let r = unsafe {
+        libc::setsockopt(
+            socket.as_raw_fd(),
+            libc::SOL_SOCKET,
+            libc::SO_ATTACH_FILTER,
+            &fprog as *const _ as *const libc::c_void,
+            std::mem::size_of_val(&fprog) as libc::socklen_t,
+        )
+    };
+    if r < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+#[cfg(not(target_os = "linux"))]
+pub fn attach(_socket: &UdpSocket) -> io::Result<()> {
+    Err(io::Error::new(
+        io::ErrorKind::Unsupported,
+        "socket_filter is only supported on Linux",
+    ))
+}
@@ -0,0 +1,131 @@
+//! Client hardware addresses of any type (`htype`) and length (`hlen`, up to the 16 bytes of
+//! `chaddr`).
+//!
+//! Ethernet addresses print as `AA:BB:CC:DD:EE:FF` and are stored as before; others (EUI-64,
+//! IEEE 802, ...) print as `hw:` followed by the type and the address in hex, e.g.
+//! `hw:1b:02:10:18:ff:fe:00:00:01`. InfiniBand clients send no address at all (RFC 4390) and go
+//! by their client identifier.
+
+use crate::utils;
+
+use serde::de::{self, Deserializer, SeqAccess, Visitor};
+use serde::ser::{SerializeSeq, Serializer};
+use serde::{Deserialize, Serialize};
+
+use std::fmt;
+use std::str::FromStr;
+
+pub const ETHERNET: u8 = 1;
+pub const INFINIBAND: u8 = 32;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+pub struct HwAddr {
+    pub htype: u8,
+    len: u8,
+    bytes: [u8; 16],
+}
+
+impl HwAddr {
+    /// The first 16 bytes of `addr`, of type `htype`.
+    pub fn new(htype: u8, addr: &[u8]) -> HwAddr {
+        let len = addr.len().min(16);
+        let mut bytes = [0; 16];
+        bytes[..len].copy_from_slice(&addr[..len]);
+        HwAddr {
+            htype,
+            len: len as u8,
+            bytes,
+        }
+    }
+
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.bytes[..self.len as usize]
+    }
+
+    /// Whether the address is missing or all zero, as in leasequeries by IP or client identifier.
+    pub fn is_unspecified(&self) -> bool {
+        self.as_bytes().iter().all(|b| *b == 0)
+    }
+
+    fn is_ethernet(&self) -> bool {
+        self.htype == ETHERNET && self.len == 6
+    }
+}
+
+impl fmt::Display for HwAddr {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if self.is_ethernet() {
+            let hex: Vec<String> = self
+                .as_bytes()
+                .iter()
+                .map(|b| format!("{:02X}", b))
+                .collect();
+            return write!(f, "{}", hex.join(":"));
+        }
+        write!(f, "hw:{:02x}", self.htype)?;
+        for b in self.as_bytes() {
+            write!(f, ":{:02x}", b)?;
+        }
+        Ok(())
+    }
+}
+
+impl FromStr for HwAddr {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<HwAddr, String> {
+        let invalid = || format!("invalid hardware address '{}'", s);
+        match s.strip_prefix("hw:") {
+            Some(h) => match utils::parse_hex(h).as_deref() {
+                Some([htype, addr @ ..]) if addr.len() <= 16 => Ok(HwAddr::new(*htype, addr)),
+                _ => Err(invalid()),
+            },
+            None => match utils::parse_hex(s) {
+                Some(mac) if mac.len() == 6 && s.len() == 17 => Ok(HwAddr::new(ETHERNET, &mac)),
+                _ => Err(invalid()),
+            },
+        }
+    }
+}
+
+/// Ethernet addresses as the byte array older lease files hold, others as text.
+impl Serialize for HwAddr {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        if !self.is_ethernet() {
+            return serializer.serialize_str(&self.to_string());
+        }
+        let mut seq = serializer.serialize_seq(Some(6))?;
+        for b in self.as_bytes() {
+            seq.serialize_element(b)?;
+        }
+        seq.end()
+    }
+}
+
+impl<'de> Deserialize<'de> for HwAddr {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<HwAddr, D::Error> {
+        struct HwAddrVisitor;
+
+        impl<'de> Visitor<'de> for HwAddrVisitor {
+            type Value = HwAddr;
+
+            fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+                f.write_str("a MAC address as bytes or a hardware address")
+            }
+
+            fn visit_seq<A: SeqAccess<'de>>(self, mut seq: A) -> Result<HwAddr, A::Error> {
+                let mut mac = vec![];
+                while let Some(b) = seq.next_element::<u8>()? {
+                    mac.push(b);
+                }
+                Ok(HwAddr::new(ETHERNET, &mac))
+            }
+
+            fn visit_str<E: de::Error>(self, s: &str) -> Result<HwAddr, E> {
+                s.parse().map_err(E::custom)
+            }
+        }
+
+        deserializer.deserialize_any(HwAddrVisitor)
+    }
+}
@@ -0,0 +1,248 @@
+//! One server instance: the socket, lease state and side channels of a single configuration.
+//!
+//! Given several configurations (`-c` more than once), each becomes an instance on its own thread,
+//! bound to its own `intf`, with its own lease files and stats. An instance whose serve loop
+//! fails or panics is restarted without affecting the others.
+
+use crate::auth;
+use crate::config::Config;
+use crate::ddns;
+use crate::dhcp6;
+use crate::dhcpd::Dhcpd;
+use crate::docker;
+use crate::dump;
+use crate::filter;
+use crate::leasequery;
+use crate::log;
+use crate::mgmt;
+use crate::mirror;
+use crate::proxy;
+use crate::ra;
+use crate::server::Server;
+use crate::snmp;
+use crate::stats;
+use crate::takeover;
+use crate::tenant::Tenants;
+
+use anyhow::Context;
+
+use std::io;
+use std::net::UdpSocket;
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::mpsc::Receiver;
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, Instant};
+
+const MIN_BACKOFF: Duration = Duration::from_secs(1);
+const MAX_BACKOFF: Duration = Duration::from_secs(60);
+
+/// Runs every configuration as an instance and waits for them.
+pub fn supervise(confs: Vec<Config>) -> anyhow::Result<()> {
+    let bind_device = confs.len() > 1;
+    let mut handles = vec![];
+    for conf in confs {
+        let name = conf.intf.clone();
+        let handle = thread::Builder::new()
+            .name(name.clone())
+            .spawn(move || run(conf, bind_device))
+            .with_context(|| format!("start instance {}", name))?;
+        handles.push((name, handle));
+    }
+    let mut failed = 0;
+    for (name, handle) in handles {
+        if let Ok(Err(e)) = handle.join() {
+            println!("ERROR: instance {} stopped: {:?}", name, e);
+            failed += 1;
+        }
+    }
+    if failed > 0 {
+        anyhow::bail!("{} instance(s) failed to start", failed);
+    }
+    Ok(())
+}
+
+/// Sets up the side channels once, then keeps the serve loop running.
+fn run(conf: Config, bind_device: bool) -> anyhow::Result<()> {
+    log::init(conf.log_target);
+    let counters = Arc::new(stats::Counters::default());
+    stats::enter(counters.clone());
+    dump::enter(conf.dump_file.clone());
+    auth::enter(conf.authentication.clone());
+    let mgmt = match conf.mgmt_listen {
+        Some(addr) => Some(
+            mgmt::spawn(addr, conf.mgmt_tokens.clone())
+                .with_context(|| format!("listen on {}", addr))?,
+        ),
+        None => None,
+    };
+    let snoop = match &conf.takeover {
+        Some(d) => match takeover::spawn(duration_str::parse(d.as_str()).unwrap()) {
+            Ok(rx) => Some(rx),
+            Err(e) => {
+                // still learn from the clients' own requests
+                println!("WARN: takeover: cannot listen on port 68: {:?}", e);
+                None
+            }
+        },
+        None => None,
+    };
+    if let Some(lq) = conf.leasequery.clone() {
+        let listen = lq.listen;
+        leasequery::spawn(lq).with_context(|| format!("listen on {}", listen))?;
+    }
+    if let Some(d) = conf.dhcp6.clone() {
+        dhcp6::spawn(&conf, d).with_context(|| format!("listen on port {}", dhcp6::PORT))?;
+    }
+    if let Some(r) = conf.router_advertisements.clone() {
+        ra::spawn(&conf, r).context("router advertisements")?;
+    }
+    if conf.proxy_dhcp {
+        proxy::spawn().with_context(|| format!("listen on port {}", proxy::PORT))?;
+    }
+    if let Some(m) = conf.mirror.clone() {
+        mirror::spawn(m);
+    }
+    if let Some(d) = conf.ddns.clone() {
+        ddns::spawn(d);
+    }
+    if let Some(d) = conf.docker.clone() {
+        docker::spawn(d);
+    }
+    if let Some(file) = conf.stats_file.clone() {
+        stats::spawn_checkpoint(file, counters.clone());
+    }
+    if let Some(s) = conf.snmp.clone() {
+        let oid = snmp::parse_oid(&s.oid).unwrap();
+        snmp::spawn(s.listen, s.community, oid, counters.clone())
+            .with_context(|| format!("listen on {}", s.listen))?;
+    }
+    if let Some(push) = conf.metrics_push.clone() {
+        stats::spawn_push(push, counters);
+    }
+
+    let mut backoff = MIN_BACKOFF;
+    loop {
+        let started = Instant::now();
+        let r = panic::catch_unwind(AssertUnwindSafe(|| {
+            serve(&conf, bind_device, mgmt.as_ref(), snoop.as_ref())
+        }));
+        match r {
+            Ok(e) => println!("ERROR: instance {}: {:?}", conf.intf, e),
+            Err(_) => println!("ERROR: instance {} panicked", conf.intf),
+        }
+        if started.elapsed() > MAX_BACKOFF {
+            backoff = MIN_BACKOFF;
+        }
+        println!("restarting instance {} in {:?}", conf.intf, backoff);
+        thread::sleep(backoff);
+        backoff = (backoff * 2).min(MAX_BACKOFF);
+    }
+}
+
+/// One run of the serve loop with fresh state read back from the lease files.
+fn serve(
+    conf: &Config,
+    bind_device: bool,
+    mgmt: Option<&Receiver<mgmt::Call>>,
+    snoop: Option<&Receiver<Vec<u8>>>,
+) -> io::Error {
+    let socket = match bind(conf, bind_device) {
+        Ok(socket) => socket,
+        Err(e) => return e,
+    };
+    if conf.tenants.is_empty() {
+        let dhcpd = Dhcpd::new(conf.clone());
+        Server::serve(
+            socket,
+            conf.listen_addr,
+            conf.broadcast,
+            mgmt,
+            snoop,
+            conf.io_batch,
+            dhcpd,
+        )
+    } else {
+        let tenants = Tenants::new(conf.clone());
+        Server::serve(
+            socket,
+            conf.listen_addr,
+            conf.broadcast,
+            mgmt,
+            snoop,
+            conf.io_batch,
+            tenants,
+        )
+    }
+}
+
+/// Binds the DHCP port. Instances sharing the process each bind to their own interface.
+fn bind(conf: &Config, bind_device: bool) -> io::Result<UdpSocket> {
+    let socket = if bind_device {
+        bind_to_device(&conf.intf)?
+    } else {
+        UdpSocket::bind("0.0.0.0:67")?
+    };
+    socket.set_broadcast(true)?;
+    if conf.socket_filter {
+        if let Err(e) = filter::attach(&socket) {
+            println!("WARN: {}: socket filter not attached: {:?}", conf.intf, e);
+        }
+    }
+    Ok(socket)
+}
+
+#[cfg(target_os = "linux")]
+fn bind_to_device(intf: &str) -> io::Result<UdpSocket> {
+    use std::os::fd::FromRawFd;
+
+    fn check(r: libc::c_int) -> io::Result<()> {
+        if r < 0 {
+            Err(io::Error::last_os_error())
+        } else {
+            Ok(())
+        }
+    }
+
+    // SO_BINDTODEVICE and SO_REUSEADDR have to be set before bind, which std cannot do
+    unsafe {
+        let fd = libc::socket(libc::AF_INET, libc::SOCK_DGRAM | libc::SOCK_CLOEXEC, 0);
+        check(fd)?;
+        let socket = UdpSocket::from_raw_fd(fd);
+        let one: libc::c_int = 1;
+        check(libc::setsockopt(
+            fd,
+            libc::SOL_SOCKET,
+            libc::SO_REUSEADDR,
+            &one as *const _ as *const libc::c_void,
+            std::mem::size_of_val(&one) as libc::socklen_t,
+        ))?;
+        check(libc::setsockopt(
+            fd,
+            libc::SOL_SOCKET,
+            libc::SO_BINDTODEVICE,
+            intf.as_ptr() as *const libc::c_void,
+            intf.len() as libc::socklen_t,
+        ))?;
+        let addr = libc::sockaddr_in {
+            sin_family: libc::AF_INET as libc::sa_family_t,
+            sin_port: 67u16.to_be(),
+            sin_addr: libc::in_addr { s_addr: 0 },
+            sin_zero: [0; 8],
+        };
+        check(libc::bind(
+            fd,
+            &addr as *const _ as *const libc::sockaddr,
+            std::mem::size_of_val(&addr) as libc::socklen_t,
+        ))?;
+        Ok(socket)
+    }
+}
+
+#[cfg(not(target_os = "linux"))]
+fn bind_to_device(_intf: &str) -> io::Result<UdpSocket> {
+    Err(io::Error::new(
+        io::ErrorKind::Unsupported,
+        "several instances need SO_BINDTODEVICE (Linux only)",
+    ))
+}
@@ -0,0 +1,231 @@
+//! Bulk (RFC 6926) and active (RFC 7724) leasequery over TCP.
+//!
+//! Queries read from the connections are answered by the serve loop from the lease table. An
+//! active leasequery keeps its connection subscribed afterwards: every lease that starts,
+//! changes or ends is pushed to it until the relay agent hangs up.
+
+use crate::config::LeasequeryConf;
+use crate::hwaddr::HwAddr;
+use crate::options::{self, DhcpOption, MessageType};
+use crate::packet::Packet;
+
+use std::cell::RefCell;
+use std::io::{self, Read, Write};
+use std::net::{IpAddr, Ipv4Addr, Shutdown, TcpListener, TcpStream};
+use std::sync::mpsc::{self, Receiver, Sender};
+use std::thread;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+pub const STATUS_MALFORMED_QUERY: u8 = 3;
+pub const STATUS_NOT_ALLOWED: u8 = 4;
+const STATUS_CATCH_UP_COMPLETE: u8 = 7;
+
+/// Connections without a query for this long are closed.
+const IDLE: Duration = Duration::from_secs(300);
+
+/// Why a query is not answered with leases: its status code and message.
+pub type Status = (u8, String);
+
+struct Call {
+    query: Packet,
+    replies: Sender<Vec<Packet>>,
+    /// Where lease changes go once an active leasequery caught up.
+    updates: Option<Sender<Packet>>,
+}
+
+thread_local! {
+    /// The queries for the instance served by this thread, see `instance.rs`.
+    static CALLS: RefCell<Option<Receiver<Call>>> = const { RefCell::new(None) };
+    /// The active leasequeries by transaction id.
+    static SUBSCRIBERS: RefCell<Vec<(u32, Sender<Packet>)>> = const { RefCell::new(vec![]) };
+}
+
+/// Listens for the calling thread's instance.
+pub fn spawn(conf: LeasequeryConf) -> io::Result<()> {
+    let listener = TcpListener::bind(conf.listen)?;
+    let (tx, rx) = mpsc::channel();
+    CALLS.with(|c| *c.borrow_mut() = Some(rx));
+    thread::spawn(move || {
+        for stream in listener.incoming().flatten() {
+            let Ok(peer) = stream.peer_addr() else {
+                continue;
+            };
+            let allowed = match peer.ip() {
+                IpAddr::V4(ip) => conf.allow.is_empty() || conf.allow.contains(&ip),
+                IpAddr::V6(_) => conf.allow.is_empty(),
+            };
+            if !allowed {
+                println!("WARN: leasequery: connection from {} refused", peer);
+                continue;
+            }
+            let tx = tx.clone();
+            thread::spawn(move || {
+                if let Err(e) = connection(stream, &tx) {
+                    println!("leasequery {}: {:?}", peer, e);
+                }
+            });
+        }
+    });
+    println!("leasequery listening on {}", conf.listen);
+    Ok(())
+}
+
+/// Answers the queries that arrived since the last call with `answer`, the lookup in the lease
+/// table, and closes each as RFC 6926 and RFC 7724 want.
+pub fn poll(server_id: Ipv4Addr, mut answer: impl FnMut(&Packet) -> Result<Vec<Packet>, Status>) {
+    let calls: Vec<Call> = CALLS.with(|c| match &*c.borrow() {
+        Some(rx) => rx.try_iter().collect(),
+        None => vec![],
+    });
+    for call in calls {
+        let active = call.query.message_type() == Ok(MessageType::ActiveLeaseQuery);
+        let (replies, status) = match answer(&call.query) {
+            Ok(replies) => {
+                let caught_up = (STATUS_CATCH_UP_COMPLETE, "catch-up complete".to_string());
+                (replies, active.then_some(caught_up))
+            }
+            Err(status) => (vec![], Some(status)),
+        };
+        let subscribe = active && matches!(status, Some((STATUS_CATCH_UP_COMPLETE, _)));
+        let t = if active {
+            MessageType::LeaseQueryStatus
+        } else {
+            MessageType::LeaseQueryDone
+        };
+        let mut replies: Vec<Packet> = replies.into_iter().map(stamp).collect();
+        replies.push(stamp(closing(&call.query, server_id, t, status)));
+        if let (true, Some(updates)) = (subscribe, call.updates) {
+            SUBSCRIBERS.with(|s| s.borrow_mut().push((call.query.xid, updates)));
+        }
+        let _ = call.replies.send(replies);
+    }
+}
+
+/// Whether any active leasequery wants lease changes.
+pub fn subscribed() -> bool {
+    SUBSCRIBERS.with(|s| !s.borrow().is_empty())
+}
+
+/// Pushes a lease change, a DHCPLEASEACTIVE or DHCPLEASEUNASSIGNED, to the active leasequeries.
+pub fn publish(p: &Packet) {
+    let p = stamp(p.clone());
+    SUBSCRIBERS.with(|s| {
+        s.borrow_mut().retain(|(xid, tx)| {
+            let mut p = p.clone();
+            p.xid = *xid;
+            tx.send(p).is_ok()
+        })
+    });
+}
+
+/// Adds what every answer over TCP carries: the server's clock and, for a lease, its state.
+fn stamp(mut p: Packet) -> Packet {
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |d| d.as_secs() as u32);
+    p.options.push(DhcpOption::BaseTime(now));
+    match p.message_type() {
+        Ok(MessageType::LeaseActive) => {
+            p.options.push(DhcpOption::DhcpState(options::STATE_ACTIVE))
+        }
+        Ok(MessageType::LeaseUnassigned) => p
+            .options
+            .push(DhcpOption::DhcpState(options::STATE_EXPIRED)),
+        _ => {}
+    }
+    p
+}
+
+/// The DHCPLEASEQUERYDONE or DHCPLEASEQUERYSTATUS after the answers to `query`.
+fn closing(query: &Packet, server_id: Ipv4Addr, t: MessageType, status: Option<Status>) -> Packet {
+    let mut opts = vec![
+        DhcpOption::DhcpMessageType(t),
+        DhcpOption::ServerIdentifier(server_id),
+    ];
+    if let Some((code, message)) = status {
+        opts.push(DhcpOption::StatusCode(code, message));
+    }
+    Packet {
+        reply: true,
+        hops: 0,
+        xid: query.xid,
+        secs: 0,
+        broadcast: false,
+        ciaddr: Ipv4Addr::UNSPECIFIED,
+        yiaddr: Ipv4Addr::UNSPECIFIED,
+        siaddr: Ipv4Addr::UNSPECIFIED,
+        giaddr: query.giaddr,
+        chaddr: HwAddr::default(),
+        sname: String::new(),
+        file: String::new(),
+        options: opts,
+    }
+}
+
+fn connection(mut stream: TcpStream, calls: &Sender<Call>) -> io::Result<()> {
+    let invalid = |m: &str| io::Error::new(io::ErrorKind::InvalidData, m.to_string());
+    stream.set_read_timeout(Some(IDLE))?;
+    loop {
+        let Some(data) = read_message(&mut stream)? else {
+            return Ok(());
+        };
+        let query = Packet::from(&data).map_err(|_| invalid("undecodable message"))?;
+        let (updates, follow) = match query.message_type() {
+            Ok(MessageType::BulkLeaseQuery) => (None, None),
+            Ok(MessageType::ActiveLeaseQuery) => {
+                let (tx, rx) = mpsc::channel();
+                (Some(tx), Some(rx))
+            }
+            _ => return Err(invalid("not a bulk or active leasequery")),
+        };
+        let (tx, rx) = mpsc::channel();
+        let call = Call {
+            query,
+            replies: tx,
+            updates,
+        };
+        if calls.send(call).is_err() {
+            return Ok(());
+        }
+        let replies = rx
+            .recv_timeout(Duration::from_secs(30))
+            .map_err(|_| io::Error::new(io::ErrorKind::TimedOut, "server busy"))?;
+        for p in &replies {
+            write_message(&mut stream, p)?;
+        }
+        if let Some(updates) = follow {
+            // the relay agent only talks again to hang up
+            let mut reader = stream.try_clone()?;
+            thread::spawn(move || {
+                let _ = reader.set_read_timeout(None);
+                let _ = reader.read(&mut [0; 1]);
+                let _ = reader.shutdown(Shutdown::Both);
+            });
+            for p in updates {
+                write_message(&mut stream, &p)?;
+            }
+            return Ok(());
+        }
+    }
+}
+
+/// Reads one message framed by its two byte length (RFC 6926 7.1); `None` at the end of the
+/// connection.
+fn read_message(stream: &mut TcpStream) -> io::Result<Option<Vec<u8>>> {
+    let mut len = [0u8; 2];
+    match stream.read_exact(&mut len) {
+        Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => return Ok(None),
+        r => r?,
+    }
+    let mut data = vec![0; u16::from_be_bytes(len) as usize];
+    stream.read_exact(&mut data)?;
+    Ok(Some(data))
+}
+
+fn write_message(stream: &mut TcpStream, p: &Packet) -> io::Result<()> {
+    let mut buf = [0u8; 1500];
+    let data = p.encode(&mut buf);
+    let mut framed = (data.len() as u16).to_be_bytes().to_vec();
+    framed.extend(data);
+    stream.write_all(&framed)
+}
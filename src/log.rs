@@ -0,0 +1,117 @@
+//! Lease events, printed to stdout or sent natively to systemd-journald.
+//!
+//! Journal entries carry structured fields, so e.g. `journalctl MESSAGE_ID=<id> IP=192.168.1.10`
+//! finds every ACK for an address. The ids are listed in the README.
+
+use crate::config::LogTarget;
+use crate::hwaddr::HwAddr;
+use crate::options::MessageType;
+
+use std::net::Ipv4Addr;
+use std::os::unix::net::UnixDatagram;
+use std::sync::OnceLock;
+use std::thread;
+
+const JOURNAL_SOCKET: &str = "/run/systemd/journal/socket";
+
+static JOURNAL: OnceLock<Option<UnixDatagram>> = OnceLock::new();
+
+/// Selects where events go; the first instance to start decides for the process.
+pub fn init(target: LogTarget) {
+    JOURNAL.get_or_init(|| match target {
+        LogTarget::Stdout => None,
+        LogTarget::Journald => match UnixDatagram::unbound() {
+            Ok(socket) => Some(socket),
+            Err(e) => {
+                println!("WARN: journald logging unavailable: {:?}", e);
+                None
+            }
+        },
+    });
+}
+
+fn message_id(t: MessageType) -> &'static str {
+    match t {
+        MessageType::Offer => "d1a02556327a422ea5f7d915fde83942",
+        MessageType::Ack => "984628d51c404e5991c67c20a62b2fe2",
+        MessageType::Nak => "58a033e3274c4d6aa4b6c7359b1cce0b",
+        MessageType::Release => "552ae4f4163b4b278cf86c5c5f15ea67",
+        MessageType::Decline => "1bf386a85ede48bdb9f71fb8475f85fe",
+        _ => "259c45d892bb4d2d9f486f10970e1d86",
+    }
+}
+
+pub fn name(t: MessageType) -> &'static str {
+    match t {
+        MessageType::Discover => "DISCOVER",
+        MessageType::Offer => "OFFER",
+        MessageType::Request => "REQUEST",
+        MessageType::Decline => "DECLINE",
+        MessageType::Ack => "ACK",
+        MessageType::Nak => "NAK",
+        MessageType::Release => "RELEASE",
+        MessageType::Inform => "INFORM",
+        MessageType::ForceRenew => "FORCERENEW",
+        MessageType::LeaseQuery => "LEASEQUERY",
+        MessageType::LeaseUnassigned => "LEASEUNASSIGNED",
+        MessageType::LeaseUnknown => "LEASEUNKNOWN",
+        MessageType::LeaseActive => "LEASEACTIVE",
+        MessageType::BulkLeaseQuery => "BULKLEASEQUERY",
+        MessageType::LeaseQueryDone => "LEASEQUERYDONE",
+        MessageType::ActiveLeaseQuery => "ACTIVELEASEQUERY",
+        MessageType::LeaseQueryStatus => "LEASEQUERYSTATUS",
+    }
+}
+
+/// Records a message sent or received for `ip`; `detail` is e.g. the reason of a NAK.
+pub fn event(t: MessageType, mac: HwAddr, ip: Ipv4Addr, xid: u32, detail: Option<&str>) {
+    let mut message = format!("{} {} {} xid {:#010x}", name(t), ip, mac, xid);
+    if let Some(d) = detail {
+        message.push_str(": ");
+        message.push_str(d);
+    }
+    let Some(Some(journal)) = JOURNAL.get() else {
+        println!("{}", message);
+        return;
+    };
+    let priority = match t {
+        MessageType::Nak | MessageType::Decline => "5",
+        _ => "6",
+    };
+    let instance = thread::current().name().unwrap_or("").to_string();
+    let mut fields = vec![
+        ("MESSAGE", message.clone()),
+        ("MESSAGE_ID", message_id(t).to_string()),
+        ("PRIORITY", priority.to_string()),
+        ("SYSLOG_IDENTIFIER", "rdhcpd".to_string()),
+        ("DHCP_MESSAGE", name(t).to_string()),
+        ("MAC", mac.to_string()),
+        ("IP", ip.to_string()),
+        ("XID", format!("{:#010x}", xid)),
+        ("INSTANCE", instance),
+    ];
+    if let Some(d) = detail {
+        fields.push(("DETAIL", d.to_string()));
+    }
+    if journal.send_to(&encode(&fields), JOURNAL_SOCKET).is_err() {
+        println!("{}", message);
+    }
+}
+
+/// Native journal protocol: `KEY=value` lines, or the length-prefixed form for values that
+/// contain a newline.
+fn encode(fields: &[(&str, String)]) -> Vec<u8> {
+    let mut buf = vec![];
+    for (key, value) in fields {
+        buf.extend_from_slice(key.as_bytes());
+        if value.contains('\n') {
+            buf.push(b'\n');
+            buf.extend_from_slice(&(value.len() as u64).to_le_bytes());
+        } else {
+            buf.push(b'=');
+        }
+        buf.extend_from_slice(value.as_bytes());
+        buf.push(b'\n');
+    }
+    buf
+}
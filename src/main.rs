@@ -1,40 +1,106 @@
 mod args;
+mod auth;
+mod batch;
+mod checks;
 mod config;
+mod ddns;
+mod dhcp6;
 mod dhcpd;
+mod docker;
+mod dump;
+mod filter;
+mod hwaddr;
+mod instance;
+mod leasequery;
+mod log;
+mod mgmt;
+mod mirror;
 mod options;
 mod packet;
+mod profiles;
+mod proxy;
+mod ra;
+mod reservations;
 mod server;
+mod snmp;
+mod stats;
+mod takeover;
+mod template;
+mod tenant;
+mod tsig;
 mod utils;
+mod webhook;
+mod wizard;
+mod zone;
 
-use args::Args;
+use args::{Args, Command, ReservationsAction};
+use config::CheckMode;
+
+use anyhow::{anyhow, bail};
 
-use std::net::UdpSocket;
 use std::path::Path;
 use std::process::exit;
 
-use crate::dhcpd::Dhcpd;
-use crate::server::Server;
+const DEFAULT_CFG: &str = "/etc/rdhcpd.yml";
 
 fn main() -> anyhow::Result<()> {
-    let Args { cfg } = Args::parse_args();
-    let cfgfile = Path::new(&cfg);
-    if !cfgfile.exists() {
-        println!("Cfg File {} Not Exists.", cfg);
-        exit(1)
+    let args = Args::parse_args();
+    if let Some(Command::Init { output }) = &args.command {
+        return wizard::run(output);
     }
-    let conf = config::read_config(cfg).unwrap();
-    println!("conf: {:?}", conf);
-
-    let socket = UdpSocket::bind("0.0.0.0:67").unwrap();
-    socket.set_broadcast(true).unwrap();
-    let dhcpd = Dhcpd::new(conf.clone());
-
-    Server::serve(
-        socket,
-        conf.listen_addr.clone(),
-        conf.broadcast.clone(),
-        dhcpd,
-    );
-
-    Ok(())
+    // the command line wins over the environment, which wins over the file
+    let mut overrides = config::env_overrides().map_err(|e| anyhow!("environment: {}", e))?;
+    overrides.extend(args.overrides());
+    let cfgs: Vec<Option<String>> = if !args.cfg.is_empty() {
+        args.cfg.into_iter().map(Some).collect()
+    } else if overrides.contains_key("intf") {
+        // ad-hoc server from the command line and environment alone
+        vec![None]
+    } else {
+        vec![Some(DEFAULT_CFG.to_string())]
+    };
+    if cfgs.len() > 1 && !overrides.is_empty() {
+        bail!("command line and environment settings need a single configuration");
+    }
+    let mut confs = vec![];
+    for cfg in cfgs {
+        if let Some(cfg) = &cfg {
+            if !Path::new(cfg).exists() {
+                println!("Cfg File {} Not Exists.", cfg);
+                exit(1)
+            }
+        }
+        let name = cfg.clone().unwrap_or_else(|| "command line".to_string());
+        confs.push(config::load(cfg, &overrides).map_err(|e| anyhow!("{}: {}", name, e))?);
+    }
+    let command = args.command;
+    if let Some(Command::Reservations {
+        action: ReservationsAction::Import { file, dry_run },
+    }) = command
+    {
+        if confs.len() != 1 {
+            bail!("reservations import works on a single configuration");
+        }
+        return reservations::import(&confs[0], &file, dry_run);
+    }
+    config::check_instances(&confs).map_err(|e| anyhow!("{}", e))?;
+    for conf in &confs {
+        println!("conf: {:?}", conf);
+        if conf.startup_checks == CheckMode::Off {
+            continue;
+        }
+        let problems = checks::check(conf);
+        for p in &problems {
+            println!("WARN: {}: {}", conf.intf, p);
+        }
+        if !problems.is_empty() && conf.startup_checks == CheckMode::Strict {
+            bail!(
+                "{} problem(s) found on {}, not starting",
+                problems.len(),
+                conf.intf
+            );
+        }
+    }
+    dump::install();
+    instance::supervise(confs)
 }
@@ -0,0 +1,233 @@
+//! A small HTTP/1.1 management interface.
+//!
+//! Connections are handled on background threads, but every request is handed to the serve
+//! loop over a channel, so the handler keeps exclusive ownership of its state.
+//!
+//! When tokens are configured every request but the `/healthz` and `/readyz` probes needs
+//! `Authorization: Bearer <token>`; `read` tokens may only GET. Requests that change state are written to the audit log.
+
+use crate::config::{MgmtToken, Role};
+
+use serde::Serialize;
+
+use std::io::{self, Read, Write};
+use std::net::{SocketAddr, TcpListener, TcpStream};
+use std::sync::{mpsc, Arc};
+use std::thread;
+use std::time::Duration;
+
+const MAX_HEADER: usize = 64 * 1024;
+const MAX_BODY: usize = 16 * 1024 * 1024;
+
+#[derive(Debug, Clone)]
+pub struct Request {
+    pub method: String,
+    pub path: String,
+    pub headers: Vec<(String, String)>,
+    pub body: Vec<u8>,
+}
+
+impl Request {
+    /// Path split on `/`, without empty segments.
+    pub fn segments(&self) -> Vec<&str> {
+        self.path
+            .split('?')
+            .next()
+            .unwrap_or("")
+            .split('/')
+            .filter(|s| !s.is_empty())
+            .collect()
+    }
+
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.headers
+            .iter()
+            .find(|(k, _)| k.eq_ignore_ascii_case(name))
+            .map(|(_, v)| v.as_str())
+    }
+}
+
+#[derive(Debug)]
+pub struct Response {
+    pub status: u16,
+    pub content_type: &'static str,
+    pub body: Vec<u8>,
+}
+
+impl Response {
+    pub fn json<T: Serialize>(status: u16, value: &T) -> Response {
+        Response {
+            status,
+            content_type: "application/json",
+            body: serde_json::to_vec(value).unwrap_or_default(),
+        }
+    }
+
+    pub fn error(status: u16, message: &str) -> Response {
+        Response::json(status, &serde_json::json!({ "error": message }))
+    }
+
+    pub fn not_found() -> Response {
+        Response::error(404, "not found")
+    }
+}
+
+/// A request together with the channel its response is sent back on.
+pub type Call = (Request, mpsc::Sender<Response>);
+
+/// Binds `addr` and starts accepting connections; requests arrive on the returned receiver.
+pub fn spawn(addr: SocketAddr, tokens: Vec<MgmtToken>) -> io::Result<mpsc::Receiver<Call>> {
+    let listener = TcpListener::bind(addr)?;
+    let (tx, rx) = mpsc::channel();
+    let tokens = Arc::new(tokens);
+    thread::spawn(move || {
+        // one thread per connection, so a slow client cannot hold up the others
+        for stream in listener.incoming().flatten() {
+            let (tx, tokens) = (tx.clone(), tokens.clone());
+            thread::spawn(move || {
+                if let Err(e) = handle_connection(stream, &tx, &tokens) {
+                    println!("mgmt: {:?}", e);
+                }
+            });
+        }
+    });
+    println!("management interface listening on {}", addr);
+    Ok(rx)
+}
+
+fn handle_connection(
+    mut stream: TcpStream,
+    tx: &mpsc::Sender<Call>,
+    tokens: &[MgmtToken],
+) -> io::Result<()> {
+    stream.set_read_timeout(Some(Duration::from_secs(5)))?;
+    let peer = stream.peer_addr()?;
+    let response = match read_request(&mut stream)? {
+        Some(req) => match authorize(&req, tokens) {
+            Ok(who) => {
+                let audit = req.method != "GET";
+                let line = format!("{} {} {} {}", peer, who, req.method, req.path);
+                let (rtx, rrx) = mpsc::channel();
+                if tx.send((req, rtx)).is_err() {
+                    return Ok(());
+                }
+                let response = rrx
+                    .recv_timeout(Duration::from_secs(30))
+                    .unwrap_or_else(|_| Response::error(503, "server busy"));
+                if audit {
+                    println!("AUDIT: {} -> {}", line, response.status);
+                }
+                response
+            }
+            Err(response) => {
+                println!("AUDIT: {} denied {} {}", peer, req.method, req.path);
+                response
+            }
+        },
+        None => Response::error(400, "bad request"),
+    };
+    write_response(&mut stream, &response)
+}
+
+/// Checks the bearer token against `req`, returning the token name to audit under.
+fn authorize<'a>(req: &Request, tokens: &'a [MgmtToken]) -> Result<&'a str, Response> {
+    // probes from orchestrators and load balancers carry no token
+    let probe = req.method == "GET" && matches!(req.segments()[..], ["healthz"] | ["readyz"]);
+    if tokens.is_empty() || probe {
+        return Ok("-");
+    }
+    let given = req
+        .header("Authorization")
+        .and_then(|v| v.strip_prefix("Bearer "))
+        .map(|v| v.trim().as_bytes())
+        .unwrap_or_default();
+    let Some(token) = tokens.iter().find(|t| ct_eq(t.token.as_bytes(), given)) else {
+        return Err(Response::error(401, "unauthorized"));
+    };
+    if token.role != Role::Admin && req.method != "GET" {
+        return Err(Response::error(403, "forbidden"));
+    }
+    Ok(&token.name)
+}
+
+/// Compares without returning early, so response times do not leak how much of a token matched.
+fn ct_eq(a: &[u8], b: &[u8]) -> bool {
+    if a.len() != b.len() {
+        return false;
+    }
+    a.iter().zip(b).fold(0u8, |acc, (x, y)| acc | (x ^ y)) == 0
+}
+
+fn read_request(stream: &mut TcpStream) -> io::Result<Option<Request>> {
+    let mut buf = Vec::new();
+    let mut chunk = [0u8; 4096];
+    let header_end = loop {
+        if let Some(pos) = buf.windows(4).position(|w| w == b"\r\n\r\n") {
+            break pos;
+        }
+        if buf.len() > MAX_HEADER {
+            return Ok(None);
+        }
+        let n = stream.read(&mut chunk)?;
+        if n == 0 {
+            return Ok(None);
+        }
+        buf.extend_from_slice(&chunk[..n]);
+    };
+    let head = String::from_utf8_lossy(&buf[..header_end]).to_string();
+    let mut lines = head.split("\r\n");
+    let mut request_line = lines.next().unwrap_or("").split_whitespace();
+    let (Some(method), Some(path)) = (request_line.next(), request_line.next()) else {
+        return Ok(None);
+    };
+    let headers: Vec<(String, String)> = lines
+        .filter_map(|l| l.split_once(':'))
+        .map(|(k, v)| (k.trim().to_string(), v.trim().to_string()))
+        .collect();
+    let mut req = Request {
+        method: method.to_string(),
+        path: path.to_string(),
+        headers,
+        body: buf[header_end + 4..].to_vec(),
+    };
+    let len: usize = req
+        .header("Content-Length")
+        .and_then(|v| v.parse().ok())
+        .unwrap_or(0);
+    if len > MAX_BODY {
+        return Ok(None);
+    }
+    while req.body.len() < len {
+        let n = stream.read(&mut chunk)?;
+        if n == 0 {
+            break;
+        }
+        req.body.extend_from_slice(&chunk[..n]);
+    }
+    req.body.truncate(len);
+    Ok(Some(req))
+}
+
+fn write_response(stream: &mut TcpStream, r: &Response) -> io::Result<()> {
+    let reason = match r.status {
+        200 => "OK",
+        400 => "Bad Request",
+        401 => "Unauthorized",
+        403 => "Forbidden",
+        404 => "Not Found",
+        409 => "Conflict",
+        500 => "Internal Server Error",
+        503 => "Service Unavailable",
+        _ => "",
+    };
+    write!(
+        stream,
+        "HTTP/1.1 {} {}\r\nContent-Type: {}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n",
+        r.status,
+        reason,
+        r.content_type,
+        r.body.len()
+    )?;
+    stream.write_all(&r.body)?;
+    stream.flush()
+}
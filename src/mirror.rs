@@ -0,0 +1,99 @@
+//! Copies every received and sent DHCP packet to a remote collector.
+//!
+//! Each packet becomes one JSON object: a datagram per packet over UDP, one line per packet over
+//! TCP. Sending happens on a background thread so a slow collector never delays the serve loop.
+
+use crate::config::{MirrorConf, MirrorProtocol};
+use crate::utils;
+
+use std::cell::RefCell;
+use std::io::Write;
+use std::net::{SocketAddr, TcpStream, UdpSocket};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{mpsc, Arc};
+use std::thread;
+use std::time::Duration;
+
+/// Packets queued beyond this are dropped rather than buffered without bound.
+const QUEUE: usize = 4096;
+
+thread_local! {
+    /// The mirror of the instance served by this thread, see `instance.rs`.
+    static MIRROR: RefCell<Option<Mirror>> = const { RefCell::new(None) };
+}
+
+struct Mirror {
+    tx: mpsc::SyncSender<String>,
+    /// Packets queued and not yet sent.
+    depth: Arc<AtomicUsize>,
+}
+
+/// Starts mirroring the packets handled on the calling thread.
+pub fn spawn(conf: MirrorConf) {
+    let (tx, rx) = mpsc::sync_channel::<String>(QUEUE);
+    let depth = Arc::new(AtomicUsize::new(0));
+    MIRROR.with(|m| {
+        *m.borrow_mut() = Some(Mirror {
+            tx,
+            depth: depth.clone(),
+        })
+    });
+    thread::spawn(move || {
+        let udp = UdpSocket::bind("0.0.0.0:0").ok();
+        let mut tcp: Option<TcpStream> = None;
+        for line in rx {
+            depth.fetch_sub(1, Ordering::Relaxed);
+            let r = match conf.protocol {
+                MirrorProtocol::Udp => match &udp {
+                    Some(s) => s.send_to(line.as_bytes(), conf.addr).map(|_| ()),
+                    None => Ok(()),
+                },
+                MirrorProtocol::Tcp => send_tcp(&mut tcp, conf.addr, &line),
+            };
+            if let Err(e) = r {
+                println!("WARN: mirror to {} failed: {:?}", conf.addr, e);
+            }
+        }
+    });
+}
+
+/// Sends over the open connection, reconnecting once if there is none or it broke.
+fn send_tcp(tcp: &mut Option<TcpStream>, addr: SocketAddr, line: &str) -> std::io::Result<()> {
+    if let Some(stream) = tcp {
+        if writeln!(stream, "{}", line).is_ok() {
+            return Ok(());
+        }
+    }
+    *tcp = None;
+    let mut stream = TcpStream::connect_timeout(&addr, Duration::from_secs(5))?;
+    writeln!(stream, "{}", line)?;
+    *tcp = Some(stream);
+    Ok(())
+}
+
+/// Queues a copy of `data`; `direction` is `in` or `out`, `peer` the other end.
+pub fn packet(direction: &str, peer: SocketAddr, data: &[u8]) {
+    if MIRROR.with(|m| m.borrow().is_none()) {
+        return;
+    }
+    let line = serde_json::json!({
+        "ts": utils::now_timestamp_ms() as u64,
+        "dir": direction,
+        "peer": peer.to_string(),
+        "data": data.iter().map(|b| format!("{:02x}", b)).collect::<String>(),
+    })
+    .to_string();
+    // a full queue means the collector is not keeping up; drop rather than block
+    MIRROR.with(|m| {
+        if let Some(m) = m.borrow().as_ref() {
+            if m.tx.try_send(line).is_ok() {
+                m.depth.fetch_add(1, Ordering::Relaxed);
+            }
+        }
+    });
+}
+
+/// Packets waiting to be mirrored, if mirroring is on.
+pub fn depth() -> Option<usize> {
+    MIRROR.with(|m| m.borrow().as_ref().map(|m| m.depth.load(Ordering::Relaxed)))
+}
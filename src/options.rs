@@ -7,7 +7,112 @@ pub struct RawDhcpOption {
     pub data: Vec<u8>,
 }
 
-#[derive(PartialEq, Debug)]
+/// One enterprise entry of the V-I Vendor Class option (RFC 3925, option 124).
+#[derive(PartialEq, Clone, Debug)]
+pub struct VendorClass {
+    pub enterprise: u32,
+    pub data: Vec<Vec<u8>>,
+}
+
+/// One enterprise entry of the V-I Vendor-Specific Information option (RFC 3925, option 125).
+#[derive(PartialEq, Clone, Debug)]
+pub struct VendorInfo {
+    pub enterprise: u32,
+    pub suboptions: Vec<RawDhcpOption>,
+}
+
+/// One route of the Classless Static Route option (RFC 3442, option 121).
+#[derive(PartialEq, Clone, Debug)]
+pub struct StaticRoute {
+    pub dest: Ipv4Addr,
+    pub prefix: u8,
+    pub router: Ipv4Addr,
+}
+
+/// Encodes a domain search list (RFC 3397, option 119): names in DNS wire format, with each
+/// suffix seen before replaced by a compression pointer.
+pub fn encode_domain_list(names: &[String]) -> Vec<u8> {
+    let mut out: Vec<u8> = vec![];
+    let mut suffixes: Vec<(String, usize)> = vec![];
+    for name in names {
+        let name = name.trim_end_matches('.').to_ascii_lowercase();
+        let labels: Vec<&str> = name.split('.').filter(|l| !l.is_empty()).collect();
+        let mut pointer = None;
+        for i in 0..labels.len() {
+            let suffix = labels[i..].join(".");
+            if let Some((_, offset)) = suffixes.iter().find(|(s, _)| *s == suffix) {
+                pointer = Some(*offset);
+                break;
+            }
+            // pointers only reach the first 16 KiB
+            if out.len() < 0x4000 {
+                suffixes.push((suffix, out.len()));
+            }
+            out.push(labels[i].len() as u8);
+            out.extend(labels[i].as_bytes());
+        }
+        match pointer {
+            Some(offset) => out.extend((0xc000 | offset as u16).to_be_bytes()),
+            None => out.push(0),
+        }
+    }
+    out
+}
+
+/// Encodes domain names in DNS wire format without compression, as DHCPv6 (RFC 3646) and
+/// router advertisements (RFC 8106) carry them.
+pub fn encode_domain_names(names: &[String]) -> Vec<u8> {
+    let mut out = vec![];
+    for name in names {
+        for label in name
+            .trim_end_matches('.')
+            .split('.')
+            .filter(|l| !l.is_empty())
+        {
+            out.push(label.len() as u8);
+            out.extend(label.to_ascii_lowercase().as_bytes());
+        }
+        out.push(0);
+    }
+    out
+}
+
+/// The Client FQDN option (RFC 4702, option 81).
+#[derive(PartialEq, Clone, Debug)]
+pub struct ClientFqdn {
+    pub flags: u8,
+    /// The name without a trailing dot.
+    pub name: String,
+}
+
+/// `S`: the server should update the A record (or, in replies, will).
+pub const FQDN_S: u8 = 0x01;
+/// `O`: set by the server when it overrides the client's `S`.
+pub const FQDN_O: u8 = 0x02;
+/// `E`: the name is in DNS wire format rather than ASCII.
+pub const FQDN_E: u8 = 0x04;
+/// `N`: the server should (or will) not update any records.
+pub const FQDN_N: u8 = 0x08;
+
+/// The Authentication option (RFC 3118, option 90).
+#[derive(PartialEq, Clone, Debug)]
+pub struct Authentication {
+    pub protocol: u8,
+    pub algorithm: u8,
+    /// Replay detection method.
+    pub rdm: u8,
+    pub replay: u64,
+    pub info: Vec<u8>,
+}
+
+/// The SIP servers of option 120 (RFC 3361), given either by name or by address.
+#[derive(PartialEq, Clone, Debug)]
+pub enum SipServers {
+    Names(Vec<String>),
+    Addrs(Vec<Ipv4Addr>),
+}
+
+#[derive(PartialEq, Clone, Debug)]
 pub enum DhcpOption {
     DhcpMessageType(MessageType),
     ServerIdentifier(Ipv4Addr),
@@ -17,8 +122,53 @@ pub enum DhcpOption {
     Router(Vec<Ipv4Addr>),
     DomainNameServer(Vec<Ipv4Addr>),
     IpAddressLeaseTime(u32),
+    RenewalTime(u32),
+    RebindingTime(u32),
     SubnetMask(Ipv4Addr),
+    TimeOffset(i32),
     Message(String),
+    VendorSpecificInformation(Vec<u8>),
+    VendorClassIdentifier(Vec<u8>),
+    UserClass(Vec<Vec<u8>>),
+    ClientArchitecture(Vec<u16>),
+    ClientNetworkInterface(Vec<u8>),
+    ClientMachineId(Vec<u8>),
+    ClientIdentifier(Vec<u8>),
+    TzPosixString(String),
+    TzDatabaseString(String),
+    RapidCommit,
+    MaxMessageSize(u16),
+    AssociatedIp(Vec<Ipv4Addr>),
+    /// A status code and its message.
+    StatusCode(u8, String),
+    /// The server's clock, in seconds since the epoch.
+    BaseTime(u32),
+    DhcpState(u8),
+    ClientFqdn(ClientFqdn),
+    Authentication(Authentication),
+    RelayAgentInformation(Vec<RawDhcpOption>),
+    SubnetSelection(Ipv4Addr),
+    Ipv6OnlyPreferred(u32),
+    CaptivePortal(String),
+    DomainName(String),
+    NtpServers(Vec<Ipv4Addr>),
+    TimeServers(Vec<Ipv4Addr>),
+    LogServers(Vec<Ipv4Addr>),
+    InterfaceMtu(u16),
+    BroadcastAddress(Ipv4Addr),
+    NetbiosNameServers(Vec<Ipv4Addr>),
+    NetbiosNodeType(u8),
+    DomainSearch(Vec<String>),
+    SipServers(SipServers),
+    TftpServerName(String),
+    TftpServerAddrs(Vec<Ipv4Addr>),
+    BootfileName(String),
+    CapwapAcV4(Vec<Ipv4Addr>),
+    ClasslessStaticRoute(Vec<StaticRoute>),
+    MudUrl(String),
+    Wpad(String),
+    VendorClass(Vec<VendorClass>),
+    VendorSpecific(Vec<VendorInfo>),
     Unrecognized(RawDhcpOption),
 }
 
@@ -69,14 +219,308 @@ impl DhcpOption {
                 code: IP_ADDRESS_LEASE_TIME,
                 data: secs.to_be_bytes().to_vec(),
             },
+            Self::RenewalTime(secs) => RawDhcpOption {
+                code: RENEWAL_TIME_VALUE,
+                data: secs.to_be_bytes().to_vec(),
+            },
+            Self::RebindingTime(secs) => RawDhcpOption {
+                code: REBINDING_TIME_VALUE,
+                data: secs.to_be_bytes().to_vec(),
+            },
             Self::SubnetMask(mask) => RawDhcpOption {
                 code: SUBNET_MASK,
                 data: mask.octets().to_vec(),
             },
+            Self::TimeOffset(secs) => RawDhcpOption {
+                code: TIME_OFFSET,
+                data: secs.to_be_bytes().to_vec(),
+            },
             Self::Message(msg) => RawDhcpOption {
                 code: MESSAGE,
                 data: msg.as_bytes().to_vec(),
             },
+            Self::VendorSpecificInformation(data) => RawDhcpOption {
+                code: VENDOR_SPECIFIC_INFORMATION,
+                data: data.clone(),
+            },
+            Self::VendorClassIdentifier(id) => RawDhcpOption {
+                code: VENDOR_CLASS_IDENTIFIER,
+                data: id.clone(),
+            },
+            Self::UserClass(classes) => RawDhcpOption {
+                code: USER_CLASS,
+                data: {
+                    let mut v = vec![];
+                    for c in classes {
+                        v.push(c.len() as u8);
+                        v.extend(c);
+                    }
+                    v
+                },
+            },
+            Self::ClientArchitecture(archs) => RawDhcpOption {
+                code: CLIENT_ARCHITECTURE,
+                data: archs.iter().flat_map(|a| a.to_be_bytes()).collect(),
+            },
+            Self::ClientNetworkInterface(nii) => RawDhcpOption {
+                code: CLIENT_NETWORK_INTERFACE_ID,
+                data: nii.clone(),
+            },
+            Self::ClientMachineId(id) => RawDhcpOption {
+                code: CLIENT_MACHINE_ID,
+                data: id.clone(),
+            },
+            Self::TzPosixString(tz) => RawDhcpOption {
+                code: TZ_POSIX_STRING,
+                data: tz.as_bytes().to_vec(),
+            },
+            Self::TzDatabaseString(tz) => RawDhcpOption {
+                code: TZ_DATABASE_STRING,
+                data: tz.as_bytes().to_vec(),
+            },
+            Self::StatusCode(code, message) => RawDhcpOption {
+                code: STATUS_CODE,
+                data: {
+                    let mut v = vec![*code];
+                    v.extend(message.as_bytes());
+                    v
+                },
+            },
+            Self::BaseTime(t) => RawDhcpOption {
+                code: BASE_TIME,
+                data: t.to_be_bytes().to_vec(),
+            },
+            Self::DhcpState(state) => RawDhcpOption {
+                code: DHCP_STATE,
+                data: vec![*state],
+            },
+            Self::Authentication(a) => RawDhcpOption {
+                code: AUTHENTICATION,
+                data: {
+                    let mut v = vec![a.protocol, a.algorithm, a.rdm];
+                    v.extend(a.replay.to_be_bytes());
+                    v.extend(&a.info);
+                    v
+                },
+            },
+            Self::MaxMessageSize(n) => RawDhcpOption {
+                code: MAXIMUM_DHCP_MESSAGE_SIZE,
+                data: n.to_be_bytes().to_vec(),
+            },
+            Self::AssociatedIp(addrs) => RawDhcpOption {
+                code: ASSOCIATED_IP,
+                data: addrs.iter().flat_map(|a| a.octets()).collect(),
+            },
+            Self::RapidCommit => RawDhcpOption {
+                code: RAPID_COMMIT,
+                data: vec![],
+            },
+            Self::ClientFqdn(fqdn) => RawDhcpOption {
+                code: CLIENT_FQDN,
+                data: {
+                    // RCODE1 and RCODE2 are deprecated; servers send 255
+                    let mut v = vec![fqdn.flags, 255, 255];
+                    if fqdn.flags & FQDN_E != 0 {
+                        v.extend(encode_domain_list(std::slice::from_ref(&fqdn.name)));
+                    } else {
+                        v.extend(fqdn.name.as_bytes());
+                    }
+                    v
+                },
+            },
+            Self::RelayAgentInformation(subs) => RawDhcpOption {
+                code: RELAY_AGENT_INFORMATION,
+                data: {
+                    let mut v = vec![];
+                    for o in subs {
+                        v.push(o.code);
+                        v.push(o.data.len() as u8);
+                        v.extend(&o.data);
+                    }
+                    v
+                },
+            },
+            Self::SubnetSelection(addr) => RawDhcpOption {
+                code: SUBNET_SELECTION,
+                data: addr.octets().to_vec(),
+            },
+            Self::Ipv6OnlyPreferred(secs) => RawDhcpOption {
+                code: IPV6_ONLY_PREFERRED,
+                data: secs.to_be_bytes().to_vec(),
+            },
+            Self::NtpServers(addrs) => RawDhcpOption {
+                code: NETWORK_TIME_PROTOCOL_SERVERS,
+                data: {
+                    let mut v = vec![];
+                    for a in addrs {
+                        v.extend(a.octets().iter());
+                    }
+                    v
+                },
+            },
+            Self::InterfaceMtu(mtu) => RawDhcpOption {
+                code: INTERFACE_MTU,
+                data: mtu.to_be_bytes().to_vec(),
+            },
+            Self::BroadcastAddress(addr) => RawDhcpOption {
+                code: BROADCAST_ADDRESS,
+                data: addr.octets().to_vec(),
+            },
+            Self::TimeServers(addrs) => RawDhcpOption {
+                code: TIME_SERVER,
+                data: {
+                    let mut v = vec![];
+                    for a in addrs {
+                        v.extend(a.octets().iter());
+                    }
+                    v
+                },
+            },
+            Self::LogServers(addrs) => RawDhcpOption {
+                code: LOG_SERVER,
+                data: {
+                    let mut v = vec![];
+                    for a in addrs {
+                        v.extend(a.octets().iter());
+                    }
+                    v
+                },
+            },
+            Self::NetbiosNameServers(addrs) => RawDhcpOption {
+                code: NETBIOS_OVER_TCPIP_NAME_SERVER,
+                data: {
+                    let mut v = vec![];
+                    for a in addrs {
+                        v.extend(a.octets().iter());
+                    }
+                    v
+                },
+            },
+            Self::NetbiosNodeType(t) => RawDhcpOption {
+                code: NETBIOS_OVER_TCPIP_NODE_TYPE,
+                data: vec![*t],
+            },
+            Self::CapwapAcV4(addrs) => RawDhcpOption {
+                code: CAPWAP_AC_V4,
+                data: {
+                    let mut v = vec![];
+                    for a in addrs {
+                        v.extend(a.octets().iter());
+                    }
+                    v
+                },
+            },
+            Self::CaptivePortal(url) => RawDhcpOption {
+                code: CAPTIVE_PORTAL,
+                data: url.as_bytes().to_vec(),
+            },
+            Self::DomainName(name) => RawDhcpOption {
+                code: DOMAIN_NAME,
+                data: name.as_bytes().to_vec(),
+            },
+            Self::DomainSearch(names) => RawDhcpOption {
+                code: DOMAIN_SEARCH,
+                data: encode_domain_list(names),
+            },
+            Self::SipServers(servers) => RawDhcpOption {
+                code: SIP_SERVERS,
+                // the first byte tells which form follows
+                data: match servers {
+                    SipServers::Names(names) => {
+                        // uncompressed, RFC 3361 leaves unclear where pointers count from
+                        let mut v = vec![0];
+                        for name in names {
+                            v.extend(encode_domain_list(std::slice::from_ref(name)));
+                        }
+                        v
+                    }
+                    SipServers::Addrs(addrs) => {
+                        let mut v = vec![1];
+                        for a in addrs {
+                            v.extend(a.octets().iter());
+                        }
+                        v
+                    }
+                },
+            },
+            Self::TftpServerName(name) => RawDhcpOption {
+                code: TFTP_SERVER_NAME,
+                data: name.as_bytes().to_vec(),
+            },
+            Self::TftpServerAddrs(addrs) => RawDhcpOption {
+                code: TFTP_SERVER_ADDRESS,
+                data: {
+                    let mut v = vec![];
+                    for a in addrs {
+                        v.extend(a.octets().iter());
+                    }
+                    v
+                },
+            },
+            Self::BootfileName(name) => RawDhcpOption {
+                code: BOOTFILE_NAME,
+                data: name.as_bytes().to_vec(),
+            },
+            Self::ClasslessStaticRoute(routes) => RawDhcpOption {
+                code: CLASSLESS_ROUTE_FORMAT,
+                data: {
+                    let mut v = vec![];
+                    for r in routes {
+                        // only the significant octets of the destination are sent
+                        v.push(r.prefix);
+                        v.extend(&r.dest.octets()[..r.prefix.div_ceil(8) as usize]);
+                        v.extend(r.router.octets());
+                    }
+                    v
+                },
+            },
+            Self::ClientIdentifier(id) => RawDhcpOption {
+                code: CLIENT_IDENTIFIER,
+                data: id.clone(),
+            },
+            Self::MudUrl(url) => RawDhcpOption {
+                code: MUD_URL,
+                data: url.as_bytes().to_vec(),
+            },
+            Self::Wpad(url) => RawDhcpOption {
+                code: WPAD,
+                data: url.as_bytes().to_vec(),
+            },
+            Self::VendorClass(classes) => RawDhcpOption {
+                code: V_I_VENDOR_CLASS,
+                data: {
+                    let mut v = vec![];
+                    for c in classes {
+                        let mut d = vec![];
+                        for item in &c.data {
+                            d.push(item.len() as u8);
+                            d.extend(item);
+                        }
+                        v.extend(c.enterprise.to_be_bytes());
+                        v.push(d.len() as u8);
+                        v.extend(d);
+                    }
+                    v
+                },
+            },
+            Self::VendorSpecific(infos) => RawDhcpOption {
+                code: V_I_VENDOR_SPECIFIC_INFORMATION,
+                data: {
+                    let mut v = vec![];
+                    for i in infos {
+                        let mut d = vec![];
+                        for o in &i.suboptions {
+                            d.push(o.code);
+                            d.push(o.data.len() as u8);
+                            d.extend(&o.data);
+                        }
+                        v.extend(i.enterprise.to_be_bytes());
+                        v.push(d.len() as u8);
+                        v.extend(d);
+                    }
+                    v
+                },
+            },
             Self::Unrecognized(raw) => raw.clone(),
         }
     }
@@ -91,8 +535,51 @@ impl DhcpOption {
             Self::Router(_) => ROUTER,
             Self::DomainNameServer(_) => DOMAIN_NAME_SERVER,
             Self::IpAddressLeaseTime(_) => IP_ADDRESS_LEASE_TIME,
+            Self::RenewalTime(_) => RENEWAL_TIME_VALUE,
+            Self::RebindingTime(_) => REBINDING_TIME_VALUE,
             Self::SubnetMask(_) => SUBNET_MASK,
+            Self::TimeOffset(_) => TIME_OFFSET,
             Self::Message(_) => MESSAGE,
+            Self::VendorSpecificInformation(_) => VENDOR_SPECIFIC_INFORMATION,
+            Self::VendorClassIdentifier(_) => VENDOR_CLASS_IDENTIFIER,
+            Self::UserClass(_) => USER_CLASS,
+            Self::ClientArchitecture(_) => CLIENT_ARCHITECTURE,
+            Self::ClientNetworkInterface(_) => CLIENT_NETWORK_INTERFACE_ID,
+            Self::ClientMachineId(_) => CLIENT_MACHINE_ID,
+            Self::ClientIdentifier(_) => CLIENT_IDENTIFIER,
+            Self::TzPosixString(_) => TZ_POSIX_STRING,
+            Self::TzDatabaseString(_) => TZ_DATABASE_STRING,
+            Self::RapidCommit => RAPID_COMMIT,
+            Self::MaxMessageSize(_) => MAXIMUM_DHCP_MESSAGE_SIZE,
+            Self::AssociatedIp(_) => ASSOCIATED_IP,
+            Self::StatusCode(..) => STATUS_CODE,
+            Self::BaseTime(_) => BASE_TIME,
+            Self::DhcpState(_) => DHCP_STATE,
+            Self::ClientFqdn(_) => CLIENT_FQDN,
+            Self::Authentication(_) => AUTHENTICATION,
+            Self::RelayAgentInformation(_) => RELAY_AGENT_INFORMATION,
+            Self::SubnetSelection(_) => SUBNET_SELECTION,
+            Self::Ipv6OnlyPreferred(_) => IPV6_ONLY_PREFERRED,
+            Self::CapwapAcV4(_) => CAPWAP_AC_V4,
+            Self::CaptivePortal(_) => CAPTIVE_PORTAL,
+            Self::DomainName(_) => DOMAIN_NAME,
+            Self::NtpServers(_) => NETWORK_TIME_PROTOCOL_SERVERS,
+            Self::TimeServers(_) => TIME_SERVER,
+            Self::LogServers(_) => LOG_SERVER,
+            Self::InterfaceMtu(_) => INTERFACE_MTU,
+            Self::BroadcastAddress(_) => BROADCAST_ADDRESS,
+            Self::NetbiosNameServers(_) => NETBIOS_OVER_TCPIP_NAME_SERVER,
+            Self::NetbiosNodeType(_) => NETBIOS_OVER_TCPIP_NODE_TYPE,
+            Self::DomainSearch(_) => DOMAIN_SEARCH,
+            Self::SipServers(_) => SIP_SERVERS,
+            Self::TftpServerName(_) => TFTP_SERVER_NAME,
+            Self::TftpServerAddrs(_) => TFTP_SERVER_ADDRESS,
+            Self::BootfileName(_) => BOOTFILE_NAME,
+            Self::ClasslessStaticRoute(_) => CLASSLESS_ROUTE_FORMAT,
+            Self::MudUrl(_) => MUD_URL,
+            Self::Wpad(_) => WPAD,
+            Self::VendorClass(_) => V_I_VENDOR_CLASS,
+            Self::VendorSpecific(_) => V_I_VENDOR_SPECIFIC_INFORMATION,
             Self::Unrecognized(x) => x.code,
         }
     }
@@ -152,6 +639,8 @@ pub const NETWORK_INFORMATION_SERVICE_DOMAIN: u8 = 40;
 pub const NETWORK_INFORMATION_SERVERS: u8 = 41;
 pub const NETWORK_TIME_PROTOCOL_SERVERS: u8 = 42;
 pub const VENDOR_SPECIFIC_INFORMATION: u8 = 43;
+/// PXE option 43 sub-option: how the client finds its boot server.
+pub const PXE_DISCOVERY_CONTROL: u8 = 6;
 pub const NETBIOS_OVER_TCPIP_NAME_SERVER: u8 = 44;
 pub const NETBIOS_OVER_TCPIP_DATAGRAM_DISTRIBUTION_SERVER: u8 = 45;
 pub const NETBIOS_OVER_TCPIP_NODE_TYPE: u8 = 46;
@@ -170,7 +659,17 @@ pub const DEFAULT_INTERNET_RELAY_CHAT_SERVER: u8 = 74;
 pub const STREETTALK_SERVER: u8 = 75;
 pub const STREETTALK_DIRECTORY_ASSISTANCE: u8 = 76;
 
+pub const RAPID_COMMIT: u8 = 80;
+pub const CLIENT_FQDN: u8 = 81;
 pub const RELAY_AGENT_INFORMATION: u8 = 82;
+pub const AUTHENTICATION: u8 = 90;
+pub const ASSOCIATED_IP: u8 = 92;
+
+// RELAY AGENT INFORMATION SUB-OPTIONS (RFC 3046)
+pub const AGENT_CIRCUIT_ID: u8 = 1;
+pub const AGENT_REMOTE_ID: u8 = 2;
+pub const AGENT_LINK_SELECTION: u8 = 5;
+pub const AGENT_RELAY_ID: u8 = 12;
 
 // DHCP EXTENSIONS
 pub const REQUESTED_IP_ADDRESS: u8 = 50;
@@ -192,12 +691,43 @@ pub const BOOTFILE_NAME: u8 = 67;
 pub const USER_CLASS: u8 = 77;
 
 pub const CLIENT_ARCHITECTURE: u8 = 93;
+pub const CLIENT_NETWORK_INTERFACE_ID: u8 = 94;
+pub const CLIENT_MACHINE_ID: u8 = 97;
 
 pub const TZ_POSIX_STRING: u8 = 100;
 pub const TZ_DATABASE_STRING: u8 = 101;
 
+pub const IPV6_ONLY_PREFERRED: u8 = 108;
+
+pub const CAPTIVE_PORTAL: u8 = 114;
+
+pub const DOMAIN_SEARCH: u8 = 119;
+pub const SIP_SERVERS: u8 = 120;
+
+pub const SUBNET_SELECTION: u8 = 118;
+
 pub const CLASSLESS_ROUTE_FORMAT: u8 = 121;
 
+pub const TFTP_SERVER_ADDRESS: u8 = 150;
+
+// BULK AND ACTIVE LEASEQUERY (RFC 6926, RFC 7724)
+pub const STATUS_CODE: u8 = 151;
+pub const BASE_TIME: u8 = 152;
+pub const DHCP_STATE: u8 = 156;
+
+// dhcp-state values
+pub const STATE_ACTIVE: u8 = 2;
+pub const STATE_EXPIRED: u8 = 3;
+
+pub const CAPWAP_AC_V4: u8 = 138;
+
+pub const V_I_VENDOR_CLASS: u8 = 124;
+pub const V_I_VENDOR_SPECIFIC_INFORMATION: u8 = 125;
+
+pub const MUD_URL: u8 = 161;
+
+pub const WPAD: u8 = 252;
+
 /// Returns title of DHCP Option code, if known.
 pub fn title(code: u8) -> Option<&'static str> {
     Some(match code {
@@ -275,7 +805,11 @@ pub fn title(code: u8) -> Option<&'static str> {
         STREETTALK_SERVER => "StreetTalk Server",
         STREETTALK_DIRECTORY_ASSISTANCE => "StreetTalk Directory Assistance (STDA) Server",
 
+        RAPID_COMMIT => "Rapid Commit",
+        CLIENT_FQDN => "Client FQDN",
         RELAY_AGENT_INFORMATION => "Relay Agent Information",
+        AUTHENTICATION => "Authentication",
+        ASSOCIATED_IP => "Associated IP",
 
         // DHCP EXTENSIONS
         REQUESTED_IP_ADDRESS => "Requested IP Address",
@@ -298,11 +832,33 @@ pub fn title(code: u8) -> Option<&'static str> {
         USER_CLASS => "User Class",
 
         CLIENT_ARCHITECTURE => "Client Architecture",
+        CLIENT_NETWORK_INTERFACE_ID => "Client Network Interface Identifier",
+        CLIENT_MACHINE_ID => "Client Machine Identifier",
 
         TZ_POSIX_STRING => "TZ-POSIX String",
         TZ_DATABASE_STRING => "TZ-Database String",
+        IPV6_ONLY_PREFERRED => "IPv6-Only Preferred",
+        CAPTIVE_PORTAL => "Captive-Portal",
+        DOMAIN_SEARCH => "Domain Search",
+        SIP_SERVERS => "SIP Servers",
+        SUBNET_SELECTION => "Subnet Selection",
         CLASSLESS_ROUTE_FORMAT => "Classless Route Format",
 
+        TFTP_SERVER_ADDRESS => "TFTP Server Address",
+
+        STATUS_CODE => "Status Code",
+        BASE_TIME => "Base Time",
+        DHCP_STATE => "DHCP State",
+
+        CAPWAP_AC_V4 => "CAPWAP Access Controller addresses",
+
+        V_I_VENDOR_CLASS => "V-I Vendor Class",
+        V_I_VENDOR_SPECIFIC_INFORMATION => "V-I Vendor-Specific Information",
+
+        MUD_URL => "Manufacturer Usage Description URL",
+
+        WPAD => "Web Proxy Auto-Discovery",
+
         _ => return None,
     })
 }
@@ -348,6 +904,34 @@ pub enum MessageType {
     /// Client to server, asking only for local configuration parameters; client already has
     /// externally configured network address.
     Inform = 8,
+
+    /// Server to client, asking it to renew its lease right away (RFC 3203).
+    ForceRenew = 9,
+
+    /// Relay agent to server, asking who holds an address, hardware address or client
+    /// identifier (RFC 4388).
+    LeaseQuery = 10,
+
+    /// Server to relay agent: the queried address is ours but not leased.
+    LeaseUnassigned = 11,
+
+    /// Server to relay agent: nothing is known about the queried client or address.
+    LeaseUnknown = 12,
+
+    /// Server to relay agent: the queried lease is active, its data follows.
+    LeaseActive = 13,
+
+    /// Relay agent to server over TCP, asking for many leases at once (RFC 6926).
+    BulkLeaseQuery = 14,
+
+    /// Server to relay agent: all answers to a bulk leasequery were sent.
+    LeaseQueryDone = 15,
+
+    /// Relay agent to server over TCP, asking for all leases and then their changes (RFC 7724).
+    ActiveLeaseQuery = 16,
+
+    /// Server to relay agent: the state of an active leasequery.
+    LeaseQueryStatus = 17,
 }
 
 impl MessageType {
@@ -361,6 +945,15 @@ impl MessageType {
             6 => Ok(MessageType::Nak),
             7 => Ok(MessageType::Release),
             8 => Ok(MessageType::Inform),
+            9 => Ok(MessageType::ForceRenew),
+            10 => Ok(MessageType::LeaseQuery),
+            11 => Ok(MessageType::LeaseUnassigned),
+            12 => Ok(MessageType::LeaseUnknown),
+            13 => Ok(MessageType::LeaseActive),
+            14 => Ok(MessageType::BulkLeaseQuery),
+            15 => Ok(MessageType::LeaseQueryDone),
+            16 => Ok(MessageType::ActiveLeaseQuery),
+            17 => Ok(MessageType::LeaseQueryStatus),
             _ => Err(format!("Invalid DHCP Message Type: {:?}", val)),
         }
     }
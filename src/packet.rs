@@ -1,7 +1,9 @@
+use crate::hwaddr::{self, HwAddr};
 use crate::options::*;
 
 use std::net::Ipv4Addr;
 
+#[derive(Debug)]
 pub enum CustomErr<I> {
     NomError((I, ErrorKind)),
     NonUtf8String,
@@ -9,6 +11,8 @@ pub enum CustomErr<I> {
     InvalidHlen,
 }
 
+#[allow(dead_code)]
+#[derive(Debug)]
 pub enum ErrorKind {
     Tag,
     MapRes,
@@ -20,7 +24,7 @@ pub enum ErrorKind {
 type IResult<I, O> = Result<(I, O), CustomErr<I>>;
 
 /// DHCP Packet Structure
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct Packet {
     pub reply: bool, // false = request, true = reply
     pub hops: u8,
@@ -31,7 +35,11 @@ pub struct Packet {
     pub yiaddr: Ipv4Addr,
     pub siaddr: Ipv4Addr,
     pub giaddr: Ipv4Addr,
-    pub chaddr: [u8; 6],
+    pub chaddr: HwAddr,
+    /// BOOTP server host name, empty when unset.
+    pub sname: String,
+    /// BOOTP boot file name, empty when unset.
+    pub file: String,
     pub options: Vec<DhcpOption>,
 }
 
@@ -54,17 +62,17 @@ fn decode_ipv4(p: &[u8]) -> IResult<&[u8], Ipv4Addr> {
     let (input, addr) = custom_take(4usize)(p)?;
     Ok((input, Ipv4Addr::new(addr[0], addr[1], addr[2], addr[3])))
 }
-fn custom_many0<I, O, F>(mut f: F) -> impl FnMut(I) -> IResult<I, Vec<O>>
+/// Applies `f` until the input is used up.
+fn custom_many0<'a, O, F>(mut f: F) -> impl FnMut(&'a [u8]) -> IResult<&'a [u8], Vec<O>>
 where
-    I: Clone + PartialEq,
-    F: FnMut(I) -> IResult<I, O>,
+    F: FnMut(&'a [u8]) -> IResult<&'a [u8], O>,
 {
-    move |input: I| {
+    move |input: &'a [u8]| {
         let mut acc = Vec::new();
-        let mut remaining = input.clone();
+        let mut remaining = input;
 
-        loop {
-            match f(remaining.clone()) {
+        while !remaining.is_empty() {
+            match f(remaining) {
                 Ok((input, o)) => {
                     if input == remaining {
                         return Ok((input, acc));
@@ -76,17 +84,17 @@ where
                 Err(e) => return Err(e),
             }
         }
+        Ok((remaining, acc))
     }
 }
-pub fn decode_option(input: &[u8]) -> IResult<&[u8], DhcpOption> {
-    let (input, code) = custom_be_u8(input)?;
-    assert!(code != END);
-
-    let (input, len) = custom_be_u8(input)?;
-    let (input, data) = custom_take(len.into())(input)?;
+/// Decodes the payload of option `code`.
+fn decode_option(code: u8, data: &[u8]) -> Result<DhcpOption, CustomErr<&[u8]>> {
     let option = match code {
         DHCP_MESSAGE_TYPE => {
-            DhcpOption::DhcpMessageType(match MessageType::from(custom_be_u8(data)?.1) {
+            if data.len() != 1 {
+                return Err(CustomErr::UnrecognizedMessageType);
+            }
+            DhcpOption::DhcpMessageType(match MessageType::from(data[0]) {
                 Ok(x) => x,
                 Err(_) => return Err(CustomErr::UnrecognizedMessageType),
             })
@@ -101,17 +109,276 @@ pub fn decode_option(input: &[u8]) -> IResult<&[u8], DhcpOption> {
         ROUTER => DhcpOption::Router(custom_many0(decode_ipv4)(data)?.1),
         DOMAIN_NAME_SERVER => DhcpOption::DomainNameServer(custom_many0(decode_ipv4)(data)?.1),
         IP_ADDRESS_LEASE_TIME => DhcpOption::IpAddressLeaseTime(custom_be_u32(data)?.1),
+        RENEWAL_TIME_VALUE => DhcpOption::RenewalTime(custom_be_u32(data)?.1),
+        REBINDING_TIME_VALUE => DhcpOption::RebindingTime(custom_be_u32(data)?.1),
         SUBNET_MASK => DhcpOption::SubnetMask(decode_ipv4(data)?.1),
+        TIME_OFFSET => DhcpOption::TimeOffset(custom_be_u32(data)?.1 as i32),
         MESSAGE => DhcpOption::Message(match std::str::from_utf8(data) {
             Ok(s) => s.to_string(),
             Err(_) => return Err(CustomErr::NonUtf8String),
         }),
+        VENDOR_SPECIFIC_INFORMATION => DhcpOption::VendorSpecificInformation(data.to_vec()),
+        VENDOR_CLASS_IDENTIFIER => DhcpOption::VendorClassIdentifier(data.to_vec()),
+        USER_CLASS => DhcpOption::UserClass(decode_user_classes(data)),
+        CLIENT_ARCHITECTURE => DhcpOption::ClientArchitecture(
+            data.chunks_exact(2)
+                .map(|c| u16::from_be_bytes([c[0], c[1]]))
+                .collect(),
+        ),
+        CLIENT_NETWORK_INTERFACE_ID => DhcpOption::ClientNetworkInterface(data.to_vec()),
+        CLIENT_MACHINE_ID => DhcpOption::ClientMachineId(data.to_vec()),
+        TZ_POSIX_STRING => DhcpOption::TzPosixString(match std::str::from_utf8(data) {
+            Ok(s) => s.to_string(),
+            Err(_) => return Err(CustomErr::NonUtf8String),
+        }),
+        TZ_DATABASE_STRING => DhcpOption::TzDatabaseString(match std::str::from_utf8(data) {
+            Ok(s) => s.to_string(),
+            Err(_) => return Err(CustomErr::NonUtf8String),
+        }),
+        RAPID_COMMIT => DhcpOption::RapidCommit,
+        MAXIMUM_DHCP_MESSAGE_SIZE => DhcpOption::MaxMessageSize(custom_be_u16(data)?.1),
+        ASSOCIATED_IP => DhcpOption::AssociatedIp(custom_many0(decode_ipv4)(data)?.1),
+        STATUS_CODE => match data {
+            [code, message @ ..] => {
+                DhcpOption::StatusCode(*code, String::from_utf8_lossy(message).into_owned())
+            }
+            [] => return Err(CustomErr::InvalidHlen),
+        },
+        BASE_TIME => DhcpOption::BaseTime(custom_be_u32(data)?.1),
+        DHCP_STATE => match data {
+            [state] => DhcpOption::DhcpState(*state),
+            _ => return Err(CustomErr::InvalidHlen),
+        },
+        CLIENT_FQDN => DhcpOption::ClientFqdn(decode_fqdn(data)?),
+        AUTHENTICATION => match data {
+            [protocol, algorithm, rdm, rest @ ..] if rest.len() >= 8 => {
+                DhcpOption::Authentication(Authentication {
+                    protocol: *protocol,
+                    algorithm: *algorithm,
+                    rdm: *rdm,
+                    replay: u64::from_be_bytes(rest[..8].try_into().unwrap()),
+                    info: rest[8..].to_vec(),
+                })
+            }
+            _ => return Err(CustomErr::InvalidHlen),
+        },
+        RELAY_AGENT_INFORMATION => DhcpOption::RelayAgentInformation(decode_suboptions(data)?.1),
+        IPV6_ONLY_PREFERRED => DhcpOption::Ipv6OnlyPreferred(custom_be_u32(data)?.1),
+        CAPTIVE_PORTAL => DhcpOption::CaptivePortal(match std::str::from_utf8(data) {
+            Ok(s) => s.to_string(),
+            Err(_) => return Err(CustomErr::NonUtf8String),
+        }),
+        DOMAIN_NAME => DhcpOption::DomainName(match std::str::from_utf8(data) {
+            Ok(s) => s.to_string(),
+            Err(_) => return Err(CustomErr::NonUtf8String),
+        }),
+        DOMAIN_SEARCH => DhcpOption::DomainSearch(decode_domain_list(data)?),
+        SIP_SERVERS => DhcpOption::SipServers(match data.split_first() {
+            Some((0, names)) => SipServers::Names(decode_domain_list(names)?),
+            Some((1, addrs)) => SipServers::Addrs(custom_many0(decode_ipv4)(addrs)?.1),
+            _ => return Err(CustomErr::InvalidHlen),
+        }),
+        TFTP_SERVER_NAME => DhcpOption::TftpServerName(match std::str::from_utf8(data) {
+            Ok(s) => s.to_string(),
+            Err(_) => return Err(CustomErr::NonUtf8String),
+        }),
+        TFTP_SERVER_ADDRESS => DhcpOption::TftpServerAddrs(custom_many0(decode_ipv4)(data)?.1),
+        BOOTFILE_NAME => DhcpOption::BootfileName(match std::str::from_utf8(data) {
+            Ok(s) => s.to_string(),
+            Err(_) => return Err(CustomErr::NonUtf8String),
+        }),
+        NETWORK_TIME_PROTOCOL_SERVERS => DhcpOption::NtpServers(custom_many0(decode_ipv4)(data)?.1),
+        TIME_SERVER => DhcpOption::TimeServers(custom_many0(decode_ipv4)(data)?.1),
+        LOG_SERVER => DhcpOption::LogServers(custom_many0(decode_ipv4)(data)?.1),
+        INTERFACE_MTU => DhcpOption::InterfaceMtu(custom_be_u16(data)?.1),
+        BROADCAST_ADDRESS => DhcpOption::BroadcastAddress(decode_ipv4(data)?.1),
+        NETBIOS_OVER_TCPIP_NAME_SERVER => {
+            DhcpOption::NetbiosNameServers(custom_many0(decode_ipv4)(data)?.1)
+        }
+        NETBIOS_OVER_TCPIP_NODE_TYPE => DhcpOption::NetbiosNodeType(custom_be_u8(data)?.1),
+        CAPWAP_AC_V4 => DhcpOption::CapwapAcV4(custom_many0(decode_ipv4)(data)?.1),
+        SUBNET_SELECTION => DhcpOption::SubnetSelection(decode_ipv4(data)?.1),
+        CLASSLESS_ROUTE_FORMAT => DhcpOption::ClasslessStaticRoute(decode_routes(data)?.1),
+        CLIENT_IDENTIFIER => DhcpOption::ClientIdentifier(data.to_vec()),
+        MUD_URL => DhcpOption::MudUrl(match std::str::from_utf8(data) {
+            Ok(s) => s.to_string(),
+            Err(_) => return Err(CustomErr::NonUtf8String),
+        }),
+        WPAD => DhcpOption::Wpad(match std::str::from_utf8(data) {
+            Ok(s) => s.to_string(),
+            Err(_) => return Err(CustomErr::NonUtf8String),
+        }),
+        V_I_VENDOR_CLASS => DhcpOption::VendorClass(decode_vendor_classes(data)?.1),
+        V_I_VENDOR_SPECIFIC_INFORMATION => DhcpOption::VendorSpecific(decode_vendor_infos(data)?.1),
         _ => DhcpOption::Unrecognized(RawDhcpOption {
             code,
             data: data.to_vec(),
         }),
     };
-    Ok((input, option))
+    Ok(option)
+}
+
+/// Parses option 121: per route a prefix length, the significant destination octets and the
+/// router.
+fn decode_routes(input: &[u8]) -> IResult<&[u8], Vec<StaticRoute>> {
+    let mut routes = Vec::new();
+    let mut rest = input;
+    while !rest.is_empty() {
+        let (input, prefix) = custom_be_u8(rest)?;
+        if prefix > 32 {
+            return Err(CustomErr::InvalidHlen);
+        }
+        let (input, dest) = custom_take(prefix.div_ceil(8).into())(input)?;
+        let (input, router) = decode_ipv4(input)?;
+        let mut octets = [0u8; 4];
+        octets[..dest.len()].copy_from_slice(dest);
+        routes.push(StaticRoute {
+            dest: Ipv4Addr::from(octets),
+            prefix,
+            router,
+        });
+        rest = input;
+    }
+    Ok((rest, routes))
+}
+
+/// Reads option 81: flags, the two deprecated RCODEs, and the name in ASCII or, with the `E`
+/// flag, as DNS labels (possibly without the final root label).
+fn decode_fqdn(data: &[u8]) -> Result<ClientFqdn, CustomErr<&[u8]>> {
+    let [flags, _, _, name @ ..] = data else {
+        return Err(CustomErr::InvalidHlen);
+    };
+    let name = if flags & FQDN_E != 0 {
+        let mut labels = vec![];
+        let mut rest = name;
+        while let [len, tail @ ..] = rest {
+            let len = *len as usize;
+            if len == 0 {
+                break;
+            }
+            let label = tail.get(..len).ok_or(CustomErr::InvalidHlen)?;
+            labels.push(String::from_utf8_lossy(label).into_owned());
+            rest = &tail[len..];
+        }
+        labels.join(".")
+    } else {
+        match std::str::from_utf8(name) {
+            Ok(s) => s.trim_end_matches('.').to_string(),
+            Err(_) => return Err(CustomErr::NonUtf8String),
+        }
+    };
+    Ok(ClientFqdn {
+        flags: *flags,
+        name,
+    })
+}
+
+/// Reads an option 119 domain list, following compression pointers.
+fn decode_domain_list(data: &[u8]) -> Result<Vec<String>, CustomErr<&[u8]>> {
+    let mut names = vec![];
+    let mut pos = 0;
+    while pos < data.len() {
+        let mut labels: Vec<String> = vec![];
+        let mut at = pos;
+        let mut end = None;
+        // every pointer has to go backwards, which bounds the walk
+        let mut limit = at;
+        loop {
+            let len = *data.get(at).ok_or(CustomErr::InvalidHlen)? as usize;
+            if len == 0 {
+                end.get_or_insert(at + 1);
+                break;
+            }
+            if len & 0xc0 == 0xc0 {
+                let low = *data.get(at + 1).ok_or(CustomErr::InvalidHlen)? as usize;
+                let target = (len & 0x3f) << 8 | low;
+                end.get_or_insert(at + 2);
+                if target >= limit {
+                    return Err(CustomErr::InvalidHlen);
+                }
+                limit = target;
+                at = target;
+                continue;
+            }
+            let label = data
+                .get(at + 1..at + 1 + len)
+                .ok_or(CustomErr::InvalidHlen)?;
+            labels.push(String::from_utf8_lossy(label).into_owned());
+            at += 1 + len;
+        }
+        names.push(labels.join("."));
+        pos = end.unwrap_or(data.len());
+    }
+    Ok(names)
+}
+
+/// Parses a sequence of `code, len, data` sub-options, as carried inside options 43, 82 and 125.
+pub fn decode_suboptions(input: &[u8]) -> IResult<&[u8], Vec<RawDhcpOption>> {
+    let mut suboptions = Vec::new();
+    let mut rest = input;
+    while !rest.is_empty() {
+        let (input, code) = custom_be_u8(rest)?;
+        let (input, len) = custom_be_u8(input)?;
+        let (input, data) = custom_take(len.into())(input)?;
+        suboptions.push(RawDhcpOption {
+            code,
+            data: data.to_vec(),
+        });
+        rest = input;
+    }
+    Ok((rest, suboptions))
+}
+
+/// Splits option 77 into its RFC 3004 instances. Many clients (iPXE, Windows) send a bare string
+/// instead, so data that does not parse as length-prefixed instances is taken as a single value.
+fn decode_user_classes(input: &[u8]) -> Vec<Vec<u8>> {
+    let mut classes = Vec::new();
+    let mut rest = input;
+    while let Some((&len, tail)) = rest.split_first() {
+        let len = len as usize;
+        if len == 0 || tail.len() < len {
+            return vec![input.to_vec()];
+        }
+        classes.push(tail[..len].to_vec());
+        rest = &tail[len..];
+    }
+    classes
+}
+
+fn decode_vendor_classes(input: &[u8]) -> IResult<&[u8], Vec<VendorClass>> {
+    let mut classes = Vec::new();
+    let mut rest = input;
+    while !rest.is_empty() {
+        let (input, enterprise) = custom_be_u32(rest)?;
+        let (input, len) = custom_be_u8(input)?;
+        let (input, mut body) = custom_take(len.into())(input)?;
+        let mut data = Vec::new();
+        while !body.is_empty() {
+            let (b, l) = custom_be_u8(body)?;
+            let (b, item) = custom_take(l.into())(b)?;
+            data.push(item.to_vec());
+            body = b;
+        }
+        classes.push(VendorClass { enterprise, data });
+        rest = input;
+    }
+    Ok((rest, classes))
+}
+
+fn decode_vendor_infos(input: &[u8]) -> IResult<&[u8], Vec<VendorInfo>> {
+    let mut infos = Vec::new();
+    let mut rest = input;
+    while !rest.is_empty() {
+        let (input, enterprise) = custom_be_u32(rest)?;
+        let (input, len) = custom_be_u8(input)?;
+        let (input, body) = custom_take(len.into())(input)?;
+        infos.push(VendorInfo {
+            enterprise,
+            suboptions: decode_suboptions(body)?.1,
+        });
+        rest = input;
+    }
+    Ok((rest, infos))
 }
 fn custom_take<'a>(n: usize) -> impl Fn(&'a [u8]) -> IResult<&'a [u8], &'a [u8]> {
     move |input: &'a [u8]| {
@@ -158,11 +425,45 @@ fn custom_be_u32(input: &[u8]) -> IResult<&[u8], u32> {
 }
 
 /// Parses Packet from byte array
+/// A NUL-terminated BOOTP header field.
+fn decode_field(field: &[u8]) -> String {
+    let end = field.iter().position(|b| *b == 0).unwrap_or(field.len());
+    String::from_utf8_lossy(&field[..end]).into_owned()
+}
+
+/// Collects the options in `rest` up to END, skipping padding, and returns what follows END.
+/// An option split over several instances (RFC 3396) is joined back together.
+fn collect_options<'a>(mut rest: &'a [u8], raw: &mut Vec<RawDhcpOption>) -> &'a [u8] {
+    loop {
+        while let [PAD, tail @ ..] = rest {
+            rest = tail;
+        }
+        let [code, len, tail @ ..] = rest else {
+            break;
+        };
+        if *code == END {
+            break;
+        }
+        let Some(data) = tail.get(..*len as usize) else {
+            break;
+        };
+        match raw.iter_mut().find(|o| o.code == *code) {
+            Some(o) => o.data.extend(data),
+            None => raw.push(RawDhcpOption {
+                code: *code,
+                data: data.to_vec(),
+            }),
+        }
+        rest = &tail[data.len()..];
+    }
+    rest.get(1..).unwrap_or(rest) // Skip the END tag byte
+}
+
 fn decode(input: &[u8]) -> IResult<&[u8], Packet> {
     let (options_input, input) = custom_take(236usize)(input)?;
 
     let (input, reply) = decode_reply(input)?;
-    let (input, _htype) = custom_take(1usize)(input)?;
+    let (input, htype) = custom_be_u8(input)?;
     let (input, hlen) = custom_be_u8(input)?;
     let (input, hops) = custom_be_u8(input)?;
     let (input, xid) = custom_be_u32(input)?;
@@ -173,26 +474,53 @@ fn decode(input: &[u8]) -> IResult<&[u8], Packet> {
     let (input, siaddr) = decode_ipv4(input)?;
     let (input, giaddr) = decode_ipv4(input)?;
 
-    if hlen != 6 {
+    if hlen > 16 {
         return Err(CustomErr::InvalidHlen);
     }
-    let (_, chaddr) = custom_take(6usize)(input)?;
-
-    let input = options_input;
-    let (input, _) = custom_tag(&COOKIE)(input)?;
-
-    let mut options = Vec::new();
-    let mut rest = input;
-
-    while let Ok((new_rest, option)) = decode_option(rest) {
-        rest = new_rest;
-        options.push(option);
-        if rest.starts_with(&[END]) {
-            break;
-        }
+    let (input, chaddr) = custom_take(16usize)(input)?;
+    let (input, sname) = custom_take(64usize)(input)?;
+    let (_, file) = custom_take(128usize)(input)?;
+
+    let mut raw = Vec::new();
+    let mut input = options_input;
+    // BOOTP clients may leave the vendor area without the magic cookie (RFC 951)
+    if let Ok((rest, _)) = custom_tag(&COOKIE)(input) {
+        input = collect_options(rest, &mut raw);
+    }
+    // RFC 2131 4.1: option 52 says the options go on in `file`, then in `sname`
+    let overload = raw
+        .iter()
+        .find(|o| o.code == OVERLOAD)
+        .and_then(|o| o.data.first().copied())
+        .unwrap_or(0);
+    if overload & OVERLOAD_FILE != 0 {
+        collect_options(file, &mut raw);
+    }
+    if overload & OVERLOAD_SNAME != 0 {
+        collect_options(sname, &mut raw);
+    }
+    let options: Vec<DhcpOption> = raw
+        .iter()
+        .filter_map(|o| decode_option(o.code, &o.data).ok())
+        .collect();
+    // only a message without option 53 is BOOTP; one whose type cannot be read is dropped
+    let typed = |code| code == DHCP_MESSAGE_TYPE;
+    if raw.iter().any(|o| typed(o.code)) && !options.iter().any(|o| typed(o.code())) {
+        return Err(CustomErr::UnrecognizedMessageType);
+    }
+    let leasequery = [
+        MessageType::LeaseQuery,
+        MessageType::BulkLeaseQuery,
+        MessageType::ActiveLeaseQuery,
+    ]
+    .iter()
+    .any(|t| options.contains(&DhcpOption::DhcpMessageType(*t)));
+    // leasequeries by client identifier and InfiniBand clients (RFC 4390) carry no hardware
+    // address, the latter a client identifier instead
+    let client_id = options.iter().any(|o| o.code() == CLIENT_IDENTIFIER);
+    if hlen == 0 && !leasequery && !(htype == hwaddr::INFINIBAND && client_id) {
+        return Err(CustomErr::InvalidHlen);
     }
-
-    let input = rest.split_at(1).1; // Skip the END tag byte
 
     Ok((
         input,
@@ -200,15 +528,24 @@ fn decode(input: &[u8]) -> IResult<&[u8], Packet> {
             reply,
             hops,
             secs,
-            broadcast: flags & 128 == 128,
+            // the leftmost bit of the flags (RFC 2131 2)
+            broadcast: flags & 0x8000 != 0,
             ciaddr,
             yiaddr,
             siaddr,
             giaddr,
             options,
-            chaddr: [
-                chaddr[0], chaddr[1], chaddr[2], chaddr[3], chaddr[4], chaddr[5],
-            ],
+            chaddr: HwAddr::new(htype, &chaddr[..hlen as usize]),
+            sname: if overload & OVERLOAD_SNAME != 0 {
+                String::new()
+            } else {
+                decode_field(sname)
+            },
+            file: if overload & OVERLOAD_FILE != 0 {
+                String::new()
+            } else {
+                decode_field(file)
+            },
             xid,
         },
     ))
@@ -224,6 +561,105 @@ impl Packet {
         self.options.iter().find(|&option| option.code() == code)
     }
 
+    /// The name the client goes by: option 12, else the name in option 81.
+    pub fn host_name(&self) -> Option<&str> {
+        match (self.option(HOST_NAME), self.option(CLIENT_FQDN)) {
+            (Some(DhcpOption::HostName(name)), _) => Some(name),
+            (_, Some(DhcpOption::ClientFqdn(fqdn))) if !fqdn.name.is_empty() => Some(&fqdn.name),
+            _ => None,
+        }
+    }
+
+    /// The largest reply the client takes: its option 57 less the IP and UDP headers, but never
+    /// below what every client must accept.
+    pub fn max_reply_size(&self) -> usize {
+        match self.option(MAXIMUM_DHCP_MESSAGE_SIZE) {
+            Some(DhcpOption::MaxMessageSize(n)) => (*n as usize)
+                .saturating_sub(IP_UDP_HEADERS)
+                .max(MAX_MESSAGE_SIZE),
+            _ => MAX_MESSAGE_SIZE,
+        }
+    }
+
+    /// The client identifier (option 61), type byte included.
+    pub fn client_id(&self) -> Option<&[u8]> {
+        match self.option(CLIENT_IDENTIFIER) {
+            Some(DhcpOption::ClientIdentifier(id)) => Some(id),
+            _ => None,
+        }
+    }
+
+    /// The address the client asks for: option 50, or ciaddr when renewing.
+    pub fn requested_ip(&self) -> Ipv4Addr {
+        match self.option(REQUESTED_IP_ADDRESS) {
+            Some(DhcpOption::RequestedIpAddress(ip)) => *ip,
+            _ => self.ciaddr,
+        }
+    }
+
+    /// The vendor class identifier (option 60) sent by the client.
+    pub fn vendor_class(&self) -> Option<&[u8]> {
+        match self.option(VENDOR_CLASS_IDENTIFIER) {
+            Some(DhcpOption::VendorClassIdentifier(id)) => Some(id),
+            _ => None,
+        }
+    }
+
+    /// The user class values (option 77) sent by the client.
+    pub fn user_classes(&self) -> &[Vec<u8>] {
+        match self.option(USER_CLASS) {
+            Some(DhcpOption::UserClass(classes)) => classes,
+            _ => &[],
+        }
+    }
+
+    /// Whether the client listed `code` in its parameter request list.
+    pub fn requests_option(&self, code: u8) -> bool {
+        match self.option(PARAMETER_REQUEST_LIST) {
+            Some(DhcpOption::ParameterRequestList(prl)) => prl.contains(&code),
+            _ => false,
+        }
+    }
+
+    /// Returns the payload of the given relay agent (option 82) sub-option.
+    pub fn relay_suboption(&self, code: u8) -> Option<&[u8]> {
+        match self.option(RELAY_AGENT_INFORMATION) {
+            Some(DhcpOption::RelayAgentInformation(subs)) => subs
+                .iter()
+                .find(|o| o.code == code)
+                .map(|o| o.data.as_slice()),
+            _ => None,
+        }
+    }
+
+    /// The subnet the client actually sits on, as given by option 82 link-selection or
+    /// option 118, if either is present.
+    pub fn selected_subnet(&self) -> Option<Ipv4Addr> {
+        if let Some(link) = self.relay_suboption(AGENT_LINK_SELECTION) {
+            if let Ok((_, addr)) = decode_ipv4(link) {
+                return Some(addr);
+            }
+        }
+        match self.option(SUBNET_SELECTION) {
+            Some(DhcpOption::SubnetSelection(addr)) => Some(*addr),
+            _ => None,
+        }
+    }
+
+    /// Enterprise numbers the client identified itself with in options 124 and 125.
+    pub fn vendor_enterprises(&self) -> Vec<u32> {
+        let mut enterprises = Vec::new();
+        if let Some(DhcpOption::VendorClass(classes)) = self.option(V_I_VENDOR_CLASS) {
+            enterprises.extend(classes.iter().map(|c| c.enterprise));
+        }
+        if let Some(DhcpOption::VendorSpecific(infos)) =
+            self.option(V_I_VENDOR_SPECIFIC_INFORMATION)
+        {
+            enterprises.extend(infos.iter().map(|i| i.enterprise));
+        }
+        enterprises
+    }
+
     /// Convenience function for extracting a packet's message type.
     pub fn message_type(&self) -> Result<MessageType, String> {
         match self.option(DHCP_MESSAGE_TYPE) {
@@ -235,14 +671,20 @@ impl Packet {
             None => Err("Packet does not have MessageType option".to_string()),
         }
     }
-    pub fn encode<'a>(&'a self, p: &'a mut [u8]) -> &[u8] {
+    pub fn encode<'a>(&'a self, p: &'a mut [u8]) -> &'a [u8] {
+        self.encode_within(p, MAX_MESSAGE_SIZE)
+    }
+
+    /// Encodes into at most `max_size` bytes, leaving out the options that do not fit.
+    pub fn encode_within<'a>(&'a self, p: &'a mut [u8], max_size: usize) -> &'a [u8] {
+        let max_size = max_size.min(p.len());
         let broadcast_flag = if self.broadcast { 128 } else { 0 };
         let mut length = 240;
 
         p[..12].copy_from_slice(&[
             if self.reply { BOOT_REPLY } else { BOOT_REQUEST },
-            1,
-            6,
+            self.chaddr.htype,
+            self.chaddr.as_bytes().len() as u8,
             self.hops,
             ((self.xid >> 24) & 0xFF) as u8,
             ((self.xid >> 16) & 0xFF) as u8,
@@ -258,22 +700,32 @@ impl Packet {
         p[16..20].copy_from_slice(&self.yiaddr.octets());
         p[20..24].copy_from_slice(&self.siaddr.octets());
         p[24..28].copy_from_slice(&self.giaddr.octets());
-        p[28..34].copy_from_slice(&self.chaddr);
-        p[34..236].fill(0);
+        p[28..236].fill(0);
+        p[28..28 + self.chaddr.as_bytes().len()].copy_from_slice(self.chaddr.as_bytes());
+        // keep the terminating NUL
+        let sname = &self.sname.as_bytes()[..self.sname.len().min(63)];
+        p[44..44 + sname.len()].copy_from_slice(sname);
+        let file = &self.file.as_bytes()[..self.file.len().min(127)];
+        p[108..108 + file.len()].copy_from_slice(file);
         p[236..240].copy_from_slice(&COOKIE);
 
         for option in &self.options {
             let option = option.to_raw();
-            let option_len = option.data.len();
-            if length + 2 + option_len >= 272 {
-                break;
+            // RFC 3396: longer options go out as several instances of the code
+            let parts: Vec<&[u8]> = match option.data.len() {
+                0 => vec![&[]],
+                _ => option.data.chunks(255).collect(),
+            };
+            if length + option.data.len() + 2 * parts.len() >= max_size {
+                // smaller options after it may still fit
+                continue;
             }
-            if let Some(dest) = p.get_mut(length..length + 2 + option_len) {
-                dest[0] = option.code;
-                dest[1] = option_len as u8;
-                dest[2..].copy_from_slice(&option.data);
+            for part in parts {
+                p[length] = option.code;
+                p[length + 1] = part.len() as u8;
+                p[length + 2..length + 2 + part.len()].copy_from_slice(part);
+                length += 2 + part.len();
             }
-            length += 2 + option_len;
         }
 
         if let Some(end_segment) = p.get_mut(length..length + 1) {
@@ -285,6 +737,12 @@ impl Packet {
             pad_segment.fill(PAD);
         }
 
+        // BOOTP clients expect the full 64 byte vendor area
+        if self.message_type().is_err() && length < BOOTP_MIN_SIZE {
+            p[length..BOOTP_MIN_SIZE].fill(PAD);
+            length = BOOTP_MIN_SIZE;
+        }
+
         &p[..length]
     }
 }
@@ -294,5 +752,169 @@ const COOKIE: [u8; 4] = [99, 130, 83, 99];
 const BOOT_REQUEST: u8 = 1; // From Client;
 const BOOT_REPLY: u8 = 2; // From Server;
 
-const END: u8 = 255;
-const PAD: u8 = 0;
+/// The fixed fields plus the 64 byte vendor area of RFC 951.
+const BOOTP_MIN_SIZE: usize = 300;
+
+/// 576 bytes less IP and UDP headers; every client must accept a message this large (RFC 2131).
+pub const MAX_MESSAGE_SIZE: usize = 548;
+const IP_UDP_HEADERS: usize = 28;
+
+pub const END: u8 = 255;
+pub const PAD: u8 = 0;
+/// Option 52 values: the fields that hold options too.
+const OVERLOAD_FILE: u8 = 1;
+const OVERLOAD_SNAME: u8 = 2;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A request from 02:00:00:00:00:01 with `vendor` as its vendor area.
+    fn request(vendor: &[u8]) -> Vec<u8> {
+        let mut p = vec![0u8; 236];
+        p[..3].copy_from_slice(&[BOOT_REQUEST, 1, 6]);
+        p[28..34].copy_from_slice(&[2, 0, 0, 0, 0, 1]);
+        p.extend(vendor);
+        p
+    }
+
+    fn with_cookie(options: &[u8]) -> Vec<u8> {
+        request(&[&COOKIE[..], options, &[END]].concat())
+    }
+
+    /// An offer carrying `options` after the message type.
+    fn offer(options: Vec<DhcpOption>) -> Packet {
+        Packet {
+            reply: true,
+            hops: 0,
+            xid: 0x01020304,
+            secs: 0,
+            broadcast: false,
+            ciaddr: Ipv4Addr::UNSPECIFIED,
+            yiaddr: Ipv4Addr::new(192, 168, 10, 100),
+            siaddr: Ipv4Addr::UNSPECIFIED,
+            giaddr: Ipv4Addr::UNSPECIFIED,
+            chaddr: HwAddr::new(1, &[2, 0, 0, 0, 0, 1]),
+            sname: String::new(),
+            file: String::new(),
+            options: std::iter::once(DhcpOption::DhcpMessageType(MessageType::Offer))
+                .chain(options)
+                .collect(),
+        }
+    }
+
+    fn unrecognized(code: u8, len: usize) -> DhcpOption {
+        DhcpOption::Unrecognized(RawDhcpOption {
+            code,
+            data: (0..len).map(|i| i as u8).collect(),
+        })
+    }
+
+    #[test]
+    fn decodes_message_type() {
+        let p = Packet::from(&with_cookie(&[DHCP_MESSAGE_TYPE, 1, 1])).unwrap();
+        assert_eq!(p.message_type(), Ok(MessageType::Discover));
+        assert_eq!(p.chaddr.as_bytes(), &[2, 0, 0, 0, 0, 1]);
+    }
+
+    #[test]
+    fn bootp_has_no_option_53() {
+        let p = Packet::from(&with_cookie(&[HOST_NAME, 1, b'a'])).unwrap();
+        assert!(p.option(DHCP_MESSAGE_TYPE).is_none());
+        // RFC 951 vendor area without the magic cookie
+        let p = Packet::from(&request(&[0; 64])).unwrap();
+        assert!(p.options.is_empty());
+    }
+
+    #[test]
+    fn drops_unreadable_message_types() {
+        for option in [
+            &[DHCP_MESSAGE_TYPE, 1, 99][..],
+            &[DHCP_MESSAGE_TYPE, 0],
+            &[DHCP_MESSAGE_TYPE, 2, 1, 1],
+        ] {
+            assert!(Packet::from(&with_cookie(option)).is_err(), "{:?}", option);
+        }
+    }
+
+    #[test]
+    fn round_trips() {
+        let mut p = offer(vec![
+            DhcpOption::ServerIdentifier(Ipv4Addr::new(192, 168, 10, 1)),
+            DhcpOption::IpAddressLeaseTime(3600),
+            DhcpOption::SubnetMask(Ipv4Addr::new(255, 255, 255, 0)),
+            DhcpOption::Router(vec![Ipv4Addr::new(192, 168, 10, 1)]),
+            DhcpOption::DomainSearch(vec!["lan".into(), "corp.lan".into()]),
+            DhcpOption::RapidCommit,
+        ]);
+        p.broadcast = true;
+        p.giaddr = Ipv4Addr::new(10, 9, 0, 1);
+        p.sname = "boot".into();
+        p.file = "pxelinux.0".into();
+        let mut buf = [0u8; 1500];
+        let decoded = Packet::from(p.encode(&mut buf)).unwrap();
+        assert!(decoded.reply && decoded.broadcast);
+        assert_eq!(decoded.xid, p.xid);
+        assert_eq!((decoded.yiaddr, decoded.giaddr), (p.yiaddr, p.giaddr));
+        assert_eq!(decoded.chaddr, p.chaddr);
+        assert_eq!(
+            (decoded.sname.as_str(), decoded.file.as_str()),
+            ("boot", "pxelinux.0")
+        );
+        assert_eq!(decoded.options, p.options);
+    }
+
+    #[test]
+    fn joins_split_options() {
+        let p = with_cookie(&[
+            DHCP_MESSAGE_TYPE,
+            1,
+            1,
+            HOST_NAME,
+            2,
+            b'a',
+            b'b',
+            PAD,
+            HOST_NAME,
+            2,
+            b'c',
+            b'd',
+        ]);
+        let p = Packet::from(&p).unwrap();
+        assert_eq!(
+            p.option(HOST_NAME),
+            Some(&DhcpOption::HostName("abcd".into()))
+        );
+    }
+
+    #[test]
+    fn splits_long_options() {
+        let long = unrecognized(224, 300);
+        let p = offer(vec![long.clone()]);
+        let mut buf = [0u8; 1500];
+        let encoded = p.encode(&mut buf);
+        // message type, then 224 as 255 + 45 bytes
+        assert_eq!(&encoded[243..245], &[224, 255]);
+        assert_eq!(&encoded[500..502], &[224, 45]);
+        assert_eq!(encoded[547], END);
+        assert_eq!(Packet::from(encoded).unwrap().option(224), Some(&long));
+    }
+
+    #[test]
+    fn skips_options_that_do_not_fit() {
+        let p = offer(vec![unrecognized(224, 400), unrecognized(225, 4)]);
+        let mut buf = [0u8; 1500];
+        let decoded = Packet::from(p.encode(&mut buf)).unwrap();
+        assert!(decoded.option(224).is_none());
+        assert_eq!(decoded.option(225), Some(&unrecognized(225, 4)));
+        let decoded = Packet::from(p.encode_within(&mut buf, 1500)).unwrap();
+        assert_eq!(decoded.option(224), Some(&unrecognized(224, 400)));
+    }
+
+    #[test]
+    fn rejects_long_hardware_addresses() {
+        let mut p = with_cookie(&[DHCP_MESSAGE_TYPE, 1, 1]);
+        p[2] = 17;
+        assert!(Packet::from(&p).is_err());
+    }
+}
@@ -0,0 +1,51 @@
+//! Built-in vendor profiles for access point / controller discovery.
+//!
+//! Each profile knows which clients it applies to (by option 60) and how that vendor expects the
+//! controller address to be encoded, so users only have to give the controller address.
+
+use crate::config::{ProfileKind, VendorProfile};
+use crate::options::DhcpOption;
+use crate::packet::Packet;
+
+use std::net::Ipv4Addr;
+
+/// The option a profile contributes for this client, if the client is one of the vendor's.
+pub fn option_for(profile: &VendorProfile, req_packet: &Packet) -> Option<DhcpOption> {
+    let vendor_class = req_packet.vendor_class().unwrap_or(&[]);
+    let addrs = &profile.controllers;
+    match profile.profile {
+        // UniFi devices send "ubnt"; sub-option 1 carries the controller address
+        ProfileKind::UbiquitiUnifi if vendor_class.starts_with(b"ubnt") => {
+            let addr = addrs.first()?;
+            let mut data = vec![1, 4];
+            data.extend(addr.octets());
+            Some(DhcpOption::VendorSpecificInformation(data))
+        }
+        // Aruba APs send "ArubaAP" and expect the controller address as plain text
+        ProfileKind::Aruba if vendor_class.starts_with(b"ArubaAP") => Some(
+            DhcpOption::VendorSpecificInformation(addrs.first()?.to_string().into_bytes()),
+        ),
+        // Ruckus: sub-option 3 is read by ZoneDirector-managed APs, 6 by SmartZone
+        ProfileKind::Ruckus if vendor_class.starts_with(b"Ruckus") => {
+            let list = join(addrs).into_bytes();
+            let mut data = vec![];
+            for code in [3u8, 6] {
+                data.push(code);
+                data.push(list.len() as u8);
+                data.extend(&list);
+            }
+            Some(DhcpOption::VendorSpecificInformation(data))
+        }
+        // CAPsMAN discovers its manager through CAPWAP (option 138), requested by the CAP itself
+        ProfileKind::MikrotikCapsman => Some(DhcpOption::CapwapAcV4(addrs.clone())),
+        _ => None,
+    }
+}
+
+fn join(addrs: &[Ipv4Addr]) -> String {
+    addrs
+        .iter()
+        .map(|a| a.to_string())
+        .collect::<Vec<String>>()
+        .join(",")
+}
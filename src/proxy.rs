@@ -0,0 +1,53 @@
+//! Proxy DHCP (PXE 2.1): the boot server and file for PXE clients whose addresses come from
+//! another DHCP server.
+//!
+//! The DISCOVER on port 67 is answered next to the other server's OFFER; clients that want more
+//! ask again with a unicast REQUEST on port 4011, which is served here.
+
+use crate::mirror;
+use crate::packet::Packet;
+
+use std::cell::RefCell;
+use std::io;
+use std::net::UdpSocket;
+
+pub const PORT: u16 = 4011;
+
+thread_local! {
+    /// The port 4011 socket of the instance served by this thread, see `instance.rs`.
+    static SOCKET: RefCell<Option<UdpSocket>> = const { RefCell::new(None) };
+}
+
+/// Listens on port 4011 for the calling thread's instance.
+pub fn spawn() -> io::Result<()> {
+    let socket = UdpSocket::bind(("0.0.0.0", PORT))?;
+    socket.set_nonblocking(true)?;
+    SOCKET.with(|s| *s.borrow_mut() = Some(socket));
+    println!("proxy DHCP listening on port {}", PORT);
+    Ok(())
+}
+
+/// Answers the requests that arrived on port 4011 since the last call with `answer`.
+pub fn poll(mut answer: impl FnMut(&Packet) -> Option<Packet>) {
+    SOCKET.with(|s| {
+        let Some(socket) = &*s.borrow() else {
+            return;
+        };
+        let mut buf = [0u8; 1500];
+        while let Ok((n, src)) = socket.recv_from(&mut buf) {
+            mirror::packet("in", src, &buf[..n]);
+            let Ok(request) = Packet::from(&buf[..n]) else {
+                continue;
+            };
+            let Some(reply) = answer(&request) else {
+                continue;
+            };
+            let mut out = [0u8; 1500];
+            let data = reply.encode(&mut out);
+            mirror::packet("out", src, data);
+            if let Err(e) = socket.send_to(data, src) {
+                println!("ERROR: proxy DHCP reply to {}: {:?}", src, e);
+            }
+        }
+    });
+}
@@ -0,0 +1,189 @@
+//! Router advertisements (RFC 4861) for networks where rdhcpd is the only source of IPv6
+//! configuration: the on-link prefix for SLAAC (RFC 4862), DNS servers and search domains
+//! (RFC 8106), and the managed and other flags telling hosts whether to ask `dhcp6` too.
+//!
+//! Advertisements go to all nodes every `interval` or so, and to a host right away when it sends
+//! a router solicitation.
+
+use crate::config::{Config, RaConf};
+use crate::options;
+use crate::utils;
+
+use std::io;
+use std::net::{Ipv6Addr, SocketAddr, SocketAddrV6, UdpSocket};
+use std::os::fd::{AsRawFd, FromRawFd};
+use std::thread;
+use std::time::{Duration, Instant};
+
+const ALL_NODES: Ipv6Addr = Ipv6Addr::new(0xff02, 0, 0, 0, 0, 0, 0, 1);
+const ALL_ROUTERS: Ipv6Addr = Ipv6Addr::new(0xff02, 0, 0, 0, 0, 0, 0, 2);
+
+const ROUTER_SOLICITATION: u8 = 133;
+const ROUTER_ADVERTISEMENT: u8 = 134;
+
+const OPTION_SOURCE_LINK_ADDR: u8 = 1;
+const OPTION_PREFIX_INFO: u8 = 3;
+const OPTION_MTU: u8 = 5;
+const OPTION_RDNSS: u8 = 25;
+const OPTION_DNSSL: u8 = 31;
+
+const FLAG_MANAGED: u8 = 0x80;
+const FLAG_OTHER: u8 = 0x40;
+const FLAG_ON_LINK: u8 = 0x80;
+const FLAG_AUTONOMOUS: u8 = 0x40;
+
+/// RFC 4861 6.2.1 defaults for the prefix.
+const PREFIX_VALID: u32 = 30 * 86400;
+const PREFIX_PREFERRED: u32 = 7 * 86400;
+/// Router lifetimes above this are invalid (RFC 4861 4.2).
+const MAX_ROUTER_LIFETIME: u64 = 9000;
+
+/// Starts advertising on `conf.intf`.
+pub fn spawn(conf: &Config, ra: RaConf) -> io::Result<()> {
+    let index = utils::interface_index(&conf.intf).ok_or_else(|| {
+        io::Error::new(
+            io::ErrorKind::NotFound,
+            format!("no interface {}", conf.intf),
+        )
+    })?;
+    let socket = open(index)?;
+    let interval = duration_str::parse(ra.interval.as_str()).unwrap();
+    let message = advertisement(conf, &ra, interval);
+    let intf = conf.intf.clone();
+    thread::spawn(move || run(socket, index, &message, interval, &intf));
+    println!("router advertisements on {} for {}", conf.intf, ra.prefix);
+    Ok(())
+}
+
+/// An ICMPv6 socket sending with hop limit 255, as hosts only accept advertisements sent so,
+/// and hearing the solicitations to all routers on interface `index`.
+fn open(index: u32) -> io::Result<UdpSocket> {
+    fn check(r: libc::c_int) -> io::Result<()> {
+        if r < 0 {
+            Err(io::Error::last_os_error())
+        } else {
+            Ok(())
+        }
+    }
+
+    // std has no raw sockets, but its datagram calls work on them all the same; the kernel
+    // fills in the ICMPv6 checksum
+    unsafe {
+        let fd = libc::socket(
+            libc::AF_INET6,
+            libc::SOCK_RAW | libc::SOCK_CLOEXEC,
+            libc::IPPROTO_ICMPV6,
+        );
+        check(fd)?;
+        let socket = UdpSocket::from_raw_fd(fd);
+        let hops: libc::c_int = 255;
+        for opt in [libc::IPV6_MULTICAST_HOPS, libc::IPV6_UNICAST_HOPS] {
+            check(libc::setsockopt(
+                socket.as_raw_fd(),
+                libc::IPPROTO_IPV6,
+                opt,
+                &hops as *const _ as *const libc::c_void,
+                std::mem::size_of_val(&hops) as libc::socklen_t,
+            ))?;
+        }
+        socket.join_multicast_v6(&ALL_ROUTERS, index)?;
+        Ok(socket)
+    }
+}
+
+fn run(socket: UdpSocket, index: u32, message: &[u8], interval: Duration, intf: &str) {
+    let all_nodes = SocketAddr::V6(SocketAddrV6::new(ALL_NODES, 0, 0, index));
+    let mut next = Instant::now();
+    let mut buf = [0u8; 1500];
+    loop {
+        let now = Instant::now();
+        if now >= next {
+            if let Err(e) = socket.send_to(message, all_nodes) {
+                println!("ERROR: router advertisement on {}: {:?}", intf, e);
+            }
+            // RFC 4861 6.2.4: between a third of the interval and the interval
+            let jitter =
+                utils::now_timestamp_ms() as u64 % (interval.as_millis() as u64 * 2 / 3 + 1);
+            next = now + interval / 3 + Duration::from_millis(jitter);
+        }
+        let wait = next.saturating_duration_since(Instant::now());
+        let _ = socket.set_read_timeout(Some(wait.max(Duration::from_millis(1))));
+        let Ok((n, src)) = socket.recv_from(&mut buf) else {
+            continue;
+        };
+        let SocketAddr::V6(src) = src else {
+            continue;
+        };
+        if n < 8 || buf[0] != ROUTER_SOLICITATION || src.scope_id() != index {
+            continue;
+        }
+        // a host without an address yet has to wait for the multicast one
+        let to = if src.ip().is_unspecified() {
+            all_nodes
+        } else {
+            SocketAddr::V6(src)
+        };
+        if let Err(e) = socket.send_to(message, to) {
+            println!("ERROR: router advertisement to {}: {:?}", to, e);
+        }
+    }
+}
+
+/// The advertisement for `conf`: its flags, DNS servers and search domains follow `dhcp6`.
+fn advertisement(conf: &Config, ra: &RaConf, interval: Duration) -> Vec<u8> {
+    let (prefix, prefix_len) = ra.prefix().unwrap();
+    let lifetime = match &ra.router_lifetime {
+        Some(d) => duration_str::parse(d.as_str()).unwrap().as_secs(),
+        None => interval.as_secs() * 3,
+    }
+    .min(MAX_ROUTER_LIFETIME) as u16;
+    let flags = match conf.dhcp6 {
+        Some(_) => FLAG_MANAGED | FLAG_OTHER,
+        None => 0,
+    };
+    let mut m = vec![ROUTER_ADVERTISEMENT, 0, 0, 0, 64, flags];
+    m.extend(lifetime.to_be_bytes());
+    // reachable time and retransmission timer left to the hosts
+    m.extend([0; 8]);
+    if let Some(mac) = utils::interface_mac(&conf.intf) {
+        m.extend([OPTION_SOURCE_LINK_ADDR, 1]);
+        m.extend(mac);
+    }
+    if let Some(mtu) = conf.mtu {
+        m.extend([OPTION_MTU, 1, 0, 0]);
+        m.extend((mtu as u32).to_be_bytes());
+    }
+    let mut prefix_flags = FLAG_ON_LINK;
+    if ra.autonomous {
+        prefix_flags |= FLAG_AUTONOMOUS;
+    }
+    m.extend([OPTION_PREFIX_INFO, 4, prefix_len, prefix_flags]);
+    m.extend(PREFIX_VALID.to_be_bytes());
+    m.extend(PREFIX_PREFERRED.to_be_bytes());
+    m.extend([0; 4]);
+    m.extend(prefix.octets());
+    // RFC 8106 5.1: by default at least three times the longest interval
+    let dns_lifetime = (interval.as_secs() * 3).min(u32::MAX as u64) as u32;
+    let dns_servers = match (&ra.dns_servers, &conf.dhcp6) {
+        (Some(servers), _) => servers.clone(),
+        (None, Some(d)) => d.dns_servers.clone(),
+        (None, None) => vec![],
+    };
+    if !dns_servers.is_empty() {
+        m.extend([OPTION_RDNSS, 1 + 2 * dns_servers.len() as u8, 0, 0]);
+        m.extend(dns_lifetime.to_be_bytes());
+        for s in &dns_servers {
+            m.extend(s.octets());
+        }
+    }
+    if !conf.search_domains.is_empty() {
+        let mut names = options::encode_domain_names(&conf.search_domains);
+        while !names.len().is_multiple_of(8) {
+            names.push(0);
+        }
+        m.extend([OPTION_DNSSL, (1 + names.len() / 8) as u8, 0, 0]);
+        m.extend(dns_lifetime.to_be_bytes());
+        m.extend(names);
+    }
+    m
+}
@@ -0,0 +1,302 @@
+//! Static reservations, stored one `client,ip` pair per line in `lease_static`.
+//!
+//! The client is a MAC address, `id:<hex>` for a client identifier (option 61, type byte
+//! included) or `duid:<hex>` for a DHCPv6 DUID carried in an RFC 4361 client identifier.
+
+use crate::config::Config;
+use crate::dhcpd;
+use crate::hwaddr::HwAddr;
+use crate::utils;
+
+use anyhow::{bail, Context};
+
+use std::collections::HashMap;
+use std::fmt;
+use std::fs::{self, File};
+use std::io::{BufRead, BufReader, BufWriter, Write};
+use std::net::Ipv4Addr;
+use std::path::Path;
+use std::str::FromStr;
+
+/// Who a reservation is for.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum Key {
+    Mac(HwAddr),
+    ClientId(Vec<u8>),
+    Duid(Vec<u8>),
+}
+
+impl Key {
+    pub fn mac(&self) -> Option<HwAddr> {
+        match self {
+            Key::Mac(mac) => Some(*mac),
+            _ => None,
+        }
+    }
+
+    /// Whether a client with hardware address `chaddr` and client identifier `client_id` is
+    /// the one reserved for.
+    pub fn matches(&self, chaddr: &HwAddr, client_id: Option<&[u8]>) -> bool {
+        match (self, client_id) {
+            (Key::Mac(mac), _) => mac == chaddr,
+            (Key::ClientId(id), Some(c)) => id.as_slice() == c,
+            // type 255, a 4 byte IAID, then the DUID
+            (Key::Duid(duid), Some([255, _, _, _, _, d @ ..])) => duid.as_slice() == d,
+            _ => false,
+        }
+    }
+}
+
+impl fmt::Display for Key {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let hex = |b: &[u8]| b.iter().map(|b| format!("{:02x}", b)).collect::<String>();
+        match self {
+            Key::Mac(mac) => write!(f, "{}", *mac),
+            Key::ClientId(id) => write!(f, "id:{}", hex(id)),
+            Key::Duid(duid) => write!(f, "duid:{}", hex(duid)),
+        }
+    }
+}
+
+impl FromStr for Key {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Key, String> {
+        let hex = |h: &str| {
+            utils::parse_hex(h)
+                .filter(|b| !b.is_empty())
+                .ok_or_else(|| format!("invalid hex '{}'", h))
+        };
+        if let Some(h) = s.strip_prefix("id:") {
+            return Ok(Key::ClientId(hex(h)?));
+        }
+        if let Some(h) = s.strip_prefix("duid:") {
+            return Ok(Key::Duid(hex(h)?));
+        }
+        HwAddr::from_str(s).map(Key::Mac)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Reservation {
+    pub key: Key,
+    pub ip: Ipv4Addr,
+}
+
+/// Parses one reservation line. Blank lines and `#` comments yield `Ok(None)`.
+pub fn parse_line(line: &str) -> Result<Option<Reservation>, String> {
+    let line = line.trim();
+    if line.is_empty() || line.starts_with('#') {
+        return Ok(None);
+    }
+    let parts: Vec<&str> = line.split(',').map(|p| p.trim()).collect();
+    if parts.len() != 2 {
+        return Err(format!("expected 'client,ip', got '{}'", line));
+    }
+    let key = parts[0].parse::<Key>()?;
+    let ip = parts[1]
+        .parse::<Ipv4Addr>()
+        .map_err(|_| format!("invalid IP address '{}'", parts[1]))?;
+    Ok(Some(Reservation { key, ip }))
+}
+
+/// Reads a reservations file, returning every valid entry and an error message per bad line.
+pub fn read<P: AsRef<Path>>(path: P) -> std::io::Result<(Vec<Reservation>, Vec<String>)> {
+    let reader = BufReader::new(File::open(path)?);
+    let mut entries = Vec::new();
+    let mut errors = Vec::new();
+    for (n, line) in reader.lines().enumerate() {
+        match parse_line(&line?) {
+            Ok(Some(r)) => entries.push(r),
+            Ok(None) => {}
+            Err(e) => errors.push(format!("line {}: {}", n + 1, e)),
+        }
+    }
+    Ok((entries, errors))
+}
+
+/// Loads the reservations used by the server, reporting and skipping invalid lines.
+pub fn load(path: &str) -> Vec<Reservation> {
+    if !Path::new(path).exists() {
+        return vec![];
+    }
+    match read(path) {
+        Ok((entries, errors)) => {
+            for e in errors {
+                println!("WARN: {}: {}", path, e);
+            }
+            entries
+        }
+        Err(e) => {
+            println!("ERROR: read {} failed: {:?}", path, e);
+            vec![]
+        }
+    }
+}
+
+/// The reservations of `conf.lease_static` that lie in the subnet `conf` serves, as pools and
+/// tenants share the file.
+pub fn for_subnet(conf: &Config) -> Vec<Reservation> {
+    let mut entries = load(&conf.lease_static);
+    entries.retain(|r| conf.subnet_contains(r.ip));
+    entries
+}
+
+/// Validates `file` and merges it into the configured reservations store.
+///
+/// Nothing is written unless every entry is valid; the store is replaced atomically.
+pub fn import(conf: &Config, file: &str, dry_run: bool) -> anyhow::Result<()> {
+    let (incoming, mut errors) = read(file).with_context(|| format!("read {}", file))?;
+    let existing = if Path::new(&conf.lease_static).exists() {
+        read(&conf.lease_static)
+            .with_context(|| format!("read {}", conf.lease_static))?
+            .0
+    } else {
+        vec![]
+    };
+    // the top-level subnet and the pools and tenants sharing its reservations
+    let subnets: Vec<(&Config, dhcpd::Leases)> = std::iter::once(conf)
+        .chain(conf.tenants.iter().map(|t| &t.conf))
+        .filter(|c| c.lease_static == conf.lease_static)
+        .map(|c| (c, dhcpd::load_lease_file(&c.lease_file)))
+        .collect();
+
+    let mut by_key: HashMap<Key, Ipv4Addr> = HashMap::new();
+    let mut by_ip: HashMap<Ipv4Addr, Key> = HashMap::new();
+    for r in &incoming {
+        let key = &r.key;
+        let ip: u32 = r.ip.into();
+        let Some((subnet, leases)) = subnets.iter().find(|(c, _)| c.subnet_contains(r.ip)) else {
+            errors.push(format!("{} is not in a served subnet", r.ip));
+            continue;
+        };
+        let net: u32 = u32::from(subnet.start) & u32::from(subnet.netmask);
+        let bcast: u32 = net | !u32::from(subnet.netmask);
+        if ip == net || ip == bcast {
+            errors.push(format!(
+                "{} is not a host address of the served subnet",
+                r.ip
+            ));
+        }
+        if r.ip == subnet.listen_addr || r.ip == subnet.gateway {
+            errors.push(format!("{} is used by the server or gateway", r.ip));
+        }
+        if let Some(ip) = by_key.insert(key.clone(), r.ip) {
+            errors.push(format!("{} is listed twice ({} and {})", key, ip, r.ip));
+        }
+        if let Some(other) = by_ip.insert(r.ip, key.clone()) {
+            errors.push(format!("{} is listed for both {} and {}", r.ip, other, key));
+        }
+        if let Some(lease) = leases.get(&r.ip) {
+            // held through a reservation already, e.g. by the client identifier being imported
+            let reserved = existing
+                .iter()
+                .any(|e| e.ip == r.ip && (e.key.mac() == Some(lease.mac) || e.key == *key));
+            if key.mac() != Some(lease.mac) && !reserved && lease.expiry > utils::now_timestamp_ms()
+            {
+                errors.push(format!("{} is currently leased to {}", r.ip, lease.mac));
+            }
+        }
+    }
+    for e in &existing {
+        if by_key.contains_key(&e.key) {
+            continue;
+        }
+        if let Some(key) = by_ip.get(&e.ip) {
+            errors.push(format!(
+                "{} is already reserved for {}, cannot assign it to {}",
+                e.ip, e.key, key
+            ));
+        }
+    }
+    if !errors.is_empty() {
+        for e in &errors {
+            println!("ERROR: {}", e);
+        }
+        bail!("{} problem(s) found, nothing imported", errors.len());
+    }
+
+    let mut merged: Vec<Reservation> = existing
+        .into_iter()
+        .filter(|e| !by_key.contains_key(&e.key))
+        .collect();
+    let kept = merged.len();
+    let imported = incoming.len();
+    merged.extend(incoming);
+    println!(
+        "{} reservation(s) imported, {} kept, {} total",
+        imported,
+        kept,
+        merged.len()
+    );
+    if dry_run {
+        return Ok(());
+    }
+    write(&conf.lease_static, &merged)
+}
+
+/// Writes the reservations store via a temporary file so readers never see a partial file.
+pub fn write(path: &str, entries: &[Reservation]) -> anyhow::Result<()> {
+    let tmp = format!("{}.tmp", path);
+    {
+        let mut writer =
+            BufWriter::new(File::create(&tmp).with_context(|| format!("create {}", tmp))?);
+        for r in entries {
+            writeln!(writer, "{},{}", r.key, r.ip)?;
+        }
+        writer.flush()?;
+    }
+    fs::rename(&tmp, path).with_context(|| format!("rename {} to {}", tmp, path))?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_keys() {
+        let mac = HwAddr::new(1, &[0xaa, 0xbb, 0xcc, 0xdd, 0xee, 0xff]);
+        assert_eq!("aa:bb:cc:dd:ee:ff".parse(), Ok(Key::Mac(mac)));
+        assert_eq!("id:01aabb".parse(), Ok(Key::ClientId(vec![1, 0xaa, 0xbb])));
+        assert_eq!(
+            "id:01:aa:bb".parse(),
+            Ok(Key::ClientId(vec![1, 0xaa, 0xbb]))
+        );
+        assert_eq!(
+            "duid:0003000102".parse(),
+            Ok(Key::Duid(vec![0, 3, 0, 1, 2]))
+        );
+        for bad in ["id:", "id:0", "duid:zz", "aa:bb", ""] {
+            assert!(bad.parse::<Key>().is_err(), "{}", bad);
+        }
+        for key in ["aa:bb:cc:dd:ee:ff", "id:01aabb", "duid:0003000102"] {
+            let parsed: Key = key.parse().unwrap();
+            assert_eq!(parsed.to_string().parse(), Ok(parsed));
+        }
+    }
+
+    #[test]
+    fn parses_lines() {
+        assert_eq!(parse_line("  # comment"), Ok(None));
+        assert_eq!(parse_line(""), Ok(None));
+        assert_eq!(
+            parse_line("id:01aabb, 192.168.10.5"),
+            Ok(Some(Reservation {
+                key: Key::ClientId(vec![1, 0xaa, 0xbb]),
+                ip: Ipv4Addr::new(192, 168, 10, 5),
+            }))
+        );
+        assert!(parse_line("aa:bb:cc:dd:ee:ff").is_err());
+        assert!(parse_line("aa:bb:cc:dd:ee:ff,192.168.10").is_err());
+    }
+
+    #[test]
+    fn duid_matches_rfc_4361_client_ids() {
+        let key = Key::Duid(vec![0, 3, 0, 1, 2]);
+        let chaddr = HwAddr::new(1, &[2, 0, 0, 0, 0, 1]);
+        assert!(key.matches(&chaddr, Some(&[255, 0, 0, 0, 1, 0, 3, 0, 1, 2])));
+        assert!(!key.matches(&chaddr, Some(&[0, 3, 0, 1, 2])));
+        assert!(!key.matches(&chaddr, None));
+    }
+}
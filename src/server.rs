@@ -1,22 +1,68 @@
 //! This is a convenience module that simplifies the writing of a DHCP server service.
 
-use std::cell::Cell;
+use std::cell::{Cell, RefCell};
+use std::io::ErrorKind;
 use std::net::{IpAddr, Ipv4Addr, SocketAddr, UdpSocket};
+use std::sync::mpsc::Receiver;
+use std::time::Duration;
 
+use crate::auth;
+use crate::batch;
+use crate::dhcp6;
+use crate::dump;
+use crate::hwaddr::HwAddr;
+use crate::leasequery;
+use crate::log;
+use crate::mgmt;
+use crate::mirror;
 use crate::options;
 use crate::options::{DhcpOption, MessageType};
 use crate::packet::*;
+use crate::proxy;
+use crate::stats;
 
 pub struct Server {
     out_buf: Cell<[u8; 1500]>,
     socket: UdpSocket,
     src: SocketAddr,
+    /// The largest reply the client being answered takes.
+    max_size: usize,
+    /// Whether replies to the client being answered carry its option 61, see `echo_client_id`.
+    echo_client_id: Cell<bool>,
+    /// The replies to a request received over DHCPv6, which go back the same way.
+    over_dhcp6: RefCell<Option<Vec<Vec<u8>>>>,
     server_ip: Ipv4Addr,
     broadcast_ip: Ipv4Addr,
+    /// Replies waiting for the end of a batch; `None` sends each one right away.
+    queue: Option<RefCell<Vec<batch::Datagram>>>,
 }
 
 pub trait Handler {
     fn handle_request(&mut self, server: &Server, in_packet: Packet);
+
+    /// Answers a request made on the management interface.
+    fn handle_mgmt(&mut self, _server: &Server, _req: mgmt::Request) -> mgmt::Response {
+        mgmt::Response::not_found()
+    }
+
+    /// Appends a human-readable account of the handler's state to a SIGUSR1 dump.
+    fn dump(&self, _out: &mut String) {}
+
+    /// Called between packets, and at least every 200ms on a quiet network.
+    fn tick(&mut self) {}
+
+    /// The answer to a request on the proxy DHCP port, see `proxy.rs`.
+    fn proxy_reply(&mut self, _request: &Packet) -> Option<Packet> {
+        None
+    }
+
+    /// The leases a bulk or active leasequery over TCP asks for, see `leasequery.rs`.
+    fn bulk_leasequery(&mut self, _query: &Packet) -> Result<Vec<Packet>, leasequery::Status> {
+        Err((
+            leasequery::STATUS_NOT_ALLOWED,
+            "leasequery not supported".to_string(),
+        ))
+    }
 }
 
 pub fn filter_options_by_req(opts: &mut Vec<DhcpOption>, req_params: &[u8]) {
@@ -26,8 +72,13 @@ pub fn filter_options_by_req(opts: &mut Vec<DhcpOption>, req_params: &[u8]) {
         options::SERVER_IDENTIFIER,
         options::SUBNET_MASK,
         options::IP_ADDRESS_LEASE_TIME,
+        options::RENEWAL_TIME_VALUE,
+        options::REBINDING_TIME_VALUE,
         options::DOMAIN_NAME_SERVER,
         options::ROUTER,
+        options::SUBNET_SELECTION,
+        options::RAPID_COMMIT,
+        options::CLIENT_FQDN,
     ] as &[u8];
 
     // Process options from req_params
@@ -75,27 +126,130 @@ impl Server {
         udp_soc: UdpSocket,
         server_ip: Ipv4Addr,
         broadcast_ip: Ipv4Addr,
+        mgmt: Option<&Receiver<mgmt::Call>>,
+        snoop: Option<&Receiver<Vec<u8>>>,
+        batch_size: usize,
         mut handler: H,
     ) -> std::io::Error {
-        let mut in_buf: [u8; 1500] = [0; 1500];
+        let mut in_bufs = vec![[0u8; batch::MAX_PACKET]; batch_size.max(1)];
         let mut s = Server {
             out_buf: Cell::new([0; 1500]),
             socket: udp_soc,
             server_ip,
             broadcast_ip,
             src: SocketAddr::new(IpAddr::V4(Ipv4Addr::new(0, 0, 0, 0)), 0),
+            max_size: MAX_MESSAGE_SIZE,
+            echo_client_id: Cell::new(true),
+            over_dhcp6: RefCell::new(None),
+            queue: (batch_size > 1).then(|| RefCell::new(Vec::with_capacity(batch_size))),
         };
+        // wake up regularly so the channels and dump requests are not starved by a quiet network
+        if let Err(e) = s.socket.set_read_timeout(Some(Duration::from_millis(200))) {
+            return e;
+        }
+        let mut dumps = 0;
+        dump::requested(&mut dumps);
         loop {
-            match s.socket.recv_from(&mut in_buf) {
+            if dump::requested(&mut dumps) {
+                let mut text = String::new();
+                handler.dump(&mut text);
+                s.dump(&mut text);
+                dump::write(&text);
+            }
+            if let Some(rx) = mgmt {
+                while let Ok((req, tx)) = rx.try_recv() {
+                    let _ = tx.send(handler.handle_mgmt(&s, req));
+                }
+            }
+            leasequery::poll(s.server_ip, |q| handler.bulk_leasequery(q));
+            proxy::poll(|r| handler.proxy_reply(r));
+            dhcp6::poll(|data| s.handle_dhcp4o6(&mut handler, data));
+            if let Some(rx) = snoop {
+                while let Ok(buf) = rx.try_recv() {
+                    if let Ok(p) = Packet::from(&buf) {
+                        handler.handle_request(&s, p);
+                    }
+                }
+            }
+            let received = if s.queue.is_some() {
+                batch::recv(&s.socket, &mut in_bufs)
+            } else {
+                s.socket.recv_from(&mut in_bufs[0]).map(|r| vec![r])
+            };
+            match received {
+                Err(e)
+                    if matches!(
+                        e.kind(),
+                        ErrorKind::WouldBlock | ErrorKind::TimedOut | ErrorKind::Interrupted
+                    ) => {}
                 Err(e) => return e,
-                Ok((l, src)) => {
-                    if let Ok(p) = Packet::from(&in_buf[..l]) {
-                        s.src = src;
+                Ok(received) => {
+                    for (i, (l, src)) in received.into_iter().enumerate() {
+                        let data = &in_bufs[i][..l];
+                        mirror::packet("in", src, data);
+                        if let Ok(p) = Packet::from(data) {
+                            if !auth::verify(data, &p) {
+                                continue;
+                            }
+                            s.src = src;
+                            s.max_size = p.max_reply_size();
+                            if let Ok(t) = p.message_type() {
+                                stats::received(t);
+                            }
 
-                        handler.handle_request(&s, p);
+                            handler.handle_request(&s, p);
+                            s.max_size = MAX_MESSAGE_SIZE;
+                            s.echo_client_id.set(true);
+                        }
                     }
                 }
             }
+            s.flush();
+            handler.tick();
+        }
+    }
+
+    /// Handles a DHCPv4 message received over DHCPv6 (RFC 7341), returning the replies.
+    fn handle_dhcp4o6<H: Handler>(&mut self, handler: &mut H, data: &[u8]) -> Vec<Vec<u8>> {
+        let Ok(p) = Packet::from(data) else {
+            return vec![];
+        };
+        if !auth::verify(data, &p) {
+            return vec![];
+        }
+        self.src = SocketAddr::new(IpAddr::V4(Ipv4Addr::UNSPECIFIED), 68);
+        if let Ok(t) = p.message_type() {
+            stats::received(t);
+        }
+        *self.over_dhcp6.borrow_mut() = Some(vec![]);
+        handler.handle_request(self, p);
+        self.echo_client_id.set(true);
+        self.over_dhcp6.borrow_mut().take().unwrap_or_default()
+    }
+
+    fn dump(&self, out: &mut String) {
+        let queued = self.queue.as_ref().map_or(0, |q| q.borrow().len());
+        out.push_str(&format!("replies queued: {}\n", queued));
+        if let Some(depth) = mirror::depth() {
+            out.push_str(&format!("mirror queued: {}\n", depth));
+        }
+    }
+
+    /// Sends the replies queued during a batch.
+    fn flush(&self) {
+        let Some(queue) = &self.queue else {
+            return;
+        };
+        let packets = std::mem::take(&mut *queue.borrow_mut());
+        if packets.is_empty() {
+            return;
+        }
+        match batch::send(&self.socket, &packets) {
+            Ok(n) if n < packets.len() => {
+                println!("WARN: sent {} of {} replies", n, packets.len())
+            }
+            Ok(_) => {}
+            Err(e) => println!("ERROR: send {} replies: {:?}", packets.len(), e),
         }
     }
 
@@ -109,10 +263,39 @@ impl Server {
         offer_ip: Ipv4Addr,
         req_packet: Packet,
     ) -> std::io::Result<usize> {
+        let p = self.reply_packet(msg_type, additional_options, offer_ip, req_packet);
+        self.send(p)
+    }
+
+    /// Builds (and logs) the reply `reply` sends, for callers that fill in header fields.
+    pub fn reply_packet(
+        &self,
+        msg_type: MessageType,
+        additional_options: Vec<DhcpOption>,
+        offer_ip: Ipv4Addr,
+        req_packet: Packet,
+    ) -> Packet {
         let ciaddr = match msg_type {
             MessageType::Nak => Ipv4Addr::new(0, 0, 0, 0),
             _ => req_packet.ciaddr,
         };
+        let detail = additional_options.iter().find_map(|o| match o {
+            DhcpOption::Message(m) => Some(m.clone()),
+            _ => None,
+        });
+        let bound = match msg_type {
+            MessageType::Nak => req_packet.requested_ip(),
+            // an ACK to an INFORM assigns nothing
+            _ if offer_ip.is_unspecified() => req_packet.ciaddr,
+            _ => offer_ip,
+        };
+        log::event(
+            msg_type,
+            req_packet.chaddr,
+            bound,
+            req_packet.xid,
+            detail.as_deref(),
+        );
 
         //let mt = &[msg_type as u8];
 
@@ -128,14 +311,30 @@ impl Server {
             data: &self.server_ip,
         });*/
         opts.extend(additional_options);
+        // RFC 3011: a server that honours subnet selection returns the option unchanged.
+        if let Some(DhcpOption::SubnetSelection(addr)) =
+            req_packet.option(options::SUBNET_SELECTION)
+        {
+            opts.push(DhcpOption::SubnetSelection(*addr));
+        }
 
         if let Some(DhcpOption::ParameterRequestList(prl)) =
             req_packet.option(options::PARAMETER_REQUEST_LIST)
         {
             filter_options_by_req(&mut opts, prl);
         }
+        // RFC 6842: the client identifier goes back as received
+        if let Some(id) = req_packet.option(options::CLIENT_IDENTIFIER) {
+            if self.echo_client_id.get() {
+                opts.push(id.clone());
+            }
+        }
+        // RFC 3046: relay agent information goes back as received, as the last option
+        if let Some(agent) = req_packet.option(options::RELAY_AGENT_INFORMATION) {
+            opts.push(agent.clone());
+        }
 
-        self.send(Packet {
+        Packet {
             reply: true,
             hops: 0,
             xid: req_packet.xid,
@@ -146,8 +345,44 @@ impl Server {
             siaddr: Ipv4Addr::new(0, 0, 0, 0),
             giaddr: req_packet.giaddr,
             chaddr: req_packet.chaddr,
+            sname: String::new(),
+            file: String::new(),
             options: opts,
-        })
+        }
+    }
+
+    /// Builds (and logs) the answer to a BOOTP request (RFC 951): the vendor extensions of
+    /// RFC 1497 without any DHCP message type or server identifier.
+    pub fn bootp_reply(
+        &self,
+        vendor_options: Vec<DhcpOption>,
+        yiaddr: Ipv4Addr,
+        req_packet: Packet,
+    ) -> Packet {
+        println!(
+            "BOOTREPLY {} {} xid {:#010x}",
+            yiaddr, req_packet.chaddr, req_packet.xid
+        );
+        Packet {
+            reply: true,
+            hops: 0,
+            xid: req_packet.xid,
+            secs: 0,
+            broadcast: req_packet.broadcast,
+            ciaddr: req_packet.ciaddr,
+            yiaddr,
+            siaddr: Ipv4Addr::new(0, 0, 0, 0),
+            giaddr: req_packet.giaddr,
+            chaddr: req_packet.chaddr,
+            sname: String::new(),
+            file: String::new(),
+            options: vendor_options,
+        }
+    }
+
+    /// Sets whether the replies to the request being handled return its client identifier.
+    pub fn echo_client_id(&self, on: bool) {
+        self.echo_client_id.set(on);
     }
 
     /// Checks the packet see if it was intended for this DHCP server (as opposed to some other also on the network).
@@ -158,14 +393,79 @@ impl Server {
         }
     }
 
+    /// Sends a DHCPFORCERENEW (RFC 3203) unicast to a bound client.
+    pub fn force_renew(&self, chaddr: HwAddr, ip: Ipv4Addr) -> std::io::Result<usize> {
+        let xid = (crate::utils::now_timestamp_ms() & 0xffff_ffff) as u32;
+        self.send_to(
+            Packet {
+                reply: true,
+                hops: 0,
+                xid,
+                secs: 0,
+                broadcast: false,
+                ciaddr: ip,
+                yiaddr: Ipv4Addr::new(0, 0, 0, 0),
+                siaddr: Ipv4Addr::new(0, 0, 0, 0),
+                giaddr: Ipv4Addr::new(0, 0, 0, 0),
+                chaddr,
+                sname: String::new(),
+                file: String::new(),
+                options: vec![
+                    DhcpOption::DhcpMessageType(MessageType::ForceRenew),
+                    DhcpOption::ServerIdentifier(self.server_ip),
+                ],
+            },
+            SocketAddr::new(IpAddr::V4(ip), 68),
+        )
+    }
+
     /// Encodes and sends a DHCP packet back to the client.
     pub fn send(&self, p: Packet) -> std::io::Result<usize> {
         let mut addr = self.src;
-        if p.broadcast || addr.ip() == IpAddr::V4(Ipv4Addr::new(0, 0, 0, 0)) {
+        if !p.giaddr.is_unspecified() {
+            // RFC 2131 4.1: replies to relayed requests go to the relay's server port
+            addr = SocketAddr::new(IpAddr::V4(p.giaddr), 67);
+        } else if p.broadcast || addr.ip() == IpAddr::V4(Ipv4Addr::new(0, 0, 0, 0)) {
             addr.set_ip(std::net::IpAddr::V4(self.broadcast_ip));
         }
         // println!("Sending Response to: {:?}", addr); // Print the address
 
-        self.socket.send_to(p.encode(&mut self.out_buf.get()), addr)
+        self.send_to(p, addr)
+    }
+
+    /// Encodes and sends a DHCP packet to an explicit address.
+    pub fn send_to(&self, mut p: Packet, addr: SocketAddr) -> std::io::Result<usize> {
+        let t = p.message_type();
+        let secret = auth::attach(&mut p);
+        if t == Ok(MessageType::ForceRenew) && secret.is_none() {
+            return Err(std::io::Error::other("FORCERENEW needs authentication"));
+        }
+        let mut buf = self.out_buf.get();
+        let len = p.encode_within(&mut buf, self.max_size).len();
+        let data = &mut buf[..len];
+        if let Some(secret) = secret {
+            if !auth::seal(data, &secret) {
+                // a client that authenticates must not get an unsigned reply
+                return Err(std::io::Error::other("authentication option did not fit"));
+            }
+        }
+        let data = &*data;
+        let r = if let Some(replies) = &mut *self.over_dhcp6.borrow_mut() {
+            replies.push(data.to_vec());
+            Ok(data.len())
+        } else {
+            mirror::packet("out", addr, data);
+            match &self.queue {
+                Some(queue) => {
+                    queue.borrow_mut().push((data.to_vec(), addr));
+                    Ok(data.len())
+                }
+                None => self.socket.send_to(data, addr),
+            }
+        };
+        if let (Ok(t), Ok(_)) = (t, &r) {
+            stats::sent(t);
+        }
+        r
     }
 }
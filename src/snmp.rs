@@ -0,0 +1,259 @@
+//! A read-only SNMPv2c agent (RFC 3416) for the statistics, for monitoring stacks that poll
+//! rather than scrape.
+//!
+//! Under the configured OID, `.1.N` are the counters in the order of `Counters::counters`
+//! (Counter64), `.2.1` the active leases, `.2.2` the peak and `.2.3` the pool size (Gauge32).
+
+use crate::stats::Counters;
+
+use std::io;
+use std::net::{SocketAddr, UdpSocket};
+use std::sync::Arc;
+use std::thread;
+
+const SEQUENCE: u8 = 0x30;
+const INTEGER: u8 = 0x02;
+const OCTET_STRING: u8 = 0x04;
+const OBJECT_IDENTIFIER: u8 = 0x06;
+const GAUGE32: u8 = 0x42;
+const COUNTER64: u8 = 0x46;
+const NO_SUCH_OBJECT: u8 = 0x80;
+const END_OF_MIB_VIEW: u8 = 0x82;
+
+const GET: u8 = 0xa0;
+const GET_NEXT: u8 = 0xa1;
+const RESPONSE: u8 = 0xa2;
+const GET_BULK: u8 = 0xa5;
+
+const VERSION_2C: i64 = 1;
+/// Replies are kept below this so they are not fragmented.
+const MAX_REPLY: usize = 1400;
+
+/// Answers SNMPv2c GET, GETNEXT and GETBULK requests carrying `community` on `listen`.
+pub fn spawn(
+    listen: SocketAddr,
+    community: String,
+    oid: Vec<u32>,
+    counters: Arc<Counters>,
+) -> io::Result<()> {
+    let socket = UdpSocket::bind(listen)?;
+    println!("snmp listening on {}", listen);
+    thread::spawn(move || {
+        let mut buf = [0u8; 1500];
+        loop {
+            let (n, peer) = match socket.recv_from(&mut buf) {
+                Ok(r) => r,
+                Err(e) => {
+                    println!("WARN: snmp: {:?}", e);
+                    continue;
+                }
+            };
+            let objects = objects(&oid, &counters);
+            if let Some(reply) = answer(&buf[..n], &community, &objects) {
+                if let Err(e) = socket.send_to(&reply, peer) {
+                    println!("WARN: snmp: reply to {} failed: {:?}", peer, e);
+                }
+            }
+        }
+    });
+    Ok(())
+}
+
+/// Parses an OID written as `1.3.6.1...`.
+pub fn parse_oid(s: &str) -> Option<Vec<u32>> {
+    let oid: Vec<u32> = s
+        .trim_start_matches('.')
+        .split('.')
+        .map(|n| n.parse().ok())
+        .collect::<Option<_>>()?;
+    (oid.len() >= 2 && oid[0] <= 2 && oid[1] < 40).then_some(oid)
+}
+
+/// The values served, in OID order, each as its encoded type and value.
+fn objects(base: &[u32], counters: &Counters) -> Vec<(Vec<u32>, Vec<u8>)> {
+    let oid = |sub: &[u32]| [base, sub].concat();
+    let gauge = |n: usize| tlv(GAUGE32, &unsigned(n.min(u32::MAX as usize) as u64));
+    let mut v: Vec<(Vec<u32>, Vec<u8>)> = counters
+        .counters()
+        .into_iter()
+        .enumerate()
+        .map(|(i, (_, total))| (oid(&[1, i as u32 + 1]), tlv(COUNTER64, &unsigned(total))))
+        .collect();
+    v.push((oid(&[2, 1]), gauge(counters.active_leases())));
+    v.push((oid(&[2, 2]), gauge(counters.peak_leases() as usize)));
+    v.push((oid(&[2, 3]), gauge(counters.pool_size())));
+    v
+}
+
+/// The response to `msg`; `None` for anything but a v2c read request with the community.
+fn answer(msg: &[u8], community: &str, objects: &[(Vec<u32>, Vec<u8>)]) -> Option<Vec<u8>> {
+    let (tag, msg, _) = read(msg)?;
+    if tag != SEQUENCE {
+        return None;
+    }
+    let (version, rest) = read_integer(msg)?;
+    let (tag, name, rest) = read(rest)?;
+    if version != VERSION_2C || tag != OCTET_STRING || name != community.as_bytes() {
+        return None;
+    }
+    let (kind, pdu, _) = read(rest)?;
+    let (request_id, rest) = read_integer(pdu)?;
+    let (non_repeaters, rest) = read_integer(rest)?;
+    let (max_repetitions, rest) = read_integer(rest)?;
+    let (tag, mut list, _) = read(rest)?;
+    if tag != SEQUENCE {
+        return None;
+    }
+    let mut oids = vec![];
+    while !list.is_empty() {
+        let (tag, bind, rest) = read(list)?;
+        let (t, oid, _) = read(bind)?;
+        if tag != SEQUENCE || t != OBJECT_IDENTIFIER {
+            return None;
+        }
+        oids.push(decode_oid(oid)?);
+        list = rest;
+    }
+
+    let exact = |oid: &Vec<u32>| match objects.iter().find(|(o, _)| o == oid) {
+        Some((o, v)) => (o.clone(), v.clone()),
+        None => (oid.clone(), tlv(NO_SUCH_OBJECT, &[])),
+    };
+    let next = |oid: &Vec<u32>| match objects.iter().find(|(o, _)| o > oid) {
+        Some((o, v)) => (o.clone(), v.clone()),
+        None => (oid.clone(), tlv(END_OF_MIB_VIEW, &[])),
+    };
+    let mut binds = vec![];
+    match kind {
+        GET => binds.extend(oids.iter().map(|o| bind(exact(o)))),
+        GET_NEXT => binds.extend(oids.iter().map(|o| bind(next(o)))),
+        GET_BULK => {
+            let n = (non_repeaters.max(0) as usize).min(oids.len());
+            binds.extend(oids[..n].iter().map(|o| bind(next(o))));
+            let mut size: usize = binds.iter().map(Vec::len).sum();
+            let mut last = oids[n..].to_vec();
+            // RFC 3416 4.2.3: fewer repetitions are fine when the reply would grow too big
+            'rows: for _ in 0..max_repetitions.max(0) {
+                for oid in last.iter_mut() {
+                    let (o, v) = next(oid);
+                    let b = bind((o.clone(), v));
+                    size += b.len();
+                    if size > MAX_REPLY - 100 {
+                        break 'rows;
+                    }
+                    *oid = o;
+                    binds.push(b);
+                }
+            }
+        }
+        _ => return None,
+    }
+
+    let mut pdu = tlv(INTEGER, &signed(request_id));
+    pdu.extend(tlv(INTEGER, &[0]));
+    pdu.extend(tlv(INTEGER, &[0]));
+    pdu.extend(tlv(SEQUENCE, &binds.concat()));
+    let mut body = tlv(INTEGER, &signed(VERSION_2C));
+    body.extend(tlv(OCTET_STRING, community.as_bytes()));
+    body.extend(tlv(RESPONSE, &pdu));
+    Some(tlv(SEQUENCE, &body))
+}
+
+fn bind((oid, value): (Vec<u32>, Vec<u8>)) -> Vec<u8> {
+    let mut b = tlv(OBJECT_IDENTIFIER, &encode_oid(&oid));
+    b.extend(value);
+    tlv(SEQUENCE, &b)
+}
+
+/// Splits off one BER element: its tag, its contents and what follows.
+fn read(data: &[u8]) -> Option<(u8, &[u8], &[u8])> {
+    let tag = *data.first()?;
+    let first = *data.get(1)? as usize;
+    let (len, at) = match first {
+        0..=0x7f => (first, 2),
+        0x81..=0x84 => {
+            let n = first & 0x7f;
+            let bytes = data.get(2..2 + n)?;
+            (bytes.iter().fold(0, |l, b| l << 8 | *b as usize), 2 + n)
+        }
+        _ => return None,
+    };
+    let contents = data.get(at..at + len)?;
+    Some((tag, contents, &data[at + len..]))
+}
+
+fn read_integer(data: &[u8]) -> Option<(i64, &[u8])> {
+    let (tag, v, rest) = read(data)?;
+    if tag != INTEGER || v.is_empty() || v.len() > 8 {
+        return None;
+    }
+    let init = if v[0] & 0x80 != 0 { -1 } else { 0 };
+    Some((v.iter().fold(init, |n, b| n << 8 | *b as i64), rest))
+}
+
+fn decode_oid(data: &[u8]) -> Option<Vec<u32>> {
+    let (&first, rest) = data.split_first()?;
+    let mut oid = vec![
+        (first / 40).min(2) as u32,
+        first as u32 - 40 * (first / 40).min(2) as u32,
+    ];
+    let mut n: u32 = 0;
+    for b in rest {
+        n = n.checked_mul(128)? | (b & 0x7f) as u32;
+        if b & 0x80 == 0 {
+            oid.push(n);
+            n = 0;
+        }
+    }
+    Some(oid)
+}
+
+fn encode_oid(oid: &[u32]) -> Vec<u8> {
+    let mut out = vec![(oid[0] * 40 + oid[1]) as u8];
+    for &n in &oid[2..] {
+        let mut bytes = vec![(n & 0x7f) as u8];
+        let mut rest = n >> 7;
+        while rest > 0 {
+            bytes.push((rest & 0x7f) as u8 | 0x80);
+            rest >>= 7;
+        }
+        out.extend(bytes.iter().rev());
+    }
+    out
+}
+
+fn tlv(tag: u8, value: &[u8]) -> Vec<u8> {
+    let mut out = vec![tag];
+    match value.len() {
+        n @ 0..=0x7f => out.push(n as u8),
+        n @ 0x80..=0xff => out.extend([0x81, n as u8]),
+        n => out.extend([0x82, (n >> 8) as u8, n as u8]),
+    }
+    out.extend(value);
+    out
+}
+
+/// A non-negative integer in the fewest bytes, with a leading zero when the top bit is set.
+fn unsigned(n: u64) -> Vec<u8> {
+    let bytes = n.to_be_bytes();
+    let skip = bytes.iter().take_while(|b| **b == 0).count().min(7);
+    let mut out = if bytes[skip] & 0x80 != 0 {
+        vec![0]
+    } else {
+        vec![]
+    };
+    out.extend(&bytes[skip..]);
+    out
+}
+
+fn signed(n: i64) -> Vec<u8> {
+    let bytes = n.to_be_bytes();
+    let mut skip = 0;
+    while skip < 7
+        && ((bytes[skip] == 0 && bytes[skip + 1] & 0x80 == 0)
+            || (bytes[skip] == 0xff && bytes[skip + 1] & 0x80 != 0))
+    {
+        skip += 1;
+    }
+    bytes[skip..].to_vec()
+}
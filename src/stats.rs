@@ -0,0 +1,296 @@
+//! Per-instance counters, updated by the serve loop and read by the metric exporters.
+//!
+//! With `stats_file` set the totals are checkpointed periodically and read back at start,
+//! so they keep counting across restarts.
+
+use crate::config::{MetricsProtocol, MetricsPush, StatsFile};
+use crate::options::MessageType;
+use crate::webhook;
+
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::fs;
+use std::io::{self, Write};
+use std::net::{TcpStream, UdpSocket};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+const MESSAGE_TYPES: [&str; 17] = [
+    "discover",
+    "offer",
+    "request",
+    "decline",
+    "ack",
+    "nak",
+    "release",
+    "inform",
+    "forcerenew",
+    "leasequery",
+    "leaseunassigned",
+    "leaseunknown",
+    "leaseactive",
+    "bulkleasequery",
+    "leasequerydone",
+    "activeleasequery",
+    "leasequerystatus",
+];
+
+/// The counters of one server instance.
+#[derive(Default)]
+pub struct Counters {
+    received: [AtomicU64; 17],
+    sent: [AtomicU64; 17],
+    /// New bindings made.
+    bound: AtomicU64,
+    /// Most leases active at once.
+    peak_leases: AtomicU64,
+    /// Active leases and pool size per lease file, so every tenant reports its own share of
+    /// the gauges.
+    leases: Mutex<HashMap<String, (usize, usize)>>,
+}
+
+impl Counters {
+    /// Monotonic counters as `(name, total)`.
+    pub fn counters(&self) -> Vec<(String, u64)> {
+        let mut v = vec![];
+        for (dir, counts) in [("received", &self.received), ("sent", &self.sent)] {
+            for (name, c) in MESSAGE_TYPES.iter().zip(counts.iter()) {
+                v.push((format!("{}.{}", dir, name), c.load(Ordering::Relaxed)));
+            }
+        }
+        v.push((
+            "leases.bound".to_string(),
+            self.bound.load(Ordering::Relaxed),
+        ));
+        v
+    }
+
+    pub fn active_leases(&self) -> usize {
+        self.leases.lock().unwrap().values().map(|(n, _)| n).sum()
+    }
+
+    /// Addresses in the pools.
+    pub fn pool_size(&self) -> usize {
+        self.leases.lock().unwrap().values().map(|(_, n)| n).sum()
+    }
+
+    pub fn peak_leases(&self) -> u64 {
+        self.peak_leases.load(Ordering::Relaxed)
+    }
+
+    /// Everything, as shown on the management interface and checkpointed.
+    pub fn to_json(&self) -> serde_json::Value {
+        let counters: serde_json::Map<String, serde_json::Value> = self
+            .counters()
+            .into_iter()
+            .map(|(name, total)| (name, total.into()))
+            .collect();
+        serde_json::json!({
+            "counters": counters,
+            "leases_active": self.active_leases(),
+            "leases_peak": self.peak_leases(),
+        })
+    }
+
+    /// Continues from the totals of a checkpoint written by `to_json`.
+    fn restore(&self, saved: &serde_json::Value) {
+        let total = |name: &str| saved["counters"][name].as_u64().unwrap_or(0);
+        for (dir, counts) in [("received", &self.received), ("sent", &self.sent)] {
+            for (name, c) in MESSAGE_TYPES.iter().zip(counts.iter()) {
+                c.store(total(&format!("{}.{}", dir, name)), Ordering::Relaxed);
+            }
+        }
+        self.bound.store(total("leases.bound"), Ordering::Relaxed);
+        self.peak_leases.fetch_max(
+            saved["leases_peak"].as_u64().unwrap_or(0),
+            Ordering::Relaxed,
+        );
+    }
+}
+
+thread_local! {
+    /// The instance served by this thread, see `instance.rs`.
+    static CURRENT: RefCell<Option<Arc<Counters>>> = const { RefCell::new(None) };
+}
+
+/// Makes `counters` the target of the updates made on this thread.
+pub fn enter(counters: Arc<Counters>) {
+    CURRENT.with(|c| *c.borrow_mut() = Some(counters));
+}
+
+fn with(f: impl FnOnce(&Counters)) {
+    CURRENT.with(|c| {
+        if let Some(counters) = c.borrow().as_ref() {
+            f(counters)
+        }
+    });
+}
+
+pub fn received(t: MessageType) {
+    with(|c| {
+        c.received[t as usize - 1].fetch_add(1, Ordering::Relaxed);
+    });
+}
+
+pub fn sent(t: MessageType) {
+    with(|c| {
+        c.sent[t as usize - 1].fetch_add(1, Ordering::Relaxed);
+    });
+}
+
+pub fn bound() {
+    with(|c| {
+        c.bound.fetch_add(1, Ordering::Relaxed);
+    });
+}
+
+pub fn set_active_leases(lease_file: &str, n: usize, pool_size: usize) {
+    with(|c| {
+        c.leases
+            .lock()
+            .unwrap()
+            .insert(lease_file.to_string(), (n, pool_size));
+        c.peak_leases
+            .fetch_max(c.active_leases() as u64, Ordering::Relaxed);
+    });
+}
+
+/// The calling thread's counters as JSON, see `Counters::to_json`.
+pub fn snapshot() -> serde_json::Value {
+    let mut v = serde_json::Value::Null;
+    with(|c| v = c.to_json());
+    v
+}
+
+/// Loads the last checkpoint into `counters` and starts writing new ones every `interval`.
+pub fn spawn_checkpoint(conf: StatsFile, counters: Arc<Counters>) {
+    match fs::read(&conf.path) {
+        Ok(data) => match serde_json::from_slice(&data) {
+            Ok(saved) => counters.restore(&saved),
+            Err(e) => println!("WARN: {}: {}", conf.path, e),
+        },
+        Err(e) if e.kind() == io::ErrorKind::NotFound => {}
+        Err(e) => println!("WARN: {}: {:?}", conf.path, e),
+    }
+    let interval = duration_str::parse(conf.interval.as_str()).unwrap();
+    thread::spawn(move || loop {
+        thread::sleep(interval);
+        let tmp = format!("{}.tmp", conf.path);
+        let r = fs::write(&tmp, counters.to_json().to_string())
+            .and_then(|_| fs::rename(&tmp, &conf.path));
+        if let Err(e) = r {
+            println!("WARN: checkpoint stats to {} failed: {:?}", conf.path, e);
+        }
+    });
+}
+
+/// Starts pushing the metrics to a StatsD, Graphite or OTLP collector every `interval`.
+pub fn spawn_push(conf: MetricsPush, counters: Arc<Counters>) {
+    let interval = duration_str::parse(conf.interval.as_str()).unwrap();
+    thread::spawn(move || {
+        // totals restored from a checkpoint were pushed before the restart
+        let mut last: HashMap<String, u64> = counters.counters().into_iter().collect();
+        let start = unix_nanos();
+        loop {
+            thread::sleep(interval);
+            let r = match conf.protocol {
+                MetricsProtocol::Statsd => push_statsd(&conf, &counters, &mut last),
+                MetricsProtocol::Graphite => push_graphite(&conf, &counters),
+                MetricsProtocol::Otlp => push_otlp(&conf, &counters, &start),
+            };
+            if let Err(e) = r {
+                println!("WARN: push metrics to {} failed: {:?}", conf.addr, e);
+            }
+        }
+    });
+}
+
+/// StatsD counters are deltas, so only the increase since the previous push is sent.
+fn push_statsd(
+    conf: &MetricsPush,
+    counters: &Counters,
+    last: &mut HashMap<String, u64>,
+) -> std::io::Result<()> {
+    let mut lines = vec![
+        format!(
+            "{}.leases.active:{}|g",
+            conf.prefix,
+            counters.active_leases()
+        ),
+        format!("{}.leases.peak:{}|g", conf.prefix, counters.peak_leases()),
+    ];
+    for (name, total) in counters.counters() {
+        let prev = last.insert(name.clone(), total).unwrap_or(0);
+        if total > prev {
+            lines.push(format!("{}.{}:{}|c", conf.prefix, name, total - prev));
+        }
+    }
+    let socket = UdpSocket::bind("0.0.0.0:0")?;
+    socket.send_to(lines.join("\n").as_bytes(), conf.addr)?;
+    Ok(())
+}
+
+fn push_graphite(conf: &MetricsPush, counters: &Counters) -> std::io::Result<()> {
+    let ts = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs();
+    let mut body = format!(
+        "{p}.leases.active {} {ts}\n{p}.leases.peak {} {ts}\n",
+        counters.active_leases(),
+        counters.peak_leases(),
+        p = conf.prefix,
+        ts = ts
+    );
+    for (name, total) in counters.counters() {
+        body.push_str(&format!("{}.{} {} {}\n", conf.prefix, name, total, ts));
+    }
+    let mut stream = TcpStream::connect_timeout(&conf.addr, Duration::from_secs(5))?;
+    stream.write_all(body.as_bytes())
+}
+
+fn unix_nanos() -> String {
+    let now = SystemTime::now().duration_since(UNIX_EPOCH);
+    now.unwrap_or_default().as_nanos().to_string()
+}
+
+/// OTLP cumulative sums since `start` and gauges, in the protobuf JSON mapping (64 bit
+/// integers as strings).
+fn push_otlp(conf: &MetricsPush, counters: &Counters, start: &str) -> std::io::Result<()> {
+    let now = unix_nanos();
+    let gauge = |name: &str, value: u64| {
+        serde_json::json!({
+            "name": format!("{}.{}", conf.prefix, name),
+            "gauge": { "dataPoints": [{ "asInt": value.to_string(), "timeUnixNano": now }] },
+        })
+    };
+    let mut metrics = vec![
+        gauge("leases.active", counters.active_leases() as u64),
+        gauge("leases.peak", counters.peak_leases()),
+    ];
+    for (name, total) in counters.counters() {
+        metrics.push(serde_json::json!({
+            "name": format!("{}.{}", conf.prefix, name),
+            "sum": {
+                "dataPoints": [{
+                    "asInt": total.to_string(),
+                    "startTimeUnixNano": start,
+                    "timeUnixNano": now,
+                }],
+                "aggregationTemporality": 2,
+                "isMonotonic": true,
+            },
+        }));
+    }
+    let body = serde_json::json!({
+        "resourceMetrics": [{
+            "resource": {
+                "attributes": [{ "key": "service.name", "value": { "stringValue": "rdhcpd" } }],
+            },
+            "scopeMetrics": [{ "scope": { "name": "rdhcpd" }, "metrics": metrics }],
+        }],
+    });
+    webhook::send(&format!("http://{}/v1/metrics", conf.addr), &body).map_err(io::Error::other)
+}
@@ -0,0 +1,31 @@
+//! Overhears the replies of the DHCP server being replaced during the takeover period.
+//!
+//! Broadcast ACKs go to the client port, so a second socket is bound on 68; its packets are
+//! handed to the serve loop like those of the main socket.
+
+use std::io;
+use std::net::UdpSocket;
+use std::sync::mpsc;
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// Listens on port 68 for `duration`, then closes the socket and the channel.
+pub fn spawn(duration: Duration) -> io::Result<mpsc::Receiver<Vec<u8>>> {
+    let socket = UdpSocket::bind("0.0.0.0:68")?;
+    socket.set_broadcast(true)?;
+    socket.set_read_timeout(Some(Duration::from_secs(1)))?;
+    let (tx, rx) = mpsc::channel();
+    let until = Instant::now() + duration;
+    thread::spawn(move || {
+        let mut buf = [0u8; 1500];
+        while Instant::now() < until {
+            if let Ok(l) = socket.recv(&mut buf) {
+                if tx.send(buf[..l].to_vec()).is_err() {
+                    return;
+                }
+            }
+        }
+        println!("takeover period over, serving clients");
+    });
+    Ok(rx)
+}
@@ -0,0 +1,58 @@
+//! `${name}` placeholders in string option values, expanded per client at reply time.
+
+use crate::hwaddr::HwAddr;
+
+use std::net::Ipv4Addr;
+
+/// What a placeholder can stand for.
+pub struct Vars {
+    pub mac: HwAddr,
+    pub ip: Ipv4Addr,
+    /// The lease's DNS name, else the sanitized name the client sent.
+    pub hostname: Option<String>,
+}
+
+impl Vars {
+    fn get(&self, name: &str) -> Option<String> {
+        let hex: Vec<String> = self
+            .mac
+            .as_bytes()
+            .iter()
+            .map(|b| format!("{:02x}", b))
+            .collect();
+        Some(match name {
+            "mac" => hex.join(":"),
+            "mac-hex" => hex.concat(),
+            "mac-dash" => hex.join("-"),
+            "ip" => self.ip.to_string(),
+            "hostname" => self.hostname.clone().unwrap_or_default(),
+            _ => return None,
+        })
+    }
+}
+
+/// Replaces every known `${name}` in `s`; unknown ones are left as they are.
+pub fn expand(s: &str, vars: &Vars) -> String {
+    let mut out = String::with_capacity(s.len());
+    let mut rest = s;
+    while let Some(start) = rest.find("${") {
+        out.push_str(&rest[..start]);
+        let after = &rest[start + 2..];
+        match after.find('}') {
+            Some(end) => {
+                let name = &after[..end];
+                match vars.get(name) {
+                    Some(v) => out.push_str(&v),
+                    None => out.push_str(&rest[start..start + 3 + end]),
+                }
+                rest = &after[end + 1..];
+            }
+            None => {
+                out.push_str(&rest[start..]);
+                rest = "";
+            }
+        }
+    }
+    out.push_str(rest);
+    out
+}
@@ -0,0 +1,377 @@
+//! Routes requests to isolated per-tenant servers based on the relay they arrived through.
+
+use crate::config::{Config, Tenant};
+use crate::dhcpd::{self, Dhcpd, Leases, Snapshot};
+use crate::leasequery;
+use crate::mgmt;
+use crate::options;
+use crate::packet::Packet;
+use crate::server;
+use crate::utils;
+
+use std::net::Ipv4Addr;
+
+pub struct Tenants {
+    local: Dhcpd,
+    tenants: Vec<(Tenant, Dhcpd)>,
+}
+
+impl Tenants {
+    pub fn new(conf: Config) -> Tenants {
+        let tenants = conf
+            .tenants
+            .iter()
+            .map(|t| (t.clone(), Dhcpd::new(t.conf.clone())))
+            .collect();
+        let mut tenants = Tenants {
+            local: Dhcpd::new(conf),
+            tenants,
+        };
+        for i in 0..=tenants.tenants.len() {
+            let Some(name) = tenants.get(i).conf().shared_network.clone() else {
+                continue;
+            };
+            let others = (0..=tenants.tenants.len())
+                .filter(|j| *j != i)
+                .map(|j| tenants.get(j).conf())
+                .filter(|c| c.shared_network.as_ref() == Some(&name))
+                .cloned()
+                .collect();
+            tenants.get_mut(i).share_network(others);
+        }
+        tenants
+    }
+
+    /// Picks the tenant by option 82 remote-id first, then by circuit-id, then by the subnet
+    /// named in option 82 link-selection or option 118, then by giaddr: the listed `relays`
+    /// first, then the subnet holding it. Packets that were not relayed are served from the
+    /// top-level configuration.
+    ///
+    /// Servers are numbered from the top-level one (0), then the tenants in order.
+    fn pick(&self, packet: &Packet) -> Option<usize> {
+        for code in [options::AGENT_REMOTE_ID, options::AGENT_CIRCUIT_ID] {
+            let Some(value) = packet.relay_suboption(code) else {
+                continue;
+            };
+            if let Some(i) = self.tenants.iter().position(|(t, _)| {
+                let ids = match code {
+                    options::AGENT_REMOTE_ID => &t.remote_ids,
+                    _ => &t.circuit_ids,
+                };
+                ids.iter()
+                    .any(|r| r.as_bytes() == value || utils::parse_hex(r).as_deref() == Some(value))
+            }) {
+                return Some(i + 1);
+            }
+        }
+        let in_subnet = |addr: Ipv4Addr| {
+            if self.local.conf().subnet_contains(addr) {
+                return Some(0);
+            }
+            self.tenants
+                .iter()
+                .position(|(t, _)| t.conf.subnet_contains(addr))
+                .map(|i| i + 1)
+        };
+        if let Some(subnet) = packet.selected_subnet() {
+            return in_subnet(subnet);
+        }
+        if packet.giaddr == Ipv4Addr::new(0, 0, 0, 0) {
+            return Some(0);
+        }
+        if let Some(i) = self
+            .tenants
+            .iter()
+            .position(|(t, _)| t.relays.contains(&packet.giaddr))
+        {
+            return Some(i + 1);
+        }
+        // a relay that is not listed anywhere sits on the subnet it forwards for
+        in_subnet(packet.giaddr)
+    }
+
+    /// Within the shared network of server `i`, the server holding the client's lease, else
+    /// the one holding the requested address, or for a DISCOVER the first with an address
+    /// left, starting from `i`.
+    fn on_shared_network(&self, i: usize, packet: &Packet) -> usize {
+        let Some(name) = &self.get(i).conf().shared_network else {
+            return i;
+        };
+        let members: Vec<usize> = std::iter::once(i)
+            .chain((0..=self.tenants.len()).filter(|j| *j != i))
+            .filter(|j| self.get(*j).conf().shared_network.as_ref() == Some(name))
+            .collect();
+        if let Some(j) = members.iter().find(|j| self.get(**j).knows(packet)) {
+            return *j;
+        }
+        let found = if packet.message_type() == Ok(options::MessageType::Discover) {
+            members.iter().find(|j| self.get(**j).has_free(packet))
+        } else {
+            let ip = packet.requested_ip();
+            members
+                .iter()
+                .find(|j| self.get(**j).conf().subnet_contains(ip))
+        };
+        found.copied().unwrap_or(i)
+    }
+
+    fn get(&self, i: usize) -> &Dhcpd {
+        match i {
+            0 => &self.local,
+            _ => &self.tenants[i - 1].1,
+        }
+    }
+
+    fn get_mut(&mut self, i: usize) -> &mut Dhcpd {
+        match i {
+            0 => &mut self.local,
+            _ => &mut self.tenants[i - 1].1,
+        }
+    }
+
+    fn select(&mut self, packet: &Packet) -> Option<&mut Dhcpd> {
+        let i = self.pick(packet)?;
+        Some(self.get_mut(i))
+    }
+
+    fn pools(&self) -> mgmt::Response {
+        let mut pools = vec![self.local.pool_json()];
+        pools.extend(self.tenants.iter().map(|(_, d)| d.pool_json()));
+        mgmt::Response::json(200, &pools)
+    }
+}
+
+impl server::Handler for Tenants {
+    fn dump(&self, out: &mut String) {
+        self.local.dump(out);
+        for (_, dhcpd) in &self.tenants {
+            dhcpd.dump(out);
+        }
+    }
+
+    fn tick(&mut self) {
+        self.local.tick();
+        for (_, dhcpd) in &mut self.tenants {
+            dhcpd.tick();
+        }
+    }
+
+    fn proxy_reply(&mut self, request: &Packet) -> Option<Packet> {
+        self.select(request)?.proxy_reply(request)
+    }
+
+    /// A leasequery goes to the server of its relay agent, like a request would.
+    fn bulk_leasequery(&mut self, query: &Packet) -> Result<Vec<Packet>, leasequery::Status> {
+        match self.select(query) {
+            Some(dhcpd) => dhcpd.bulk_leasequery(query),
+            None => Err((
+                leasequery::STATUS_NOT_ALLOWED,
+                format!("no tenant for relay {}", query.giaddr),
+            )),
+        }
+    }
+
+    fn handle_request(&mut self, server: &server::Server, in_packet: Packet) {
+        let Some(i) = self.pick(&in_packet) else {
+            println!("no tenant for relay {:?}, ignored", in_packet.giaddr);
+            return;
+        };
+        let i = self.on_shared_network(i, &in_packet);
+        self.get_mut(i).handle_request(server, in_packet)
+    }
+
+    /// Management requests go to the top-level server first, then to each tenant, until one
+    /// of them knows the addressed object.
+    ///
+    /// A snapshot covers every tenant; on restore each one takes the leases in its own subnet.
+    fn handle_mgmt(&mut self, server: &server::Server, req: mgmt::Request) -> mgmt::Response {
+        match (req.method.as_str(), req.segments().as_slice()) {
+            ("GET", ["pool"]) => return self.pools(),
+            ("GET", ["readyz"]) => {
+                let mut ready = true;
+                let mut details = vec![];
+                for d in std::iter::once(&self.local).chain(self.tenants.iter().map(|(_, d)| d)) {
+                    let (r, v) = d.readiness();
+                    ready &= r;
+                    details.push(v);
+                }
+                return mgmt::Response::json(if ready { 200 } else { 503 }, &details);
+            }
+            ("POST", ["drain"]) => {
+                let d = match dhcpd::drain_lease_time(&req.body) {
+                    Ok(d) => d,
+                    Err(response) => return response,
+                };
+                self.local.start_drain(d);
+                for (_, dhcpd) in self.tenants.iter_mut() {
+                    dhcpd.start_drain(d);
+                }
+                return self.pools();
+            }
+            ("POST", ["resume"]) => {
+                self.local.resume();
+                for (_, dhcpd) in self.tenants.iter_mut() {
+                    dhcpd.resume();
+                }
+                return self.pools();
+            }
+            ("GET", ["snapshot"]) => {
+                let mut snapshot = Snapshot::new(self.local.snapshot());
+                for (tenant, dhcpd) in &self.tenants {
+                    snapshot
+                        .tenants
+                        .insert(tenant.conf.lease_file.clone(), dhcpd.snapshot());
+                }
+                return mgmt::Response::json(200, &snapshot);
+            }
+            ("PUT", ["snapshot"]) => {
+                let snapshot = match Snapshot::parse(&req.body) {
+                    Ok(snapshot) => snapshot,
+                    Err(response) => return response,
+                };
+                let mut restored = self.local.restore(&snapshot.leases);
+                // Version 1 snapshots merged every tenant into `leases`.
+                let merged = snapshot.tenants.is_empty();
+                for (tenant, dhcpd) in self.tenants.iter_mut() {
+                    restored += match snapshot.tenants.get(&tenant.conf.lease_file) {
+                        Some(leases) => dhcpd.restore(leases),
+                        None if merged => dhcpd.restore(&snapshot.leases),
+                        None => dhcpd.restore(&Leases::new()),
+                    };
+                }
+                println!("restored {} lease(s) from snapshot", restored);
+                return mgmt::Response::json(200, &serde_json::json!({ "restored": restored }));
+            }
+            _ => {}
+        }
+        let mut response = self.local.handle_mgmt(server, req.clone());
+        for (_, dhcpd) in self.tenants.iter_mut() {
+            if response.status != 404 {
+                break;
+            }
+            response = dhcpd.handle_mgmt(server, req.clone());
+        }
+        response
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::hwaddr::HwAddr;
+    use crate::options::{DhcpOption, MessageType, RawDhcpOption};
+
+    /// Server 1 is the tenant behind relay 10.9.0.1, 2 the one of remote-id `sw1` or `01:02`,
+    /// 3 a pool.
+    fn tenants() -> Tenants {
+        let conf = crate::config::from_value(
+            serde_yaml::from_str(
+                "
+intf: lo
+listen_addr: 192.168.10.1
+start: 192.168.10.100
+end: 192.168.10.200
+netmask: 255.255.255.0
+gateway: 192.168.10.1
+broadcast: 192.168.10.255
+dns_servers: [192.168.10.1]
+lease_static: /nonexistent/rdhcpd-test.static
+lease_file: /nonexistent/rdhcpd-test.leases
+lease_time: 1h
+tenants:
+  - relays: [10.9.0.1]
+    start: 10.1.0.100
+    end: 10.1.0.200
+    netmask: 255.255.255.0
+    gateway: 10.1.0.1
+    lease_file: /nonexistent/rdhcpd-test.leases.1
+  - remote_ids: [sw1, '01:02']
+    start: 10.3.0.100
+    end: 10.3.0.200
+    netmask: 255.255.255.0
+    gateway: 10.3.0.1
+    lease_file: /nonexistent/rdhcpd-test.leases.3
+pools:
+  - start: 10.2.0.100
+    end: 10.2.0.200
+    netmask: 255.255.255.0
+    gateway: 10.2.0.1
+",
+            )
+            .unwrap(),
+        )
+        .unwrap();
+        Tenants::new(conf)
+    }
+
+    fn discover(giaddr: [u8; 4], options: Vec<DhcpOption>) -> Packet {
+        Packet {
+            reply: false,
+            hops: 1,
+            xid: 1,
+            secs: 0,
+            broadcast: false,
+            ciaddr: Ipv4Addr::UNSPECIFIED,
+            yiaddr: Ipv4Addr::UNSPECIFIED,
+            siaddr: Ipv4Addr::UNSPECIFIED,
+            giaddr: Ipv4Addr::from(giaddr),
+            chaddr: HwAddr::new(1, &[2, 0, 0, 0, 0, 1]),
+            sname: String::new(),
+            file: String::new(),
+            options: std::iter::once(DhcpOption::DhcpMessageType(MessageType::Discover))
+                .chain(options)
+                .collect(),
+        }
+    }
+
+    fn subnet_selection(addr: [u8; 4]) -> DhcpOption {
+        DhcpOption::SubnetSelection(Ipv4Addr::from(addr))
+    }
+
+    #[test]
+    fn picks_by_giaddr() {
+        let t = tenants();
+        assert_eq!(t.pick(&discover([0, 0, 0, 0], vec![])), Some(0));
+        assert_eq!(t.pick(&discover([10, 9, 0, 1], vec![])), Some(1));
+        // unlisted relays sit on the subnet they forward for
+        assert_eq!(t.pick(&discover([10, 2, 0, 1], vec![])), Some(3));
+        assert_eq!(t.pick(&discover([192, 168, 10, 2], vec![])), Some(0));
+        assert_eq!(t.pick(&discover([10, 4, 0, 1], vec![])), None);
+    }
+
+    #[test]
+    fn subnet_selection_wins_over_giaddr() {
+        let t = tenants();
+        let p = discover([10, 9, 0, 1], vec![subnet_selection([10, 2, 0, 0])]);
+        assert_eq!(t.pick(&p), Some(3));
+        let p = discover([0, 0, 0, 0], vec![subnet_selection([10, 1, 0, 7])]);
+        assert_eq!(t.pick(&p), Some(1));
+        let p = discover([10, 9, 0, 1], vec![subnet_selection([10, 4, 0, 0])]);
+        assert_eq!(t.pick(&p), None);
+    }
+
+    #[test]
+    fn remote_id_wins_over_subnet() {
+        let t = tenants();
+        let agent = |remote_id: &[u8]| {
+            DhcpOption::RelayAgentInformation(vec![RawDhcpOption {
+                code: options::AGENT_REMOTE_ID,
+                data: remote_id.to_vec(),
+            }])
+        };
+        let p = discover(
+            [10, 2, 0, 1],
+            vec![agent(b"sw1"), subnet_selection([10, 1, 0, 0])],
+        );
+        assert_eq!(t.pick(&p), Some(2));
+        // as hex too
+        assert_eq!(
+            t.pick(&discover([10, 2, 0, 1], vec![agent(&[1, 2])])),
+            Some(2)
+        );
+        assert_eq!(
+            t.pick(&discover([10, 2, 0, 1], vec![agent(b"sw2")])),
+            Some(3)
+        );
+    }
+}
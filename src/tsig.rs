@@ -0,0 +1,343 @@
+//! TSIG signatures (RFC 8945) for the dynamic DNS updates and their answers, with HMAC-SHA256.
+
+use crate::options;
+
+use std::time::{SystemTime, UNIX_EPOCH};
+
+pub const HMAC_SHA256: &str = "hmac-sha256";
+
+const TYPE_TSIG: u16 = 250;
+const CLASS_ANY: u16 = 255;
+/// Seconds the server may be off from our clock.
+const FUDGE: u16 = 300;
+
+/// A shared key as configured: its name and the base64 secret.
+#[derive(Debug, Clone)]
+pub struct Key {
+    pub name: String,
+    pub secret: Vec<u8>,
+}
+
+impl Key {
+    /// Signs `msg`, a complete message without additional records, by appending the TSIG
+    /// record, and returns the MAC the answer is bound to.
+    pub fn sign(&self, msg: &mut Vec<u8>) -> [u8; 32] {
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |d| d.as_secs());
+        let name = options::encode_domain_list(&[self.name.to_ascii_lowercase()]);
+        let algorithm = options::encode_domain_list(&[HMAC_SHA256.to_string()]);
+        let time = &now.to_be_bytes()[2..];
+
+        let mut signed = msg.clone();
+        signed.extend(&name);
+        signed.extend(CLASS_ANY.to_be_bytes());
+        signed.extend(0u32.to_be_bytes());
+        signed.extend(&algorithm);
+        signed.extend(time);
+        signed.extend(FUDGE.to_be_bytes());
+        // no error, no other data
+        signed.extend([0, 0, 0, 0]);
+        let mac = hmac_sha256(&self.secret, &signed);
+
+        let mut rdata = algorithm;
+        rdata.extend(time);
+        rdata.extend(FUDGE.to_be_bytes());
+        rdata.extend((mac.len() as u16).to_be_bytes());
+        rdata.extend(&mac);
+        rdata.extend(&msg[..2]);
+        rdata.extend([0, 0, 0, 0]);
+
+        msg.extend(name);
+        msg.extend(TYPE_TSIG.to_be_bytes());
+        msg.extend(CLASS_ANY.to_be_bytes());
+        msg.extend(0u32.to_be_bytes());
+        msg.extend((rdata.len() as u16).to_be_bytes());
+        msg.extend(rdata);
+        let additional = u16::from_be_bytes([msg[10], msg[11]]) + 1;
+        msg[10..12].copy_from_slice(&additional.to_be_bytes());
+        mac
+    }
+
+    /// Checks the TSIG record closing `msg`, the answer to a request signed with `request_mac`
+    /// (RFC 8945 5.3.1).
+    pub fn verify(&self, msg: &[u8], request_mac: &[u8]) -> Result<(), String> {
+        let t = last_tsig(msg).ok_or("answer is not signed")?;
+        if t.name != self.name.trim_end_matches('.').to_ascii_lowercase() {
+            return Err(format!("answer signed with key {}", t.name));
+        }
+        if t.algorithm != HMAC_SHA256 {
+            return Err(format!("answer signed with {}", t.algorithm));
+        }
+        if t.error != 0 {
+            return Err(format!("TSIG error {}", t.error));
+        }
+
+        let mut signed = (request_mac.len() as u16).to_be_bytes().to_vec();
+        signed.extend(request_mac);
+        signed.extend(t.original_id.to_be_bytes());
+        signed.extend(&msg[2..10]);
+        signed.extend((t.additional - 1).to_be_bytes());
+        signed.extend(&msg[12..t.start]);
+        signed.extend(options::encode_domain_list(std::slice::from_ref(&t.name)));
+        signed.extend(CLASS_ANY.to_be_bytes());
+        signed.extend(0u32.to_be_bytes());
+        signed.extend(options::encode_domain_list(std::slice::from_ref(
+            &t.algorithm,
+        )));
+        signed.extend(t.time);
+        signed.extend(t.fudge.to_be_bytes());
+        signed.extend(t.error.to_be_bytes());
+        signed.extend((t.other.len() as u16).to_be_bytes());
+        signed.extend(t.other);
+        if t.mac != hmac_sha256(&self.secret, &signed) {
+            return Err("bad TSIG signature on the answer".to_string());
+        }
+
+        let mut time = [0u8; 8];
+        time[2..].copy_from_slice(t.time);
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |d| d.as_secs());
+        if u64::from_be_bytes(time).abs_diff(now) > t.fudge as u64 {
+            return Err("answer signed at a bad time".to_string());
+        }
+        Ok(())
+    }
+}
+
+/// The TSIG record of a message, with `start` its offset.
+struct Tsig<'a> {
+    start: usize,
+    additional: u16,
+    name: String,
+    algorithm: String,
+    time: &'a [u8],
+    fudge: u16,
+    mac: &'a [u8],
+    original_id: u16,
+    error: u16,
+    other: &'a [u8],
+}
+
+/// Finds the TSIG record, which has to be the last one of the message.
+fn last_tsig(msg: &[u8]) -> Option<Tsig<'_>> {
+    let additional = be16(msg, 10)?;
+    let records = be16(msg, 6)? as usize + be16(msg, 8)? as usize + additional as usize;
+    let mut at = 12;
+    for _ in 0..be16(msg, 4)? {
+        at = read_name(msg, at)?.1 + 4;
+    }
+    for _ in 1..records {
+        let end = read_name(msg, at)?.1;
+        at = end + 10 + be16(msg, end + 8)? as usize;
+    }
+    let start = at;
+    let (name, at) = read_name(msg, at)?;
+    if additional == 0 || be16(msg, at)? != TYPE_TSIG {
+        return None;
+    }
+    let rdata = msg.get(at + 10..at + 10 + be16(msg, at + 8)? as usize)?;
+    // algorithm, time signed (48 bits), fudge, MAC, original id, error, other data
+    let (algorithm, at) = read_name(rdata, 0)?;
+    let mac_end = at + 10 + be16(rdata, at + 8)? as usize;
+    let other_len = be16(rdata, mac_end + 4)? as usize;
+    Some(Tsig {
+        start,
+        additional,
+        name,
+        algorithm,
+        time: rdata.get(at..at + 6)?,
+        fudge: be16(rdata, at + 6)?,
+        mac: rdata.get(at + 10..mac_end)?,
+        original_id: be16(rdata, mac_end)?,
+        error: be16(rdata, mac_end + 2)?,
+        other: rdata.get(mac_end + 6..mac_end + 6 + other_len)?,
+    })
+}
+
+fn be16(b: &[u8], at: usize) -> Option<u16> {
+    Some(u16::from_be_bytes([*b.get(at)?, *b.get(at + 1)?]))
+}
+
+/// Reads the name at `at`, following compression pointers, as lowercase labels joined by dots;
+/// also returns the offset after it.
+fn read_name(msg: &[u8], mut at: usize) -> Option<(String, usize)> {
+    let mut labels = vec![];
+    let mut end = None;
+    for _ in 0..128 {
+        let len = *msg.get(at)? as usize;
+        if len == 0 {
+            return Some((labels.join("."), end.unwrap_or(at + 1)));
+        }
+        if len & 0xc0 == 0xc0 {
+            end.get_or_insert(at + 2);
+            at = (len & 0x3f) << 8 | *msg.get(at + 1)? as usize;
+            continue;
+        }
+        let label = msg.get(at + 1..at + 1 + len)?;
+        labels.push(String::from_utf8_lossy(label).to_ascii_lowercase());
+        at += 1 + len;
+    }
+    None
+}
+
+/// Decodes standard base64, padding optional; `None` on any other character.
+pub fn decode_base64(s: &str) -> Option<Vec<u8>> {
+    let mut out = vec![];
+    let (mut acc, mut bits) = (0u32, 0);
+    for c in s.trim_end_matches('=').bytes() {
+        let v = match c {
+            b'A'..=b'Z' => c - b'A',
+            b'a'..=b'z' => c - b'a' + 26,
+            b'0'..=b'9' => c - b'0' + 52,
+            b'+' => 62,
+            b'/' => 63,
+            _ => return None,
+        };
+        acc = acc << 6 | v as u32;
+        bits += 6;
+        if bits >= 8 {
+            bits -= 8;
+            out.push((acc >> bits) as u8);
+        }
+    }
+    Some(out)
+}
+
+fn hmac_sha256(key: &[u8], data: &[u8]) -> [u8; 32] {
+    let mut block = [0u8; 64];
+    if key.len() > 64 {
+        block[..32].copy_from_slice(&sha256(key));
+    } else {
+        block[..key.len()].copy_from_slice(key);
+    }
+    let mut inner: Vec<u8> = block.iter().map(|b| b ^ 0x36).collect();
+    inner.extend(data);
+    let mut outer: Vec<u8> = block.iter().map(|b| b ^ 0x5c).collect();
+    outer.extend(sha256(&inner));
+    sha256(&outer)
+}
+
+const K: [u32; 64] = [
+    0x428a2f98, 0x71374491, 0xb5c0fbcf, 0xe9b5dba5, 0x3956c25b, 0x59f111f1, 0x923f82a4, 0xab1c5ed5,
+    0xd807aa98, 0x12835b01, 0x243185be, 0x550c7dc3, 0x72be5d74, 0x80deb1fe, 0x9bdc06a7, 0xc19bf174,
+    0xe49b69c1, 0xefbe4786, 0x0fc19dc6, 0x240ca1cc, 0x2de92c6f, 0x4a7484aa, 0x5cb0a9dc, 0x76f988da,
+    0x983e5152, 0xa831c66d, 0xb00327c8, 0xbf597fc7, 0xc6e00bf3, 0xd5a79147, 0x06ca6351, 0x14292967,
+    0x27b70a85, 0x2e1b2138, 0x4d2c6dfc, 0x53380d13, 0x650a7354, 0x766a0abb, 0x81c2c92e, 0x92722c85,
+    0xa2bfe8a1, 0xa81a664b, 0xc24b8b70, 0xc76c51a3, 0xd192e819, 0xd6990624, 0xf40e3585, 0x106aa070,
+    0x19a4c116, 0x1e376c08, 0x2748774c, 0x34b0bcb5, 0x391c0cb3, 0x4ed8aa4a, 0x5b9cca4f, 0x682e6ff3,
+    0x748f82ee, 0x78a5636f, 0x84c87814, 0x8cc70208, 0x90befffa, 0xa4506ceb, 0xbef9a3f7, 0xc67178f2,
+];
+
+/// SHA-256 (FIPS 180-4).
+fn sha256(data: &[u8]) -> [u8; 32] {
+    let mut h: [u32; 8] = [
+        0x6a09e667, 0xbb67ae85, 0x3c6ef372, 0xa54ff53a, 0x510e527f, 0x9b05688c, 0x1f83d9ab,
+        0x5be0cd19,
+    ];
+    let mut padded = data.to_vec();
+    padded.push(0x80);
+    while padded.len() % 64 != 56 {
+        padded.push(0);
+    }
+    padded.extend((data.len() as u64 * 8).to_be_bytes());
+    for chunk in padded.chunks(64) {
+        let mut w = [0u32; 64];
+        for (i, word) in chunk.chunks(4).enumerate() {
+            w[i] = u32::from_be_bytes([word[0], word[1], word[2], word[3]]);
+        }
+        for i in 16..64 {
+            let s0 = w[i - 15].rotate_right(7) ^ w[i - 15].rotate_right(18) ^ (w[i - 15] >> 3);
+            let s1 = w[i - 2].rotate_right(17) ^ w[i - 2].rotate_right(19) ^ (w[i - 2] >> 10);
+            w[i] = w[i - 16]
+                .wrapping_add(s0)
+                .wrapping_add(w[i - 7])
+                .wrapping_add(s1);
+        }
+        let [mut a, mut b, mut c, mut d, mut e, mut f, mut g, mut hh] = h;
+        for i in 0..64 {
+            let s1 = e.rotate_right(6) ^ e.rotate_right(11) ^ e.rotate_right(25);
+            let ch = (e & f) ^ (!e & g);
+            let t1 = hh
+                .wrapping_add(s1)
+                .wrapping_add(ch)
+                .wrapping_add(K[i])
+                .wrapping_add(w[i]);
+            let s0 = a.rotate_right(2) ^ a.rotate_right(13) ^ a.rotate_right(22);
+            let maj = (a & b) ^ (a & c) ^ (b & c);
+            let t2 = s0.wrapping_add(maj);
+            hh = g;
+            g = f;
+            f = e;
+            e = d.wrapping_add(t1);
+            d = c;
+            c = b;
+            b = a;
+            a = t1.wrapping_add(t2);
+        }
+        for (x, y) in h.iter_mut().zip([a, b, c, d, e, f, g, hh]) {
+            *x = x.wrapping_add(y);
+        }
+    }
+    let mut out = [0u8; 32];
+    for (i, x) in h.iter().enumerate() {
+        out[i * 4..i * 4 + 4].copy_from_slice(&x.to_be_bytes());
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn hex(b: &[u8]) -> String {
+        b.iter().map(|x| format!("{:02x}", x)).collect()
+    }
+
+    #[test]
+    fn sha256_vectors() {
+        let cases: [(&[u8], &str); 3] = [
+            (
+                b"",
+                "e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855",
+            ),
+            (
+                b"abc",
+                "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad",
+            ),
+            (
+                b"abcdbcdecdefdefgefghfghighijhijkijkljklmklmnlmnomnopnopq",
+                "248d6a61d20638b8e5c026930c3e6039a33ce45964ff2167f6ecedd419db06c1",
+            ),
+        ];
+        for (data, digest) in cases {
+            assert_eq!(hex(&sha256(data)), digest);
+        }
+    }
+
+    /// RFC 4231 test cases 1, 2 and 6.
+    #[test]
+    fn hmac_sha256_vectors() {
+        let cases: [(&[u8], &[u8], &str); 3] = [
+            (
+                &[0x0b; 20],
+                b"Hi There",
+                "b0344c61d8db38535ca8afceaf0bf12b881dc200c9833da726e9376c2e32cff7",
+            ),
+            (
+                b"Jefe",
+                b"what do ya want for nothing?",
+                "5bdcc146bf60754e6a042426089575c75a003f089d2739839dec58b964ec3843",
+            ),
+            (
+                &[0xaa; 131],
+                b"Test Using Larger Than Block-Size Key - Hash Key First",
+                "60e431591ee0b67f0d8a26aacbf5b77f8e0bc6213728c5140546040f0ee37f54",
+            ),
+        ];
+        for (key, data, mac) in cases {
+            assert_eq!(hex(&hmac_sha256(key, data)), mac);
+        }
+    }
+}
@@ -1,3 +1,4 @@
+use std::net::Ipv4Addr;
 use std::time::{SystemTime, UNIX_EPOCH};
 
 pub fn now_timestamp_ms() -> u128 {
@@ -7,3 +8,88 @@ pub fn now_timestamp_ms() -> u128 {
         .expect("Time went backwards");
     since_the_epoch.as_millis()
 }
+
+/// Parses hex bytes written as `0a0b0c`, optionally separated by `:` or `-`.
+pub fn parse_hex(s: &str) -> Option<Vec<u8>> {
+    let digits: String = s.chars().filter(|c| *c != ':' && *c != '-').collect();
+    if !digits.len().is_multiple_of(2) {
+        return None;
+    }
+    (0..digits.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(digits.get(i..i + 2)?, 16).ok())
+        .collect()
+}
+
+/// The IPv4 addresses of interface `intf` with their netmasks. Empty if it does not exist.
+pub fn interface_addrs(intf: &str) -> std::io::Result<Vec<(Ipv4Addr, Ipv4Addr)>> {
+    Ok(all_interface_addrs()?
+        .into_iter()
+        .filter(|(name, _, _)| name == intf)
+        .map(|(_, ip, mask)| (ip, mask))
+        .collect())
+}
+
+/// Every IPv4 address of the host as `(interface, address, netmask)`.
+pub fn all_interface_addrs() -> std::io::Result<Vec<(String, Ipv4Addr, Ipv4Addr)>> {
+    use std::ffi::CStr;
+
+    let mut addrs = vec![];
+    let mut ifap: *mut libc::ifaddrs = std::ptr::null_mut();
+    unsafe {
+        if libc::getifaddrs(&mut ifap) != 0 {
+            return Err(std::io::Error::last_os_error());
+        }
+        let mut ifa = ifap;
+        while !ifa.is_null() {
+            let a = &*ifa;
+            if !a.ifa_addr.is_null()
+                && !a.ifa_netmask.is_null()
+                && (*a.ifa_addr).sa_family as i32 == libc::AF_INET
+            {
+                let ip = &*(a.ifa_addr as *const libc::sockaddr_in);
+                let mask = &*(a.ifa_netmask as *const libc::sockaddr_in);
+                addrs.push((
+                    CStr::from_ptr(a.ifa_name).to_string_lossy().into_owned(),
+                    Ipv4Addr::from(u32::from_be(ip.sin_addr.s_addr)),
+                    Ipv4Addr::from(u32::from_be(mask.sin_addr.s_addr)),
+                ));
+            }
+            ifa = a.ifa_next;
+        }
+        libc::freeifaddrs(ifap);
+    }
+    Ok(addrs)
+}
+
+/// The index of interface `intf`, `None` if it does not exist.
+pub fn interface_index(intf: &str) -> Option<u32> {
+    let name = std::ffi::CString::new(intf).ok()?;
+    let index = unsafe { libc::if_nametoindex(name.as_ptr()) };
+    (index != 0).then_some(index)
+}
+
+/// The MAC address of interface `intf`, `None` if it has none.
+pub fn interface_mac(intf: &str) -> Option<Vec<u8>> {
+    std::fs::read_to_string(format!("/sys/class/net/{}/address", intf))
+        .ok()
+        .and_then(|s| parse_hex(s.trim()))
+        .filter(|mac| mac.len() == 6)
+}
+
+/// A random number from the kernel, or one seeded by std's randomly keyed hasher if
+/// `/dev/urandom` cannot be read.
+pub fn random_u32() -> u32 {
+    use std::hash::{BuildHasher, Hasher};
+    use std::io::Read;
+
+    let mut buf = [0u8; 4];
+    if let Ok(mut f) = std::fs::File::open("/dev/urandom") {
+        if f.read_exact(&mut buf).is_ok() {
+            return u32::from_ne_bytes(buf);
+        }
+    }
+    let mut h = std::collections::hash_map::RandomState::new().build_hasher();
+    h.write_u128(now_timestamp_ms());
+    h.finish() as u32
+}
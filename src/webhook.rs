@@ -0,0 +1,58 @@
+//! Fire-and-forget JSON notifications over plain HTTP.
+
+use std::io::{Read, Write};
+use std::net::{TcpStream, ToSocketAddrs};
+use std::thread;
+use std::time::Duration;
+
+/// POSTs `body` to an `http://host[:port]/path` URL on a background thread, logging failures.
+pub fn post(url: &str, body: serde_json::Value) {
+    let url = url.to_string();
+    thread::spawn(move || {
+        if let Err(e) = send(&url, &body) {
+            println!("webhook {} failed: {}", url, e);
+        }
+    });
+}
+
+/// POSTs `body` and waits for a 2xx status.
+pub fn send(url: &str, body: &serde_json::Value) -> Result<(), String> {
+    let rest = url
+        .strip_prefix("http://")
+        .ok_or("only http:// URLs are supported")?;
+    let (authority, path) = match rest.find('/') {
+        Some(i) => (&rest[..i], &rest[i..]),
+        None => (rest, "/"),
+    };
+    let target = if authority.contains(':') {
+        authority.to_string()
+    } else {
+        format!("{}:80", authority)
+    };
+    let addr = target
+        .to_socket_addrs()
+        .map_err(|e| e.to_string())?
+        .next()
+        .ok_or("host not found")?;
+    let mut stream =
+        TcpStream::connect_timeout(&addr, Duration::from_secs(5)).map_err(|e| e.to_string())?;
+    stream
+        .set_read_timeout(Some(Duration::from_secs(5)))
+        .map_err(|e| e.to_string())?;
+    let payload = body.to_string();
+    write!(
+        stream,
+        "POST {} HTTP/1.1\r\nHost: {}\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+        path,
+        authority,
+        payload.len(),
+        payload
+    )
+    .map_err(|e| e.to_string())?;
+    let mut status = [0u8; 12];
+    stream.read_exact(&mut status).map_err(|e| e.to_string())?;
+    match &status[9..10] {
+        b"2" => Ok(()),
+        _ => Err(String::from_utf8_lossy(&status).to_string()),
+    }
+}
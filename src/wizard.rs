@@ -0,0 +1,192 @@
+//! `rdhcpd init`: asks a few questions and writes a configuration for one local interface.
+
+use crate::args;
+use crate::checks;
+use crate::config;
+use crate::utils;
+
+use anyhow::{anyhow, bail, Context};
+use serde_yaml::{Mapping, Value};
+
+use std::fs;
+use std::io::{self, BufRead, Write};
+use std::net::Ipv4Addr;
+use std::path::Path;
+
+pub fn run(output: &str) -> anyhow::Result<()> {
+    let mut input = io::stdin().lock();
+    let candidates: Vec<(String, Ipv4Addr, Ipv4Addr)> = utils::all_interface_addrs()
+        .context("list interfaces")?
+        .into_iter()
+        .filter(|(_, ip, mask)| !ip.is_loopback() && u32::from(*mask).leading_ones() <= 30)
+        .collect();
+    if candidates.is_empty() {
+        bail!("no interface with an IPv4 address to serve");
+    }
+    println!("Interfaces:");
+    for (i, (name, ip, mask)) in candidates.iter().enumerate() {
+        println!(
+            "  {}) {} {}/{}",
+            i + 1,
+            name,
+            ip,
+            u32::from(*mask).leading_ones()
+        );
+    }
+    let (intf, addr, mask) = loop {
+        let answer = ask(&mut input, "Interface to serve", "1")?;
+        let pick = answer
+            .parse::<usize>()
+            .ok()
+            .and_then(|n| candidates.get(n.wrapping_sub(1)))
+            .or_else(|| candidates.iter().find(|c| c.0 == answer));
+        match pick {
+            Some(c) => break c.clone(),
+            None => println!("  pick a number from the list"),
+        }
+    };
+
+    let (start, end) = propose_pool(addr, mask);
+    let (start, end) = loop {
+        let answer = ask(&mut input, "Address pool", &format!("{}-{}", start, end))?;
+        match args::parse_range(&answer) {
+            Ok(r) => break r,
+            Err(e) => println!("  {}", e),
+        }
+    };
+    let gateway: Ipv4Addr = ask_parsed(&mut input, "Gateway", &addr.to_string())?;
+    let dns: Vec<Ipv4Addr> = loop {
+        let answer = ask(
+            &mut input,
+            "DNS servers, comma separated",
+            &addr.to_string(),
+        )?;
+        match answer.split(',').map(|s| s.trim().parse()).collect() {
+            Ok(dns) => break dns,
+            Err(e) => println!("  {}", e),
+        }
+    };
+    let lease_time = loop {
+        let answer = ask(&mut input, "Lease time", "12h")?;
+        match duration_str::parse(answer.as_str()) {
+            Ok(_) => break answer,
+            Err(e) => println!("  {}", e),
+        }
+    };
+    let lease_file = ask(&mut input, "Lease file", "/var/lib/rdhcpd/dhcpd.leases")?;
+    let lease_static = ask(&mut input, "Reservations file", "/etc/rdhcpd.static")?;
+
+    let broadcast = Ipv4Addr::from(u32::from(addr) | !u32::from(mask));
+    let ip = |a: Ipv4Addr| Value::from(a.to_string());
+    let mut m = Mapping::new();
+    m.insert("intf".into(), intf.as_str().into());
+    m.insert("listen_addr".into(), ip(addr));
+    m.insert("start".into(), ip(start));
+    m.insert("end".into(), ip(args::end_after(end)));
+    m.insert("netmask".into(), ip(mask));
+    m.insert("gateway".into(), ip(gateway));
+    m.insert("broadcast".into(), ip(broadcast));
+    m.insert(
+        "dns_servers".into(),
+        Value::Sequence(dns.into_iter().map(ip).collect()),
+    );
+    m.insert("lease_static".into(), lease_static.as_str().into());
+    m.insert("lease_file".into(), lease_file.as_str().into());
+    m.insert("lease_time".into(), lease_time.as_str().into());
+    let value = Value::Mapping(m);
+    let conf = config::from_value(value.clone()).map_err(|e| anyhow!("{}", e))?;
+
+    if Path::new(output).exists() {
+        let answer = ask(
+            &mut input,
+            &format!("{} exists, overwrite? (y/n)", output),
+            "n",
+        )?;
+        if !answer.eq_ignore_ascii_case("y") {
+            bail!("not written");
+        }
+    }
+    if let Some(dir) = Path::new(&lease_file).parent() {
+        if !dir.as_os_str().is_empty() {
+            fs::create_dir_all(dir).with_context(|| format!("create {}", dir.display()))?;
+        }
+    }
+    for p in checks::check(&conf) {
+        println!("WARN: {}", p);
+    }
+    fs::write(output, serde_yaml::to_string(&value)?)
+        .with_context(|| format!("write {}", output))?;
+    println!("wrote {}, start with: rdhcpd -c {}", output, output);
+    Ok(())
+}
+
+/// The half of the subnet that does not hold the server's own address, first and last address
+/// included.
+fn propose_pool(addr: Ipv4Addr, mask: Ipv4Addr) -> (Ipv4Addr, Ipv4Addr) {
+    let net = u32::from(addr) & u32::from(mask);
+    let size = !u32::from(mask) + 1;
+    let half = size / 2;
+    let (first, last) = if u32::from(addr) - net < half {
+        (half, size - 2)
+    } else {
+        (1, half - 1)
+    };
+    (Ipv4Addr::from(net + first), Ipv4Addr::from(net + last))
+}
+
+/// Asks until the answer parses as `T`.
+fn ask_parsed<T>(input: &mut impl BufRead, question: &str, default: &str) -> anyhow::Result<T>
+where
+    T: std::str::FromStr,
+    T::Err: std::fmt::Display,
+{
+    loop {
+        match ask(input, question, default)?.parse() {
+            Ok(v) => return Ok(v),
+            Err(e) => println!("  {}", e),
+        }
+    }
+}
+
+/// Prints `question [default]: ` and returns the trimmed answer, or `default` if it was empty.
+fn ask(input: &mut impl BufRead, question: &str, default: &str) -> anyhow::Result<String> {
+    print!("{} [{}]: ", question, default);
+    io::stdout().flush()?;
+    let mut line = String::new();
+    if input.read_line(&mut line)? == 0 {
+        bail!("input closed");
+    }
+    let answer = line.trim();
+    Ok(if answer.is_empty() {
+        default.to_string()
+    } else {
+        answer.to_string()
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn pool_is_the_other_half() {
+        let mask = Ipv4Addr::new(255, 255, 255, 0);
+        let low = propose_pool(Ipv4Addr::new(192, 168, 1, 1), mask);
+        assert_eq!(
+            low,
+            (
+                Ipv4Addr::new(192, 168, 1, 128),
+                Ipv4Addr::new(192, 168, 1, 254)
+            )
+        );
+        let high = propose_pool(Ipv4Addr::new(192, 168, 1, 200), mask);
+        assert_eq!(
+            high,
+            (
+                Ipv4Addr::new(192, 168, 1, 1),
+                Ipv4Addr::new(192, 168, 1, 127)
+            )
+        );
+        assert_eq!(args::end_after(low.1), Ipv4Addr::new(192, 168, 1, 255));
+    }
+}
@@ -0,0 +1,140 @@
+//! BIND-style zone files rendered from the active leases.
+//!
+//! The reverse zone holds a PTR record per named lease, the optional forward zone the matching A
+//! records. Files are replaced atomically and the reload command runs after each change.
+
+use crate::config::ZoneConf;
+
+use std::fs;
+use std::io;
+use std::net::Ipv4Addr;
+use std::process::Command;
+use std::thread;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// A named address, as published in the zones.
+pub type Host = (String, Ipv4Addr);
+
+/// Longest DNS label.
+const MAX_LABEL: usize = 63;
+
+/// The client-supplied host name as a DNS label: its first label lowercased, every run of
+/// other characters turned into one hyphen, cut to 63 bytes. `None` if nothing is left.
+pub fn sanitize(name: &str) -> Option<String> {
+    let mut label = String::new();
+    for c in name.split('.').next()?.chars() {
+        if c.is_ascii_alphanumeric() {
+            label.push(c.to_ascii_lowercase());
+        } else if !label.is_empty() && !label.ends_with('-') {
+            label.push('-');
+        }
+    }
+    label.truncate(MAX_LABEL);
+    let label = label.trim_end_matches('-');
+    (!label.is_empty()).then(|| label.to_string())
+}
+
+/// The name for a client wanting `base`: its `current` one if that still derives from `base` and
+/// nobody else took it, else the first of `base`, `base-2`, `base-3`, ... that is free.
+pub fn unique_name(base: &str, current: Option<&str>, taken: impl Fn(&str) -> bool) -> String {
+    let candidate = |n: u32| {
+        if n == 1 {
+            return base.to_string();
+        }
+        let suffix = format!("-{}", n);
+        let stem = &base[..base.len().min(MAX_LABEL - suffix.len())];
+        format!("{}{}", stem.trim_end_matches('-'), suffix)
+    };
+    if let Some(current) = current {
+        let derived = current == base
+            || current
+                .rsplit_once('-')
+                .and_then(|(_, n)| n.parse::<u32>().ok())
+                .is_some_and(|n| n > 1 && candidate(n) == current);
+        if derived && !taken(current) {
+            return current.to_string();
+        }
+    }
+    (1..)
+        .map(candidate)
+        .find(|c| !taken(c))
+        .unwrap_or_else(|| base.to_string())
+}
+
+/// Writes the zones for `hosts` in the subnet `net`/`mask`, then runs the reload command.
+pub fn publish(conf: &ZoneConf, net: Ipv4Addr, mask: Ipv4Addr, hosts: &[Host]) {
+    let serial = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |d| d.as_secs() as u32);
+    let (origin, octets) = reverse_origin(net, mask);
+    let mut ptr = header(conf, &origin, serial);
+    for (name, ip) in hosts {
+        let o = ip.octets();
+        let label: Vec<String> = o[octets..].iter().rev().map(|b| b.to_string()).collect();
+        let target = match &conf.domain {
+            Some(domain) => format!("{}.{}.", name, domain.trim_end_matches('.')),
+            None => format!("{}.", name),
+        };
+        ptr.push_str(&format!("{}\tIN\tPTR\t{}\n", label.join("."), target));
+    }
+    let mut written = replace(&conf.reverse, &ptr);
+    if let (Some(path), Some(domain)) = (&conf.forward, &conf.domain) {
+        let origin = format!("{}.", domain.trim_end_matches('.'));
+        let mut fwd = header(conf, &origin, serial);
+        for (name, ip) in hosts {
+            fwd.push_str(&format!("{}\tIN\tA\t{}\n", name, ip));
+        }
+        written = written.and(replace(path, &fwd));
+    }
+    if let Err(e) = written {
+        println!("ERROR: write zone: {:?}", e);
+        return;
+    }
+    if let Some(cmd) = conf.reload.clone() {
+        thread::spawn(
+            move || match Command::new("sh").arg("-c").arg(&cmd).status() {
+                Ok(status) if status.success() => {}
+                Ok(status) => println!("WARN: zone reload '{}' exited with {}", cmd, status),
+                Err(e) => println!("WARN: zone reload '{}': {:?}", cmd, e),
+            },
+        );
+    }
+}
+
+/// The `in-addr.arpa` origin of the subnet and how many leading octets it fixes. Subnets that
+/// do not end on an octet boundary get the zone of the enclosing one.
+fn reverse_origin(net: Ipv4Addr, mask: Ipv4Addr) -> (String, usize) {
+    let octets = (u32::from(mask).leading_ones() / 8).min(3) as usize;
+    let labels: Vec<String> = net.octets()[..octets]
+        .iter()
+        .rev()
+        .map(|b| b.to_string())
+        .collect();
+    let mut origin = labels.join(".");
+    if !origin.is_empty() {
+        origin.push('.');
+    }
+    (format!("{}in-addr.arpa.", origin), octets)
+}
+
+fn header(conf: &ZoneConf, origin: &str, serial: u32) -> String {
+    let ns = format!("{}.", conf.nameserver.trim_end_matches('.'));
+    format!(
+        "; generated by rdhcpd, do not edit\n\
+         $ORIGIN {origin}\n\
+         $TTL {ttl}\n\
+         @\tIN\tSOA\t{ns} hostmaster.{ns} ({serial} 3600 600 86400 {ttl})\n\
+         @\tIN\tNS\t{ns}\n",
+        origin = origin,
+        ttl = conf.ttl,
+        ns = ns,
+        serial = serial,
+    )
+}
+
+/// Replaces `path` with `content` through a rename, so readers never see half a zone.
+fn replace(path: &str, content: &str) -> io::Result<()> {
+    let tmp = format!("{}.tmp", path);
+    fs::write(&tmp, content)?;
+    fs::rename(&tmp, path)
+}